@@ -0,0 +1,93 @@
+//! `log4rs` appender writing records to kernel log
+
+use std::fmt;
+
+use log::{Log, Record};
+use log4rs::append::Append;
+use log4rs::config::{Deserialize, Deserializers};
+
+use crate::{Config, KernelLog};
+
+/// `log4rs` appender writing records to kernel log through [`KernelLog`]
+///
+/// Records are formatted by the logger, not by log4rs encoders.
+pub struct KernelAppender {
+    klog: KernelLog,
+}
+
+impl KernelAppender {
+    /// Create appender writing to kernel logger
+    pub fn new(klog: KernelLog) -> KernelAppender {
+        KernelAppender { klog }
+    }
+}
+
+impl fmt::Debug for KernelAppender {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("KernelAppender").field("device", &self.klog.handle().device()).finish()
+    }
+}
+
+impl Append for KernelAppender {
+    fn append(&self, record: &Record) -> anyhow::Result<()> {
+        self.klog.log(record);
+        Ok(())
+    }
+
+    fn flush(&self) {
+        self.klog.flush();
+    }
+}
+
+/// Deserializer of [`KernelAppender`] from log4rs config, taking [`Config`] fields
+///
+/// ```rust,no_run
+/// let mut deserializers = log4rs::config::Deserializers::default();
+/// deserializers.insert("kmsg", kernlog::KernelAppenderDeserializer);
+/// log4rs::init_file("log4rs.yaml", deserializers).unwrap();
+/// ```
+///
+/// ```yaml
+/// appenders:
+///   kmsg:
+///     kind: kmsg
+///     tag: myinit
+///     level: info
+/// root:
+///   appenders:
+///     - kmsg
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KernelAppenderDeserializer;
+
+impl Deserialize for KernelAppenderDeserializer {
+    type Trait = dyn Append;
+    type Config = Config;
+
+    fn deserialize(&self, config: Config, _deserializers: &Deserializers) -> anyhow::Result<Box<dyn Append>> {
+        Ok(Box::new(KernelAppender::new(KernelLog::from_config(&config)?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use log::{Level, Record};
+    use log4rs::config::{Deserialize, Deserializers};
+
+    use super::KernelAppenderDeserializer;
+    use crate::Config;
+
+    #[test]
+    fn append_records() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-log4rs", std::process::id()));
+        fs::write(&path, "").unwrap();
+        let config = Config { device: path.clone(), pid: false, tag: Some("init".to_owned()), ..Config::default() };
+        let appender = KernelAppenderDeserializer.deserialize(config, &Deserializers::default()).unwrap();
+        appender.append(&Record::builder().level(Level::Warn).target("test").args(format_args!("hello")).build()).unwrap();
+        appender.flush();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<4>init: test: hello\n");
+        fs::remove_file(path).unwrap();
+    }
+}
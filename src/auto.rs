@@ -0,0 +1,96 @@
+//! Picking log destination from the environment
+
+use std::fs::File;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsFd;
+use std::path::{Path, PathBuf};
+
+use crate::{env, preflight, KernelLog};
+
+/// Log destination picked by [`init_auto()`](crate::init_auto), see [`Destination::detect()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Destination {
+    /// Kernel log device
+    Kmsg,
+    /// systemd-journald, through stderr if it's connected to the journal,
+    /// or through native socket otherwise
+    Journald,
+    /// stderr, with `<priority>` prefixes
+    Stderr,
+}
+
+impl Destination {
+    /// Pick the best destination for current process
+    ///
+    /// Inside containers it's stderr, as kernel log belongs to the host there.
+    /// Otherwise it's the kernel log device (`KERNLOG_DEVICE` or `/dev/kmsg`) if it's writable,
+    /// journald if stderr is connected to the journal (`$JOURNAL_STREAM`) or, with `journald`
+    /// feature, its native socket exists, and stderr as the last resort.
+    pub fn detect() -> Destination {
+        if in_container() {
+            return Destination::Stderr;
+        }
+        if preflight::check_available(&device()).is_ok() {
+            return Destination::Kmsg;
+        }
+        if journal_stream() || journald_socket() {
+            return Destination::Journald;
+        }
+        Destination::Stderr
+    }
+}
+
+/// Get device to check, the same [`init()`](crate::init) would log to
+pub(crate) fn device() -> PathBuf {
+    env::device().map_or_else(|| PathBuf::from(KernelLog::DEFAULT_DEVICE), PathBuf::from)
+}
+
+/// Check for container managers' markers: `$container` set by systemd-nspawn, podman and LXC,
+/// and files created by docker and podman
+fn in_container() -> bool {
+    std::env::var_os("container").is_some_and(|value| !value.is_empty())
+        || Path::new("/.dockerenv").exists()
+        || Path::new("/run/.containerenv").exists()
+}
+
+/// Check if stderr is connected to the journal, comparing device and inode numbers
+/// of stderr with ones in `$JOURNAL_STREAM`
+pub(crate) fn journal_stream() -> bool {
+    let (dev, ino) = match std::env::var("JOURNAL_STREAM").ok().as_deref().and_then(parse_journal_stream) {
+        Some(stream) => stream,
+        None => return false,
+    };
+    match io::stderr().as_fd().try_clone_to_owned() {
+        Ok(stderr) => File::from(stderr).metadata().is_ok_and(|meta| meta.dev() == dev && meta.ino() == ino),
+        Err(_) => false,
+    }
+}
+
+/// Parse `device:inode` value of `$JOURNAL_STREAM`
+fn parse_journal_stream(value: &str) -> Option<(u64, u64)> {
+    let (dev, ino) = value.split_once(':')?;
+    Some((dev.parse().ok()?, ino.parse().ok()?))
+}
+
+#[cfg(feature = "journald")]
+fn journald_socket() -> bool {
+    Path::new(crate::fallback::JOURNALD_SOCKET).exists()
+}
+
+#[cfg(not(feature = "journald"))]
+fn journald_socket() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_journal_stream;
+
+    #[test]
+    fn parse_stream() {
+        assert_eq!(parse_journal_stream("8:12345"), Some((8, 12345)));
+        assert_eq!(parse_journal_stream("8"), None);
+        assert_eq!(parse_journal_stream("x:1"), None);
+    }
+}
@@ -0,0 +1,226 @@
+//! Destinations formatted records are written to
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, IoSlice, Write};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use crate::{split, KernelLog};
+
+/// Destination of formatted records, `/dev/kmsg` device by default
+///
+/// Backends get complete records: `<priority>` prefix, message, dictionary lines and
+/// a trailing new line, each record in a single call. The default backend drops dictionary
+/// lines, as kernel doesn't parse them from userspace writes. Filtering, formatting, splitting,
+/// rate limits and background writing are done by the logger before records reach it.
+///
+/// Records are written through a shared reference from all logging threads at once,
+/// so backends have to synchronize writes themselves if the destination isn't atomic per write.
+///
+/// ```rust
+/// use std::io;
+/// use std::sync::Mutex;
+///
+/// #[derive(Default)]
+/// struct Memory(Mutex<Vec<String>>);
+///
+/// impl kernlog::KmsgBackend for Memory {
+///     fn open(&mut self) -> io::Result<()> {
+///         Ok(())
+///     }
+///
+///     fn write_record(&self, record: &[u8]) -> io::Result<()> {
+///         self.0.lock().unwrap().push(String::from_utf8_lossy(record).into_owned());
+///         Ok(())
+///     }
+/// }
+///
+/// let klog = kernlog::KernelLog::builder().backend(Memory::default()).build().unwrap();
+/// ```
+pub trait KmsgBackend: Send + Sync + 'static {
+    /// Open destination, replacing the current one if it's already open
+    ///
+    /// Called before the first write if the backend isn't open yet (see [`KmsgBackend::is_open()`]),
+    /// and on [`KernelLog::reopen()`](crate::KernelLog::reopen).
+    fn open(&mut self) -> io::Result<()>;
+
+    /// Write a single record
+    fn write_record(&self, record: &[u8]) -> io::Result<()>;
+
+    /// Write a single record given in several buffers, concatenating them by default
+    fn write_record_vectored(&self, bufs: &mut [IoSlice<'_>]) -> io::Result<()> {
+        let record: Vec<u8> = bufs.iter().flat_map(|buf| buf.iter().copied()).collect();
+        self.write_record(&record)
+    }
+
+    /// Get maximum length of a record without dictionary lines, longer messages are split
+    /// into several records
+    ///
+    /// Detected from running kernel version by default, like for `/dev/kmsg`.
+    fn max_len(&self) -> usize {
+        split::detect_max_record_len()
+    }
+
+    /// Check if destination is open, so records can be written, `true` by default
+    fn is_open(&self) -> bool {
+        true
+    }
+
+    /// Get path of the device written to, if any
+    fn device(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Get the open file written to, if any, for background writer to submit writes
+    /// through io_uring
+    ///
+    /// Records are written to it as to the kernel log device, without dictionary lines.
+    fn file(&self) -> Option<&File> {
+        None
+    }
+}
+
+/// Kernel log device or an already opened file, the default backend
+///
+/// Only message lines of records are written, as kernel takes anything after the first
+/// new line of a userspace write as part of the message.
+pub(crate) struct Kmsg {
+    pub(crate) file: Option<File>,
+    pub(crate) device: Option<PathBuf>,
+    /// Open device with `O_NONBLOCK`
+    pub(crate) nonblocking: bool
+}
+
+impl Kmsg {
+    pub(crate) fn new(device: &Path, nonblocking: bool) -> io::Result<Kmsg> {
+        Ok(Kmsg {
+            file: Some(open_device(device, nonblocking)?),
+            device: Some(device.to_owned()),
+            nonblocking
+        })
+    }
+
+    pub(crate) fn lazy(device: &Path, nonblocking: bool) -> Kmsg {
+        Kmsg { file: None, device: Some(device.to_owned()), nonblocking }
+    }
+
+    pub(crate) fn from_file(file: File) -> Kmsg {
+        Kmsg { file: Some(file), device: None, nonblocking: false }
+    }
+}
+
+impl KmsgBackend for Kmsg {
+    /// Open the device again, replacing current device file
+    ///
+    /// Current device file is kept if the device can't be opened.
+    fn open(&mut self) -> io::Result<()> {
+        let device = self.device.as_ref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "logger has no device path to reopen")
+        })?;
+        self.file = Some(open_device(device, self.nonblocking)?);
+        Ok(())
+    }
+
+    fn write_record(&self, record: &[u8]) -> io::Result<()> {
+        match self.file {
+            Some(ref file) => (&*file).write_all(message_line(record)),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "device is not opened")),
+        }
+    }
+
+    /// Write record with a single `writev(2)`, without concatenating buffers
+    fn write_record_vectored(&self, bufs: &mut [IoSlice<'_>]) -> io::Result<()> {
+        let mut file = match self.file {
+            Some(ref file) => file,
+            None => return Err(io::Error::new(io::ErrorKind::NotFound, "device is not opened")),
+        };
+        let mut bufs = bufs;
+        while !bufs.is_empty() {
+            match file.write_vectored(bufs) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => IoSlice::advance_slices(&mut bufs, len),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    fn is_open(&self) -> bool {
+        self.file.is_some()
+    }
+
+    fn device(&self) -> Option<&Path> {
+        self.device.as_deref()
+    }
+
+    fn file(&self) -> Option<&File> {
+        self.file.as_ref()
+    }
+}
+
+/// Get `<priority>` prefix and message of a record with the trailing new line,
+/// dropping dictionary lines
+pub(crate) fn message_line(record: &[u8]) -> &[u8] {
+    match record.iter().position(|&byte| byte == b'\n') {
+        Some(end) => &record[..=end],
+        None => record,
+    }
+}
+
+/// Check if device is the kernel log device, `/dev/kmsg` or another node of it
+pub(crate) fn is_kmsg(device: &Path) -> bool {
+    device == Path::new(KernelLog::DEFAULT_DEVICE)
+        || fs::metadata(device).is_ok_and(|meta| meta.file_type().is_char_device() && meta.rdev() == libc::makedev(1, 11))
+}
+
+fn open_device(device: &Path, nonblocking: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if nonblocking {
+        options.custom_flags(libc::O_NONBLOCK);
+    }
+    options.open(device)
+}
+
+/// Switch already opened file to non-blocking mode
+pub(crate) fn set_nonblocking(file: &File) -> io::Result<()> {
+    let fd = file.as_raw_fd();
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags < 0 || unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::IoSlice;
+
+    use std::path::Path;
+
+    use super::{is_kmsg, Kmsg, KmsgBackend};
+
+    #[test]
+    fn write_to_device() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-backend", std::process::id()));
+        fs::write(&path, "").unwrap();
+        let mut kmsg = Kmsg::lazy(&path, false);
+        assert!(!kmsg.is_open());
+        assert!(kmsg.write_record(b"<6>lost\n").is_err());
+
+        kmsg.open().unwrap();
+        assert!(kmsg.is_open());
+        assert_eq!(kmsg.device(), Some(path.as_path()));
+        kmsg.write_record(b"<6>first\n SYSLOG_PID=1\n").unwrap();
+        kmsg.write_record_vectored(&mut [IoSlice::new(b"<6>"), IoSlice::new(b"second"), IoSlice::new(b"\n")]).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<6>first\n<6>second\n");
+        assert!(!is_kmsg(&path));
+        fs::remove_file(path).unwrap();
+
+        assert!(is_kmsg(Path::new("/dev/kmsg")));
+        assert!(!is_kmsg(Path::new("/dev/null")));
+    }
+}
@@ -0,0 +1,142 @@
+//! Forwarding kernel log records to the `log` facade
+
+use std::io;
+use std::thread::{self, JoinHandle};
+
+use log::{LevelFilter, Log, Record};
+
+use crate::{Facility, KmsgPosition, KmsgReader, KmsgRecord};
+
+/// Forwarder of kernel log records to the installed logger
+///
+/// Each kernel record is logged as a `log` record with the target `kernel` and the level
+/// of [`KmsgRecord::level()`], so kernel messages go through application's own
+/// filters and outputs.
+///
+/// Only records logged by kernel itself are forwarded by default: forwarding records written
+/// from userspace into a logger writing to `/dev/kmsg`, like [`KernelLog`](crate::KernelLog),
+/// would loop them back forever.
+///
+/// ```rust,no_run
+/// let bridge = kernlog::KernelBridge::new().unwrap();
+/// bridge.spawn().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct KernelBridge {
+    reader: KmsgReader,
+    target: String,
+}
+
+impl KernelBridge {
+    /// Forward kernel messages logged from now on
+    pub fn new() -> io::Result<KernelBridge> {
+        let mut reader = KmsgReader::new()?.facilities([Facility::Kern]);
+        reader.seek(KmsgPosition::Newest)?;
+        Ok(KernelBridge::from_reader(reader))
+    }
+
+    /// Forward records of a reader, applying its position and filters as is
+    pub fn from_reader(reader: KmsgReader) -> KernelBridge {
+        KernelBridge { reader, target: "kernel".to_owned() }
+    }
+
+    /// Set target to log records with, `kernel` by default
+    pub fn target(mut self, target: impl Into<String>) -> KernelBridge {
+        self.target = target.into();
+        self
+    }
+
+    /// Forward records logged so far, returning number of records read
+    ///
+    /// Records above `log::max_level()` are read but not forwarded, like `log` macros skip them.
+    pub fn forward_pending(&mut self) -> io::Result<usize> {
+        self.forward_pending_to(log::logger(), log::max_level())
+    }
+
+    /// Forward records forever, waiting for new ones
+    pub fn run(mut self) -> io::Result<()> {
+        loop {
+            let record = self.reader.wait_record()?;
+            if record.level() <= log::max_level() {
+                self.forward(log::logger(), &record);
+            }
+        }
+    }
+
+    /// Forward records forever from a background thread
+    pub fn spawn(self) -> io::Result<JoinHandle<io::Result<()>>> {
+        thread::Builder::new().name("kernlog-bridge".to_owned()).spawn(move || self.run())
+    }
+
+    fn forward_pending_to(&mut self, logger: &dyn Log, max_level: LevelFilter) -> io::Result<usize> {
+        let mut count = 0;
+        while let Some(record) = self.reader.read_record()? {
+            if record.level() <= max_level {
+                self.forward(logger, &record);
+            }
+            count += 1;
+        }
+        logger.flush();
+        Ok(count)
+    }
+
+    fn forward(&self, logger: &dyn Log, record: &KmsgRecord) {
+        let mut builder = Record::builder();
+        builder.level(record.level()).target(&self.target);
+        // Dictionary fields become record keys
+        #[cfg(feature = "kv")]
+        builder.key_values(&record.dictionary);
+        logger.log(&builder.args(format_args!("{}", record.message)).build());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+
+    use super::KernelBridge;
+    use crate::KmsgReader;
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<String>>);
+
+    impl Log for Collect {
+        fn enabled(&self, _metadata: &Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record) {
+            #[cfg(feature = "kv")]
+            let fields = format!(" {:?}", record.key_values().get("SUBSYSTEM".into()).map(|value| value.to_string()));
+            #[cfg(not(feature = "kv"))]
+            let fields = "";
+            self.0.lock().unwrap().push(format!("{} {}: {}{}", record.level(), record.target(), record.args(), fields));
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn forward_records() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-bridge", std::process::id()));
+        std::fs::write(&path, "3,5,100,-;disk failed\n SUBSYSTEM=scsi\n").unwrap();
+        let mut bridge = KernelBridge::from_reader(KmsgReader::open(&path).unwrap());
+        let logger = Collect::default();
+        assert_eq!(bridge.forward_pending_to(&logger, LevelFilter::Trace).unwrap(), 1);
+
+        let mut bridge = bridge.target("dmesg");
+        bridge.forward(&logger, &crate::KmsgRecord::parse(b"13,6,200,-;link up").unwrap());
+        #[cfg(feature = "kv")]
+        assert_eq!(*logger.0.lock().unwrap(), [
+            "ERROR kernel: disk failed Some(\"scsi\")",
+            "INFO dmesg: link up None",
+        ]);
+        #[cfg(not(feature = "kv"))]
+        assert_eq!(*logger.0.lock().unwrap(), ["ERROR kernel: disk failed", "INFO dmesg: link up"]);
+        assert_eq!(bridge.forward_pending_to(&logger, LevelFilter::Trace).unwrap(), 0);
+        assert_eq!(Level::Info, crate::KmsgRecord::parse(b"5,1,0,-;notice").unwrap().level());
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,134 @@
+//! Stack allocated record buffer
+
+use std::cell::Cell;
+use std::fmt;
+use std::io::{self, Write};
+use std::mem;
+use std::str;
+
+use crate::split::MAX_RECORD_LEN;
+
+/// Largest heap buffer kept for reuse, so a single huge message doesn't pin its memory forever
+const MAX_SPARE_LEN: usize = 64 * 1024;
+
+thread_local! {
+    /// Heap buffer of a dropped record buffer, reused by the next one spilling over in this thread
+    static SPARE: Cell<Vec<u8>> = const { Cell::new(Vec::new()) };
+}
+
+/// Buffer for a record or a message, kept on stack unless it outgrows
+/// the largest kmsg record, so the common log path doesn't allocate
+pub(crate) struct RecordBuf {
+    stack: [u8; MAX_RECORD_LEN],
+    len: usize,
+    /// Whole buffer contents once it has spilled over to heap,
+    /// may have capacity reserved by previous records even if it's empty
+    heap: Vec<u8>,
+}
+
+impl RecordBuf {
+    pub(crate) fn new() -> RecordBuf {
+        let heap = SPARE.try_with(Cell::take).unwrap_or_default();
+        RecordBuf { stack: [0; MAX_RECORD_LEN], len: 0, heap }
+    }
+
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        if self.heap.is_empty() {
+            &self.stack[..self.len]
+        } else {
+            &self.heap
+        }
+    }
+
+    /// Get buffer contents as text, empty if it's not valid UTF-8,
+    /// which can't happen if it was only written with `fmt::Write`
+    pub(crate) fn as_str(&self) -> &str {
+        str::from_utf8(self.as_bytes()).unwrap_or_default()
+    }
+
+    /// Empty buffer, keeping heap memory if it has spilled over already
+    pub(crate) fn clear(&mut self) {
+        self.len = 0;
+        self.heap.clear();
+    }
+}
+
+impl Drop for RecordBuf {
+    fn drop(&mut self) {
+        if self.heap.capacity() == 0 || self.heap.capacity() > MAX_SPARE_LEN {
+            return;
+        }
+        let mut heap = mem::take(&mut self.heap);
+        heap.clear();
+        let _ = SPARE.try_with(|spare| {
+            let current = spare.take();
+            spare.set(if current.capacity() >= heap.capacity() { current } else { heap });
+        });
+    }
+}
+
+impl Write for RecordBuf {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.heap.is_empty() {
+            if self.len + data.len() <= MAX_RECORD_LEN {
+                self.stack[self.len..self.len + data.len()].copy_from_slice(data);
+                self.len += data.len();
+                return Ok(data.len());
+            }
+            self.heap.reserve(self.len + data.len());
+            self.heap.extend_from_slice(&self.stack[..self.len]);
+        }
+        self.heap.extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl fmt::Write for RecordBuf {
+    fn write_str(&mut self, text: &str) -> fmt::Result {
+        self.write_all(text.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+    use std::io::Write;
+
+    use super::RecordBuf;
+    use crate::split::MAX_RECORD_LEN;
+
+    #[test]
+    fn spill_to_heap() {
+        let mut buf = RecordBuf::new();
+        buf.write_all(b"<6>").unwrap();
+        buf.write_all(&[b'x'; MAX_RECORD_LEN - 3]).unwrap();
+        assert!(buf.heap.is_empty());
+        assert_eq!(buf.as_bytes().len(), MAX_RECORD_LEN);
+
+        buf.write_all(b"\n").unwrap();
+        assert_eq!(buf.as_bytes().len(), MAX_RECORD_LEN + 1);
+        assert!(buf.as_bytes().starts_with(b"<6>xxx"));
+        assert!(buf.as_bytes().ends_with(b"xxx\n"));
+
+        buf.clear();
+        fmt::Write::write_fmt(&mut buf, format_args!("{}: {}", "init", 42)).unwrap();
+        assert_eq!(buf.as_str(), "init: 42");
+    }
+
+    #[test]
+    fn reuse_heap_buffer() {
+        let mut buf = RecordBuf::new();
+        buf.write_all(&[b'x'; MAX_RECORD_LEN * 2]).unwrap();
+        let capacity = buf.heap.capacity();
+        drop(buf);
+
+        let buf = RecordBuf::new();
+        assert_eq!(buf.heap.capacity(), capacity);
+        assert_eq!(buf.as_bytes(), b"");
+        assert_eq!(RecordBuf::new().heap.capacity(), 0);
+    }
+}
@@ -0,0 +1,780 @@
+//! Kernel logger builder
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{Level, LevelFilter, Record};
+
+use crate::cmdline;
+use crate::dedup::Dedup;
+use crate::dictionary::Dictionary;
+use crate::fallback::{Fallback, FallbackWriter};
+use crate::filter::Filter;
+use crate::format::{program_name, DefaultFormat, KmsgFormat, Origin};
+use crate::level::{self, Facility};
+use crate::queue::{self, Backpressure, Queue, Retry};
+use crate::ratelimit::{self, Budget, CallSites, Limits, RateLimit};
+use crate::replay::Pending;
+use crate::sync::Mutex;
+use crate::template::TemplateFormat;
+use crate::sanitize::Sanitizer;
+use crate::stats::Report;
+use crate::backend::{self, set_nonblocking, Kmsg, KmsgBackend};
+use crate::{env, sysctl, ErrorHook, KernelLog, Shared};
+
+enum Target {
+    Device(PathBuf),
+    File(File),
+    Backend(Box<dyn KmsgBackend>),
+}
+
+/// Kernel logger builder
+///
+/// ```rust,no_run
+/// use kernlog::{KernelLog, DefaultFormat};
+/// use log::LevelFilter;
+///
+/// let klog = KernelLog::builder()
+///     .level(LevelFilter::Info)
+///     .formatter(Box::new(DefaultFormat::new().pid(false)))
+///     .build()
+///     .unwrap();
+/// ```
+pub struct Builder {
+    target: Target,
+    level: LevelFilter,
+    lazy: bool,
+    default_format: DefaultFormat,
+    format: Option<Box<dyn KmsgFormat>>,
+    template: Option<String>,
+    sanitizer: Sanitizer,
+    max_record_len: Option<usize>,
+    syslog_fields: bool,
+    fields: Vec<(String, String)>,
+    facility: Facility,
+    severity_suffixes: bool,
+    filter: Option<String>,
+    #[cfg(feature = "env_filter")]
+    env_filter: Option<String>,
+    console_level: bool,
+    kernel_cmdline: bool,
+    allow_targets: Vec<String>,
+    deny_targets: Vec<String>,
+    remaps: Vec<(String, Level, Level)>,
+    background: Option<usize>,
+    backpressure: Backpressure,
+    io_uring: bool,
+    retry: Retry,
+    fallback: Fallback,
+    replay_buffer: usize,
+    nonblocking: bool,
+    on_error: Option<ErrorHook>,
+    auto_reopen: u32,
+    dedup: bool,
+    call_site_rate_limit: Option<RateLimit>,
+    rate_limit: Option<RateLimit>,
+    pace_printk_ratelimit: bool,
+    target_rate_limits: Vec<(String, RateLimit)>,
+    level_rate_limits: [Option<RateLimit>; 5],
+    sampling: [u32; 5],
+    never_suppress: LevelFilter,
+    suppression_report: Option<Duration>,
+}
+
+impl Builder {
+    /// Create new builder logging everything to `/dev/kmsg` in default format
+    pub fn new() -> Builder {
+        Builder {
+            target: Target::Device(PathBuf::from(KernelLog::DEFAULT_DEVICE)),
+            level: LevelFilter::Trace,
+            lazy: false,
+            default_format: DefaultFormat::new(),
+            format: None,
+            template: None,
+            sanitizer: Sanitizer::default(),
+            max_record_len: None,
+            syslog_fields: false,
+            fields: Vec::new(),
+            facility: Facility::Kern,
+            severity_suffixes: false,
+            filter: None,
+            #[cfg(feature = "env_filter")]
+            env_filter: None,
+            console_level: false,
+            kernel_cmdline: false,
+            allow_targets: Vec::new(),
+            deny_targets: Vec::new(),
+            remaps: Vec::new(),
+            background: None,
+            backpressure: Backpressure::DropNewest,
+            io_uring: false,
+            retry: Retry::NONE,
+            fallback: Fallback::None,
+            replay_buffer: 0,
+            nonblocking: false,
+            on_error: None,
+            auto_reopen: 0,
+            dedup: false,
+            call_site_rate_limit: None,
+            rate_limit: None,
+            pace_printk_ratelimit: false,
+            target_rate_limits: Vec::new(),
+            level_rate_limits: [None; 5],
+            sampling: [1; 5],
+            never_suppress: LevelFilter::Error,
+            suppression_report: None,
+        }
+    }
+
+    /// Log to specific device
+    pub fn device(mut self, device: impl AsRef<Path>) -> Builder {
+        self.target = Target::Device(device.as_ref().to_owned());
+        self
+    }
+
+    /// Log to already opened device file, see [`KernelLog::from_file()`]
+    pub fn file(mut self, file: File) -> Builder {
+        self.target = Target::File(file);
+        self
+    }
+
+    /// Write records to custom backend instead of a device, see [`KmsgBackend`]
+    ///
+    /// Device specific options, like [`Builder::lazy()`] and [`Builder::nonblocking()`],
+    /// don't apply to it. Records are split by backend's [`KmsgBackend::max_len()`],
+    /// unless overridden with [`Builder::max_record_len()`].
+    pub fn backend(mut self, backend: impl KmsgBackend) -> Builder {
+        self.target = Target::Backend(Box::new(backend));
+        self
+    }
+
+    /// Set level filter
+    pub fn level(mut self, filter: LevelFilter) -> Builder {
+        self.level = filter;
+        self
+    }
+
+    /// Set per-target level filter directives, like `info,my_crate::net=trace,hyper=warn`
+    ///
+    /// Directives are comma separated, each one is either:
+    ///
+    /// - a level, which replaces level filter set with [`Builder::level()`],
+    /// - a target with a level (`hyper=warn`), which sets level filter for the target
+    ///   and all its submodules (`hyper::client`, but not `hyperlocal`),
+    /// - a target alone, which enables all levels for it,
+    /// - a target with a rate limit (`udev_helper=5/s`), see [`Builder::rate_limit_target()`].
+    ///
+    /// With `regex` feature, target may be also a regular expression between slashes,
+    /// like `/^app::(net|db)/=trace`. Such directives are only consulted for records
+    /// not matching any plain target directive, in the order given.
+    ///
+    /// The most specific directive matching record target wins, records with targets
+    /// not matching any directive are filtered by the level filter.
+    /// Directives are parsed when the logger is built.
+    pub fn filter(mut self, directives: impl Into<String>) -> Builder {
+        self.filter = Some(directives.into());
+        self
+    }
+
+    /// Filter records with `env_logger` filter spec, like `info,hyper=warn/timeout`
+    ///
+    /// The spec is parsed by `env_filter` crate, so it means exactly the same as in `RUST_LOG`
+    /// for `env_logger`, including message patterns after a slash, which are regular expressions
+    /// with `regex` feature and substrings otherwise. It replaces level filter
+    /// and [`Builder::filter()`] level directives, while allowed and denied targets,
+    /// level remapping and verbosity guards still apply.
+    /// The spec is parsed when the logger is built.
+    #[cfg(feature = "env_filter")]
+    pub fn env_filter(mut self, spec: impl Into<String>) -> Builder {
+        self.env_filter = Some(spec.into());
+        self
+    }
+
+    /// Pass only records from these targets and their submodules, all targets by default
+    ///
+    /// Can be called several times to allow more targets.
+    pub fn allow_targets(mut self, targets: &[&str]) -> Builder {
+        self.allow_targets.extend(targets.iter().map(|&target| target.to_owned()));
+        self
+    }
+
+    /// Drop records from these targets and their submodules regardless of level
+    ///
+    /// Denied targets take precedence over allowed ones, so a chatty submodule
+    /// of an allowed target can still be excluded.
+    /// Can be called several times to deny more targets.
+    pub fn deny_targets(mut self, targets: &[&str]) -> Builder {
+        self.deny_targets.extend(targets.iter().map(|&target| target.to_owned()));
+        self
+    }
+
+    /// Treat records of `from` level coming from target and its submodules as `to` level
+    ///
+    /// ```rust,no_run
+    /// use kernlog::KernelLog;
+    /// use log::Level;
+    ///
+    /// let klog = KernelLog::builder()
+    ///     .remap_level("reqwest", Level::Info, Level::Debug)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    ///
+    /// Remapping is applied before level filtering and choosing printk priority, so
+    /// dependency chatter doesn't land at `KERN_NOTICE`. The most specific target wins.
+    /// Remapping to a more severe level doesn't let through records filtered out
+    /// by global `log` max level.
+    pub fn remap_level(mut self, target: &str, from: Level, to: Level) -> Builder {
+        self.remaps.push((target.to_owned(), from, to));
+        self
+    }
+
+    /// Take level filter from current kernel console loglevel, see [`sysctl::console_loglevel()`]
+    ///
+    /// Records are passed if the kernel would print them to the console, e.g. the default
+    /// console loglevel 4 passes only errors and 8 passes everything. This overrides
+    /// [`Builder::level()`], which is still used if `kernel.printk` sysctl can't be read.
+    pub fn console_level(mut self, console_level: bool) -> Builder {
+        self.console_level = console_level;
+        self
+    }
+
+    /// Take level filter from `quiet`, `debug` and `loglevel=N` kernel command line parameters
+    ///
+    /// They are treated as console loglevel, like with [`Builder::console_level()`]: `quiet` passes
+    /// only errors and `debug` passes everything. This takes precedence over other level settings,
+    /// including a bare level in [`Builder::filter()`] directives, which are still used if `/proc/cmdline` can't be read or has none of these parameters.
+    pub fn respect_kernel_cmdline(mut self, respect: bool) -> Builder {
+        self.kernel_cmdline = respect;
+        self
+    }
+
+    /// Write records somewhere else if the device can't be opened or written to
+    ///
+    /// With a fallback, failure to open the device doesn't fail [`Builder::build()`]:
+    /// the device is opened lazily instead, so logging switches back to it once it's available.
+    /// Only records logged through [`Log`](log::Log) are written to fallback,
+    /// [`KernelLog::write_raw()`] reports errors instead.
+    pub fn fallback(mut self, fallback: Fallback) -> Builder {
+        self.fallback = fallback;
+        self
+    }
+
+    /// Call `on_error` with the error and the record whenever the device fails to accept a record
+    ///
+    /// The record is passed as formatted, with priority prefix and dictionary, but without
+    /// the trailing new line. It's called after writing the record to [`Builder::fallback()`],
+    /// from the logging thread or background writer thread, with no logger locks held,
+    /// so it may log through this logger itself. On background writer thread records logged
+    /// by the hook are dropped if the queue is full, even with [`Backpressure::Block`],
+    /// and [`Log::flush()`](log::Log::flush) returns at once, as the thread can't wait for itself.
+    pub fn on_error<F>(mut self, on_error: F) -> Builder
+    where
+        F: Fn(&io::Error, &str) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Box::new(on_error));
+        self
+    }
+
+    /// Buffer up to `capacity` records in memory while the device can't be opened,
+    /// writing them in order once it's opened
+    ///
+    /// Useful in early initramfs, where `/dev/kmsg` may not exist yet. Failure to open
+    /// the device doesn't fail [`Builder::build()`] then, the device is opened lazily instead.
+    /// Only records fallback destination doesn't accept are buffered (see [`Builder::fallback()`]),
+    /// so they are not written twice. Records logged after the buffer is full are dropped,
+    /// so the earliest records are kept.
+    pub fn replay_buffer(mut self, capacity: usize) -> Builder {
+        self.replay_buffer = capacity;
+        self
+    }
+
+    /// Open device lazily on first log record, see [`KernelLog::new_lazy()`]
+    pub fn lazy(mut self, lazy: bool) -> Builder {
+        self.lazy = lazy;
+        self
+    }
+
+    /// Write to device in non-blocking mode, so logging never blocks the calling thread
+    ///
+    /// Records the device doesn't accept right away are dropped and counted,
+    /// see [`KernelLog::would_block_drops()`].
+    pub fn nonblocking(mut self, nonblocking: bool) -> Builder {
+        self.nonblocking = nonblocking;
+        self
+    }
+
+    /// Reopen the device when writes fail with `EBADF`, `ENXIO` or `EIO`, retrying the failed write
+    ///
+    /// This restores logging after e.g. mount namespace switch without process restart,
+    /// see [`KernelLog::reopen()`]. Reopening is given up after `attempts` failures in a row,
+    /// until a write succeeds again. It's disabled by default, and has no effect
+    /// for loggers created from an already opened file.
+    pub fn auto_reopen(mut self, attempts: u32) -> Builder {
+        self.auto_reopen = attempts;
+        self
+    }
+
+    /// Suppress identical consecutive records, writing `last message repeated N times` summary instead
+    ///
+    /// Records are compared as formatted, so formats with timestamps or counters defeat this.
+    /// The summary is formatted with the same priority, target and tag as the repeated record,
+    /// and written once a different record is logged,
+    /// or on [`Log::flush()`](log::Log::flush).
+    pub fn dedup(mut self, dedup: bool) -> Builder {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Limit rate of records from each call site, keyed by source file and line
+    ///
+    /// Records from a call site over its budget are dropped, and `suppressed N messages at file:line`
+    /// record is written once it passes a record again, once `limit` interval has passed since
+    /// the first of them and another record is logged, or on [`Log::flush()`](log::Log::flush).
+    /// Records without source location are not limited.
+    pub fn rate_limit_call_sites(mut self, limit: RateLimit) -> Builder {
+        self.call_site_rate_limit = Some(limit);
+        self
+    }
+
+    /// Limit rate of all records together
+    ///
+    /// Records over the limit are dropped, and once a record is passed again,
+    /// `suppressed N messages in the last Ns` warning is written before it,
+    /// like kernel reports its own rate limited messages.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Builder {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Limit rate of all records together to kernel printk rate limit, see [`sysctl::printk_ratelimit()`]
+    ///
+    /// Applies only when writing to `/dev/kmsg` while `kernel.printk_devkmsg` sysctl is `ratelimit`,
+    /// as kernel silently drops records over the limit then. Records are suppressed and reported
+    /// like with [`Builder::rate_limit()`] instead, which takes precedence if set.
+    pub fn pace_printk_ratelimit(mut self, pace: bool) -> Builder {
+        self.pace_printk_ratelimit = pace;
+        self
+    }
+
+    /// Limit rate of records from a target and its submodules, e.g. `udev_helper`
+    ///
+    /// Only the most specific target limit matching record target applies, followed by
+    /// [`Builder::rate_limit_level()`] and [`Builder::rate_limit()`] ones. Target limits can be
+    /// also given in [`Builder::filter()`] directives, like `udev_helper=5/s`, which replace
+    /// ones set with this method for the same target. Once a record from the target is passed again,
+    /// `suppressed N messages from <target> in the last Ns` warning is written before it.
+    pub fn rate_limit_target(mut self, target: impl Into<String>, limit: RateLimit) -> Builder {
+        self.target_rate_limits.push((target.into(), limit));
+        self
+    }
+
+    /// Limit rate of records of a level, e.g. to keep tracing enabled without drowning errors
+    ///
+    /// Records of other levels are not affected, except by [`Builder::rate_limit()`],
+    /// which is checked after this one. Once a record of the level is passed again,
+    /// `suppressed N <level> messages in the last Ns` record of the same level is written before it.
+    pub fn rate_limit_level(mut self, level: Level, limit: RateLimit) -> Builder {
+        self.level_rate_limits[level as usize - 1] = Some(limit);
+        self
+    }
+
+    /// Keep randomly chosen one in `one_in` records of a level, dropping the rest
+    ///
+    /// Meant for high frequency `Debug` and `Trace` instrumentation, where logging every record
+    /// is too expensive. Records are sampled before rate limits are checked, and dropped ones
+    /// take no rate limit budget and get no summary of their own, but are counted
+    /// in [`Builder::suppression_report()`]. Passing 0 or 1 keeps all records of the level.
+    pub fn sample(mut self, level: Level, one_in: u32) -> Builder {
+        self.sampling[level as usize - 1] = one_in;
+        self
+    }
+
+    /// Never rate limit or sample records at or above the level, `Error` by default
+    ///
+    /// This makes sure critical failures always reach the ring buffer, even if some loop
+    /// exhausted the budget. Use `LevelFilter::Off` to subject all records to limits.
+    pub fn never_suppress(mut self, level: LevelFilter) -> Builder {
+        self.never_suppress = level;
+        self
+    }
+
+    /// Report numbers of records suppressed by rate limits, sampling or [`Builder::dedup()`]
+    /// for each target at most once per `interval`
+    ///
+    /// The report is a single `suppressed in the last Ns: target=N ...` warning, written before
+    /// the next logged record once `interval` passed since the first suppressed one,
+    /// so the loss is visible in dmesg itself.
+    pub fn suppression_report(mut self, interval: Duration) -> Builder {
+        self.suppression_report = Some(interval);
+        self
+    }
+
+    /// Prefix records with a tag, like syslog identifier
+    ///
+    /// Only applies to the default format, see [`DefaultFormat::tag()`].
+    pub fn tag(mut self, tag: impl Into<String>) -> Builder {
+        self.default_format = self.default_format.tag(tag);
+        self
+    }
+
+    /// Prefix records with program name as a tag
+    ///
+    /// Only applies to the default format, see [`DefaultFormat::program_tag()`].
+    pub fn program_tag(mut self) -> Builder {
+        self.default_format = self.default_format.program_tag();
+        self
+    }
+
+    /// Choose what to report as record origin, target by default
+    ///
+    /// Only applies to the default format, see [`DefaultFormat::origin()`].
+    pub fn origin(mut self, origin: Origin) -> Builder {
+        self.default_format = self.default_format.origin(origin);
+        self
+    }
+
+    /// Report process id in records, enabled by default
+    ///
+    /// Single process programs may disable it to save bytes of the record size limit.
+    /// Only applies to the default format, see [`DefaultFormat::pid()`].
+    pub fn pid(mut self, pid: bool) -> Builder {
+        self.default_format = self.default_format.pid(pid);
+        self
+    }
+
+    /// Report thread id in records, disabled by default
+    ///
+    /// Only applies to the default format, see [`DefaultFormat::tid()`].
+    pub fn tid(mut self, tid: bool) -> Builder {
+        self.default_format = self.default_format.tid(tid);
+        self
+    }
+
+    /// Report current thread name in records, disabled by default
+    ///
+    /// Only applies to the default format, see [`DefaultFormat::thread_name()`].
+    pub fn thread_name(mut self, thread_name: bool) -> Builder {
+        self.default_format = self.default_format.thread_name(thread_name);
+        self
+    }
+
+    /// Append source location to messages, disabled by default
+    ///
+    /// Only applies to the default format, see [`DefaultFormat::location()`].
+    pub fn location(mut self, location: bool) -> Builder {
+        self.default_format = self.default_format.location(location);
+        self
+    }
+
+    /// Set record formatter, replacing the default format
+    pub fn formatter(mut self, format: Box<dyn KmsgFormat>) -> Builder {
+        self.format = Some(format);
+        self.template = None;
+        self
+    }
+
+    /// Set record format template, replacing the default format
+    ///
+    /// Template is parsed when the logger is built, see [`TemplateFormat`] for its syntax.
+    /// Tag set with [`Builder::tag()`] is used for `{tag}` placeholder.
+    pub fn template(mut self, template: impl Into<String>) -> Builder {
+        self.template = Some(template.into());
+        self.format = None;
+        self
+    }
+
+    /// Set closure as a record formatter
+    ///
+    /// ```rust,no_run
+    /// use std::io::Write;
+    ///
+    /// let klog = kernlog::KernelLog::builder()
+    ///     .format(|buf, record| write!(buf, "{}", record.args()))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn format<F>(self, format: F) -> Builder
+    where
+        F: Fn(&mut dyn Write, &Record) -> io::Result<()> + Send + Sync + 'static,
+    {
+        self.formatter(Box::new(format))
+    }
+
+    /// Remove ANSI escape sequences (e.g. colors) from messages, disabled by default
+    ///
+    /// Third party crates sometimes color their messages, which looks like garbage in `dmesg`.
+    pub fn strip_ansi(mut self, strip_ansi: bool) -> Builder {
+        self.sanitizer.strip_ansi = strip_ansi;
+        self
+    }
+
+    /// Set syslog facility of records, `Kern` by default
+    ///
+    /// Facility lets consumers tell userspace programs apart from true kernel messages.
+    pub fn facility(mut self, facility: Facility) -> Builder {
+        self.facility = facility;
+        self
+    }
+
+    /// Map records with target suffixed by `::emerg`, `::alert` or `::crit`
+    /// to the corresponding printk severity, disabled by default
+    ///
+    /// Suffix is stripped from the target. This allows to emit critical records,
+    /// which reach the console, using normal `log` macros:
+    ///
+    /// ```rust,no_run
+    /// # use log::error;
+    /// error!(target: "myinit::emerg", "no root filesystem, halting");
+    /// ```
+    pub fn severity_suffixes(mut self, severity_suffixes: bool) -> Builder {
+        self.severity_suffixes = severity_suffixes;
+        self
+    }
+
+    /// Escape ASCII control characters in messages as `\xNN`, enabled by default
+    ///
+    /// This keeps binary data in messages from corrupting records and `dmesg` output.
+    pub fn escape_control(mut self, escape_control: bool) -> Builder {
+        self.sanitizer.escape_control = escape_control;
+        self
+    }
+
+    /// Append `SYSLOG_IDENTIFIER` and `SYSLOG_PID` dictionary fields to records, disabled by default
+    ///
+    /// Dictionary fields are passed to backends as continuation lines after the message,
+    /// so journald, syslog and logd backends and journald fallback can attribute records properly.
+    /// They are not written to the kernel log device, as kernel doesn't parse dictionaries
+    /// of userspace writes. Identifier is the tag (see [`Builder::tag()`]) or program name
+    /// if there's none.
+    pub fn syslog_fields(mut self, syslog_fields: bool) -> Builder {
+        self.syslog_fields = syslog_fields;
+        self
+    }
+
+    /// Append custom dictionary field to records
+    ///
+    /// Field names may consist of uppercase letters, digits and underscores only
+    /// and must not start with an underscore or a digit, otherwise building fails.
+    pub fn field(mut self, key: impl Into<String>, value: impl Into<String>) -> Builder {
+        self.fields.push((key.into(), value.into()));
+        self
+    }
+
+    /// Write records from a background thread, queueing up to `capacity` records
+    ///
+    /// Records are formatted in the logging thread, but the possibly blocking device
+    /// writes happen in a dedicated `kernlog` thread, so callers never stall on a slow
+    /// or rate-limited device. Records are dropped while the queue is full,
+    /// unless a different [`Builder::backpressure()`] policy is chosen.
+    /// [`Log::flush()`](log::Log::flush) waits until all queued records are written,
+    /// and records still queued when the logger is dropped are written synchronously.
+    pub fn background(mut self, capacity: usize) -> Builder {
+        self.background = Some(capacity);
+        self
+    }
+
+    /// Choose what to do when background writer queue is full, see [`Builder::background()`]
+    ///
+    /// Dropped records are counted, see [`KernelLog::queue_overflows()`].
+    pub fn backpressure(mut self, backpressure: Backpressure) -> Builder {
+        self.backpressure = backpressure;
+        self
+    }
+
+    /// Retry writes failing with `EAGAIN` or `EINTR` up to `attempts` times from background writer thread,
+    /// see [`Builder::background()`]
+    ///
+    /// The first retry is made after `backoff`, which is doubled for each next one, so records
+    /// survive brief printk ratelimit windows. Records still failing are dropped or written to fallback.
+    /// It has no effect without background writer thread, as logging threads are never delayed.
+    pub fn retry(mut self, attempts: u32, backoff: Duration) -> Builder {
+        self.retry = Retry { attempts, backoff };
+        self
+    }
+
+    /// Submit records through io_uring from background writer thread, see [`Builder::background()`]
+    ///
+    /// All records queued since the last wakeup are submitted at once, saving syscalls
+    /// when logging at high rate. It has no effect without background writer thread,
+    /// and plain writes are used if io_uring is unavailable, e.g. disabled by
+    /// `kernel.io_uring_disabled` sysctl or seccomp.
+    #[cfg(feature = "io-uring")]
+    pub fn io_uring(mut self, io_uring: bool) -> Builder {
+        self.io_uring = io_uring;
+        self
+    }
+
+    /// Override maximum record length, detected from running kernel version by default,
+    /// see [`KernelLog::max_record_len()`]
+    pub fn max_record_len(mut self, max_record_len: usize) -> Builder {
+        self.max_record_len = Some(max_record_len);
+        self
+    }
+
+    /// Apply settings from environment variables, see [`KernelLog::from_env()`]
+    ///
+    /// Invalid values are ignored.
+    pub fn env(mut self) -> Builder {
+        if let Ok(Some(filter)) = env::level() {
+            self = self.level(filter);
+        }
+        if let Ok(Some(directives)) = env::filter() {
+            self = self.filter(directives);
+        }
+        if let Ok(Some(template)) = env::template() {
+            self = self.template(template);
+        }
+        self
+    }
+
+    /// Apply settings from environment variables, failing on invalid values,
+    /// see [`KernelLog::from_env_strict()`]
+    pub fn env_strict(mut self) -> io::Result<Builder> {
+        if let Some(filter) = env::level()? {
+            self = self.level(filter);
+        }
+        if let Some(directives) = env::filter()? {
+            self = self.filter(directives);
+        }
+        if let Some(template) = env::template()? {
+            self = self.template(template);
+        }
+        Ok(self)
+    }
+
+    /// Build kernel logger, opening the device unless it's lazy
+    ///
+    /// Fails if the device can't be opened, filter directives, the format template
+    /// or dictionary field names are invalid, or background writer thread can't be spawned.
+    pub fn build(self) -> io::Result<KernelLog> {
+        let mut filter = match self.filter {
+            Some(ref directives) => Filter::parse(directives)?,
+            None => Filter::default(),
+        };
+        #[cfg(feature = "env_filter")]
+        if let Some(ref spec) = self.env_filter {
+            let env = env_filter::Builder::new()
+                .try_parse(spec)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid env filter: {}", err)))?
+                .build();
+            filter.env = Some(env);
+        }
+        filter.allow = self.allow_targets;
+        filter.deny = self.deny_targets;
+        for (target, from, to) in &self.remaps {
+            filter.add_remap(target, *from, *to);
+        }
+        let mut target_rate_limits = self.target_rate_limits;
+        target_rate_limits.append(&mut filter.rate_limits);
+        let mut targets: Vec<(String, Budget)> = Vec::new();
+        for (target, limit) in target_rate_limits {
+            match targets.iter_mut().find(|(name, _)| *name == target) {
+                Some(existing) => existing.1 = Budget::new(limit),
+                None => targets.push((target, Budget::new(limit))),
+            }
+        }
+        ratelimit::sort_targets(&mut targets);
+        let dictionary = Dictionary {
+            identifier: if self.syslog_fields {
+                Some(self.default_format.tag.clone().unwrap_or_else(program_name))
+            } else {
+                None
+            },
+            pid: self.syslog_fields,
+            fields: self.fields,
+        };
+        dictionary.validate()?;
+
+        let paced = self.pace_printk_ratelimit
+            && matches!(self.target, Target::Device(ref device) if backend::is_kmsg(device))
+            && sysctl::devkmsg_mode().ok() == Some(sysctl::DevkmsgMode::Ratelimit);
+        let rate_limit = match self.rate_limit {
+            Some(limit) => Some(limit),
+            None if paced => sysctl::printk_ratelimit().ok(),
+            None => None,
+        };
+        let kmsg: Box<dyn KmsgBackend> = match self.target {
+            Target::Device(ref device) if self.lazy => Box::new(Kmsg::lazy(device, self.nonblocking)),
+            Target::Device(ref device) => match Kmsg::new(device, self.nonblocking) {
+                Ok(kmsg) => Box::new(kmsg),
+                Err(_) if self.fallback != Fallback::None || self.replay_buffer > 0 => {
+                    Box::new(Kmsg::lazy(device, self.nonblocking))
+                }
+                Err(err) => return Err(err),
+            },
+            Target::File(file) => {
+                if self.nonblocking {
+                    set_nonblocking(&file)?;
+                }
+                Box::new(Kmsg::from_file(file))
+            }
+            Target::Backend(backend) => backend,
+        };
+        // Tag of the default format is rendered into record prefixes once
+        let mut tag = None;
+        let format: Box<dyn KmsgFormat> = match (self.format, self.template) {
+            (Some(format), _) => format,
+            (None, Some(template)) => match self.default_format.tag {
+                Some(tag) => Box::new(TemplateFormat::new(&template)?.tag(tag)),
+                None => Box::new(TemplateFormat::new(&template)?),
+            },
+            (None, None) => {
+                let mut format = self.default_format;
+                tag = format.tag.take();
+                Box::new(format)
+            }
+        };
+        let cmdline = if self.kernel_cmdline { cmdline::console_loglevel().ok().flatten() } else { None };
+        let console = if self.console_level { sysctl::console_loglevel().ok() } else { None };
+        // Kernel command line overrides default level of filter directives too
+        let level = cmdline.map(level::filter_from_console_loglevel)
+            .or(filter.default)
+            .or_else(|| console.map(level::filter_from_console_loglevel))
+            .unwrap_or(self.level);
+        let mut shared = Shared::new(kmsg, level, format);
+        shared.filter = filter;
+        shared.sanitizer = self.sanitizer;
+        shared.dictionary = dictionary;
+        shared.prefixes = level::Prefixes::new(self.facility, tag.as_deref());
+        shared.fallback = FallbackWriter::new(self.fallback);
+        shared.nonblocking = self.nonblocking;
+        shared.pending = Mutex::new(Pending::new(self.replay_buffer));
+        shared.severity_suffixes = self.severity_suffixes;
+        shared.on_error = self.on_error;
+        shared.reopen_attempts = self.auto_reopen;
+        shared.limits = Limits {
+            call_sites: self.call_site_rate_limit.map(CallSites::new),
+            targets,
+            exempt: self.never_suppress,
+            sampling: self.sampling,
+            levels: self.level_rate_limits.map(|limit| limit.map(Budget::new)),
+            global: rate_limit.map(Budget::new),
+        };
+        shared.report = self.suppression_report.map(Report::new);
+        if self.dedup {
+            shared.dedup = Some(Mutex::new(Dedup::default()));
+        }
+        if let Some(max_record_len) = self.max_record_len {
+            shared.max_record_len = max_record_len;
+        }
+        let backpressure = self.backpressure;
+        let queue = self.background.map(|capacity| Arc::new(Queue::new(capacity, backpressure)));
+        shared.queue = queue.clone();
+
+        let shared = Arc::new(shared);
+        if let Some(queue) = queue {
+            queue::spawn(queue, Arc::downgrade(&shared), self.io_uring, self.retry)?;
+        }
+        Ok(KernelLog { shared })
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
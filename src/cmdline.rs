@@ -0,0 +1,44 @@
+//! Kernel command line parsing
+
+use std::fs;
+use std::io;
+
+const CMDLINE: &str = "/proc/cmdline";
+
+/// Console loglevel kernel uses with `quiet` parameter
+const QUIET_LOGLEVEL: u8 = 4;
+/// Console loglevel kernel uses with `debug` parameter
+const DEBUG_LOGLEVEL: u8 = 10;
+
+/// Get console loglevel requested on kernel command line with `quiet`, `debug` or `loglevel=N`,
+/// `None` if there are no such parameters
+pub(crate) fn console_loglevel() -> io::Result<Option<u8>> {
+    Ok(parse_console_loglevel(&fs::read_to_string(CMDLINE)?))
+}
+
+/// The last parameter wins, as in kernel
+fn parse_console_loglevel(cmdline: &str) -> Option<u8> {
+    cmdline.split_whitespace().fold(None, |level, param| match param {
+        "quiet" => Some(QUIET_LOGLEVEL),
+        "debug" => Some(DEBUG_LOGLEVEL),
+        _ => match param.strip_prefix("loglevel=") {
+            Some(value) => value.parse().ok().or(level),
+            None => level,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_console_loglevel;
+
+    #[test]
+    fn parse_cmdline() {
+        assert_eq!(parse_console_loglevel("BOOT_IMAGE=/vmlinuz root=/dev/sda1 ro\n"), None);
+        assert_eq!(parse_console_loglevel("root=/dev/sda1 quiet splash"), Some(4));
+        assert_eq!(parse_console_loglevel("quiet debug"), Some(10));
+        assert_eq!(parse_console_loglevel("debug loglevel=6"), Some(6));
+        assert_eq!(parse_console_loglevel("loglevel=7 loglevel=x"), Some(7));
+        assert_eq!(parse_console_loglevel("systemd.log_level=debug"), None);
+    }
+}
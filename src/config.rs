@@ -0,0 +1,190 @@
+//! Logger configuration
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use log::{Level, LevelFilter};
+
+use crate::{Backpressure, Facility, Fallback, KernelLog, Origin, RateLimit};
+
+/// Kernel logger configuration
+///
+/// With `serde` feature enabled it can be deserialized from any format
+/// supported by serde, e.g. a section of TOML or JSON config file.
+/// All fields are optional and default to the values of [`Config::default()`].
+///
+/// ```toml
+/// device = "/dev/kmsg"
+/// level = "info"
+/// format = "{tag}: {target}: {msg}"
+/// tag = "myinit"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default, deny_unknown_fields))]
+pub struct Config {
+    /// Device to log to, `/dev/kmsg` by default
+    pub device: PathBuf,
+    /// Level filter, `trace` by default
+    pub level: LevelFilter,
+    /// Take level filter from kernel console loglevel, see [`Builder::console_level()`](crate::Builder::console_level)
+    pub console_level: bool,
+    /// Take level filter from kernel command line,
+    /// see [`Builder::respect_kernel_cmdline()`](crate::Builder::respect_kernel_cmdline)
+    pub respect_kernel_cmdline: bool,
+    /// Per-target level filter directives, see [`Builder::filter()`](crate::Builder::filter)
+    pub filter: Option<String>,
+    /// `env_logger` filter spec, see [`Builder::env_filter()`](crate::Builder::env_filter)
+    #[cfg(feature = "env_filter")]
+    pub env_filter: Option<String>,
+    /// Targets to pass exclusively, see [`Builder::allow_targets()`](crate::Builder::allow_targets)
+    pub allow_targets: Vec<String>,
+    /// Targets to drop, see [`Builder::deny_targets()`](crate::Builder::deny_targets)
+    pub deny_targets: Vec<String>,
+    /// Level remappings for noisy targets, see [`Builder::remap_level()`](crate::Builder::remap_level)
+    pub remap_levels: Vec<LevelRemap>,
+    /// Where to write records if the device fails, see [`Builder::fallback()`](crate::Builder::fallback)
+    pub fallback: Fallback,
+    /// Number of records to buffer while the device can't be opened,
+    /// see [`Builder::replay_buffer()`](crate::Builder::replay_buffer)
+    pub replay_buffer: Option<usize>,
+    /// Open device lazily on first log record, see [`KernelLog::new_lazy()`]
+    pub lazy: bool,
+    /// Write to device in non-blocking mode, see [`Builder::nonblocking()`](crate::Builder::nonblocking)
+    pub nonblocking: bool,
+    /// Number of times to try reopening the device after it starts failing writes, none by default,
+    /// see [`Builder::auto_reopen()`](crate::Builder::auto_reopen)
+    pub auto_reopen: u32,
+    /// Suppress identical consecutive records, see [`Builder::dedup()`](crate::Builder::dedup)
+    pub dedup: bool,
+    /// Rate limit of records from each call site like `10/5s`, none by default,
+    /// see [`Builder::rate_limit_call_sites()`](crate::Builder::rate_limit_call_sites)
+    pub call_site_rate_limit: Option<RateLimit>,
+    /// Rate limit of all records together like `100/s`, none by default,
+    /// see [`Builder::rate_limit()`](crate::Builder::rate_limit)
+    pub rate_limit: Option<RateLimit>,
+    /// Limit rate of all records to kernel printk rate limit, disabled by default,
+    /// see [`Builder::pace_printk_ratelimit()`](crate::Builder::pace_printk_ratelimit)
+    pub pace_printk_ratelimit: bool,
+    /// Rate limits of targets, see [`Builder::rate_limit_target()`](crate::Builder::rate_limit_target)
+    pub target_rate_limits: BTreeMap<String, RateLimit>,
+    /// Rate limits of records of each level, see [`Builder::rate_limit_level()`](crate::Builder::rate_limit_level)
+    pub level_rate_limits: BTreeMap<Level, RateLimit>,
+    /// Keep one in that many records of each level, see [`Builder::sample()`](crate::Builder::sample)
+    pub sampling: BTreeMap<Level, u32>,
+    /// Never rate limit or sample records at or above the level, `Error` by default,
+    /// see [`Builder::never_suppress()`](crate::Builder::never_suppress)
+    pub never_suppress: LevelFilter,
+    /// Interval in seconds to report numbers of suppressed records at, none by default,
+    /// see [`Builder::suppression_report()`](crate::Builder::suppression_report)
+    pub suppression_report_secs: Option<u64>,
+    /// Tag to prefix log records with, none by default
+    ///
+    /// Empty tag stands for program name, see [`DefaultFormat::program_tag()`](crate::DefaultFormat::program_tag).
+    pub tag: Option<String>,
+    /// Record format template, see [`TemplateFormat`](crate::TemplateFormat), default format if not set
+    pub format: Option<String>,
+    /// What to report as record origin, `target` by default
+    pub origin: Origin,
+    /// Report process id in log records, enabled by default
+    pub pid: bool,
+    /// Report thread id in log records, disabled by default
+    pub tid: bool,
+    /// Report thread name in log records, disabled by default
+    pub thread_name: bool,
+    /// Append source location to log messages, disabled by default
+    pub location: bool,
+    /// Remove ANSI escape sequences from log messages, disabled by default
+    pub strip_ansi: bool,
+    /// Escape control characters in log messages, enabled by default
+    pub escape_control: bool,
+    /// Append `SYSLOG_IDENTIFIER` and `SYSLOG_PID` dictionary fields, disabled by default
+    pub syslog_fields: bool,
+    /// Custom dictionary fields to append to records
+    pub fields: BTreeMap<String, String>,
+    /// Syslog facility, `kern` by default
+    pub facility: Facility,
+    /// Map `::emerg`, `::alert` and `::crit` target suffixes to printk severities, disabled by default
+    pub severity_suffixes: bool,
+    /// Maximum record length, detected from kernel version if not set
+    pub max_record_len: Option<usize>,
+    /// Capacity of background writer queue, records are written synchronously if not set,
+    /// see [`Builder::background()`](crate::Builder::background)
+    pub background: Option<usize>,
+    /// What to do when background writer queue is full, drop new records by default
+    pub backpressure: Backpressure,
+    /// Number of retries for writes failing with `EAGAIN` or `EINTR` in background writer thread,
+    /// none by default, see [`Builder::retry()`](crate::Builder::retry)
+    pub retry_attempts: u32,
+    /// Delay before the first retry in milliseconds, doubled for each next one
+    pub retry_backoff_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            device: PathBuf::from(KernelLog::DEFAULT_DEVICE),
+            level: LevelFilter::Trace,
+            console_level: false,
+            respect_kernel_cmdline: false,
+            filter: None,
+            #[cfg(feature = "env_filter")]
+            env_filter: None,
+            allow_targets: Vec::new(),
+            deny_targets: Vec::new(),
+            remap_levels: Vec::new(),
+            fallback: Fallback::None,
+            replay_buffer: None,
+            lazy: false,
+            nonblocking: false,
+            auto_reopen: 0,
+            dedup: false,
+            call_site_rate_limit: None,
+            rate_limit: None,
+            pace_printk_ratelimit: false,
+            target_rate_limits: BTreeMap::new(),
+            level_rate_limits: BTreeMap::new(),
+            sampling: BTreeMap::new(),
+            never_suppress: LevelFilter::Error,
+            suppression_report_secs: None,
+            tag: None,
+            format: None,
+            origin: Origin::Target,
+            pid: true,
+            tid: false,
+            thread_name: false,
+            location: false,
+            strip_ansi: false,
+            escape_control: true,
+            syslog_fields: false,
+            fields: BTreeMap::new(),
+            facility: Facility::Kern,
+            severity_suffixes: false,
+            max_record_len: None,
+            background: None,
+            backpressure: Backpressure::DropNewest,
+            retry_attempts: 0,
+            retry_backoff_ms: 10,
+        }
+    }
+}
+
+/// Level remapping for records from a target, see [`Builder::remap_level()`](crate::Builder::remap_level)
+///
+/// ```toml
+/// [[remap_levels]]
+/// target = "reqwest"
+/// from = "info"
+/// to = "debug"
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct LevelRemap {
+    /// Target to remap levels of, along with its submodules
+    pub target: String,
+    /// Level to remap
+    pub from: Level,
+    /// Level to treat records as
+    pub to: Level,
+}
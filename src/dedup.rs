@@ -0,0 +1,99 @@
+//! Suppression of identical consecutive records
+
+use log::{Level, Record};
+
+/// Last written record and number of its suppressed repeats
+#[derive(Debug, Default)]
+pub(crate) struct Dedup {
+    last: Vec<u8>,
+    origin: Option<Origin>,
+    repeated: u64,
+}
+
+/// Metadata of the last record, so the summary of its repeats is formatted like it
+#[derive(Debug)]
+struct Origin {
+    level: Level,
+    target: String,
+    module_path: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    severity: u8,
+}
+
+impl Dedup {
+    /// Check if formatted record is different from the previous one, so it should be written
+    ///
+    /// Once a different record comes, a summary of suppressed repeats of the previous one
+    /// is passed to `summary` before it's remembered.
+    pub(crate) fn push(&mut self, record: &Record, severity: u8, buf: &[u8], summary: impl FnOnce(&Record, u8)) -> bool {
+        if buf == self.last {
+            self.repeated += 1;
+            return false;
+        }
+        self.flush(summary);
+        self.last.clear();
+        self.last.extend_from_slice(buf);
+        self.origin = Some(Origin {
+            level: record.level(),
+            target: record.target().to_owned(),
+            module_path: record.module_path().map(str::to_owned),
+            file: record.file().map(str::to_owned),
+            line: record.line(),
+            severity,
+        });
+        true
+    }
+
+    /// Pass summary of suppressed repeats of the last record to `summary` to be formatted
+    /// and written, if there are any
+    ///
+    /// The summary has the same metadata and priority as the repeated record.
+    pub(crate) fn flush(&mut self, summary: impl FnOnce(&Record, u8)) {
+        let origin = match self.origin {
+            Some(ref origin) if self.repeated > 0 => origin,
+            _ => return,
+        };
+        let times = if self.repeated == 1 { "time" } else { "times" };
+        summary(&Record::builder()
+            .level(origin.level)
+            .target(&origin.target)
+            .module_path(origin.module_path.as_deref())
+            .file(origin.file.as_deref())
+            .line(origin.line)
+            .args(format_args!("last message repeated {} {}", self.repeated, times))
+            .build(), origin.severity);
+        self.repeated = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, Record};
+
+    use super::Dedup;
+
+    #[test]
+    fn suppress_repeats() {
+        let mut dedup = Dedup::default();
+        let mut written = Vec::new();
+        let mut summaries = Vec::new();
+        let fail = Record::builder().level(Level::Error).target("net").line(Some(7)).args(format_args!("fail")).build();
+        let done = Record::builder().level(Level::Info).target("net").args(format_args!("done")).build();
+        for (record, severity, buf) in [(&fail, 3, &b"<3>fail\n"[..]), (&fail, 3, b"<3>fail\n"), (&fail, 3, b"<3>fail\n"),
+            (&done, 5, b"<5>done\n"), (&done, 5, b"<5>done\n")] {
+            let fresh = dedup.push(record, severity, buf,
+                |record, severity| summaries.push((severity, record.target().to_owned(), record.line(), record.args().to_string())));
+            if fresh {
+                written.push(buf.to_vec());
+            }
+        }
+        dedup.flush(|record, severity| summaries.push((severity, record.target().to_owned(), record.line(), record.args().to_string())));
+        dedup.flush(|_, _| panic!("no repeats left"));
+        assert_eq!(written, [&b"<3>fail\n"[..], b"<5>done\n"]);
+        assert_eq!(summaries, [
+            (3, "net".to_owned(), Some(7), "last message repeated 2 times".to_owned()),
+            (5, "net".to_owned(), None, "last message repeated 1 time".to_owned()),
+        ]);
+    }
+}
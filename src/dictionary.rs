@@ -0,0 +1,86 @@
+//! Structured dictionary fields appended to records
+
+use std::io::{self, Write};
+
+use crate::format::getpid;
+
+/// Dictionary fields written after message text as continuation lines (` KEY=value`),
+/// for backends that carry them
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Dictionary {
+    /// Value of `SYSLOG_IDENTIFIER` field, if it's enabled
+    pub(crate) identifier: Option<String>,
+    /// Emit `SYSLOG_PID` field
+    pub(crate) pid: bool,
+    /// User supplied fields
+    pub(crate) fields: Vec<(String, String)>,
+}
+
+impl Dictionary {
+    /// Check all field names are valid: uppercase letters, digits and underscores,
+    /// not starting with an underscore or a digit
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        for (key, _) in &self.fields {
+            let valid = !key.is_empty()
+                && !key.starts_with(|c: char| c == '_' || c.is_ascii_digit())
+                && key.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+            if !valid {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid dictionary field name {:?}", key)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Write dictionary continuation lines, each preceded by a new line
+    pub(crate) fn write(&self, buf: &mut dyn Write) -> io::Result<()> {
+        if let Some(ref identifier) = self.identifier {
+            write_field(buf, "SYSLOG_IDENTIFIER", identifier)?;
+        }
+        if self.pid {
+            write!(buf, "\n SYSLOG_PID={}", getpid())?;
+        }
+        for (key, value) in &self.fields {
+            write_field(buf, key, value)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_field(buf: &mut dyn Write, key: &str, value: &str) -> io::Result<()> {
+    write!(buf, "\n {}=", key)?;
+    // New lines would end the field, so escape them the way kernel does
+    for (index, part) in value.split('\n').enumerate() {
+        if index > 0 {
+            buf.write_all(b"\\x0a")?;
+        }
+        buf.write_all(part.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Dictionary;
+
+    #[test]
+    fn write_fields() {
+        let dict = Dictionary {
+            identifier: Some("init".into()),
+            pid: false,
+            fields: vec![("UNIT".into(), "a\nb".into())],
+        };
+        let mut buf = Vec::new();
+        dict.write(&mut buf).unwrap();
+        assert_eq!(buf, b"\n SYSLOG_IDENTIFIER=init\n UNIT=a\\x0ab");
+    }
+
+    #[test]
+    fn validate_names() {
+        let dict = |key: &str| Dictionary { fields: vec![(key.into(), String::new())], ..Dictionary::default() };
+        assert!(dict("MY_FIELD2").validate().is_ok());
+        assert!(dict("_PRIVILEGED").validate().is_err());
+        assert!(dict("lower").validate().is_err());
+        assert!(dict("2FAST").validate().is_err());
+        assert!(dict("").validate().is_err());
+    }
+}
@@ -0,0 +1,52 @@
+//! `fern` dispatch output writing records to kernel log
+
+use crate::KernelLog;
+
+/// Kernel logger can be chained to a `fern` dispatch
+///
+/// Level of each chain is controlled by its dispatch, so only some levels can be teed
+/// into kernel log while others go elsewhere:
+///
+/// ```rust,no_run
+/// use log::LevelFilter;
+///
+/// let klog = kernlog::KernelLog::new().unwrap();
+/// fern::Dispatch::new()
+///     .chain(fern::Dispatch::new().level(LevelFilter::Debug).chain(std::io::stderr()))
+///     .chain(fern::Dispatch::new().level(LevelFilter::Warn).chain(klog))
+///     .apply()
+///     .unwrap();
+/// ```
+///
+/// Records are formatted by the logger, unless the dispatch sets its own format,
+/// in which case it only replaces the message.
+impl From<KernelLog> for fern::Output {
+    fn from(klog: KernelLog) -> fern::Output {
+        fern::Output::from(Box::new(klog) as Box<dyn log::Log>)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use log::{Level, LevelFilter, Log, Record};
+
+    use crate::KernelLog;
+
+    #[test]
+    fn chain_to_dispatch() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-fern", std::process::id()));
+        fs::write(&path, "").unwrap();
+        let klog = KernelLog::builder().device(&path).pid(false).build().unwrap();
+        let (_, dispatch) = fern::Dispatch::new()
+            .chain(fern::Dispatch::new().level(LevelFilter::Warn).chain(klog))
+            .into_log();
+        for level in [Level::Info, Level::Warn, Level::Error] {
+            dispatch.log(&Record::builder().level(level).target("test").args(format_args!("{}", level)).build());
+        }
+        dispatch.flush();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<4>test: WARN\n<3>test: ERROR\n");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+//! `slog` drain writing records to kernel log
+
+use std::fmt::{self, Write};
+
+use log::{Level, Log, Metadata, Record};
+use slog::{Drain, Key, OwnedKVList, Serializer, KV};
+
+use crate::KernelLog;
+
+/// Serializer formatting key-value pairs as `key=value`
+struct Pairs(String);
+
+impl Serializer for Pairs {
+    fn emit_arguments(&mut self, key: Key, value: &fmt::Arguments) -> slog::Result {
+        if !self.0.is_empty() {
+            self.0.push(' ');
+        }
+        let _ = write!(self.0, "{}={}", key, value);
+        Ok(())
+    }
+}
+
+fn level(level: slog::Level) -> Level {
+    match level {
+        slog::Level::Critical | slog::Level::Error => Level::Error,
+        slog::Level::Warning => Level::Warn,
+        slog::Level::Info => Level::Info,
+        slog::Level::Debug => Level::Debug,
+        slog::Level::Trace => Level::Trace,
+    }
+}
+
+/// Kernel logger is an `slog` drain, writing records with module path as target
+///
+/// Record key-values are appended to the message after logger ones, as `key=value`.
+/// `Critical` records are logged as `Error` ones.
+///
+/// ```rust,no_run
+/// use slog::{info, o, Drain};
+///
+/// let klog = kernlog::KernelLog::new().unwrap();
+/// let logger = slog::Logger::root(klog.fuse(), o!("service" => "netd"));
+/// info!(logger, "link up"; "iface" => "eth0");
+/// ```
+impl Drain for KernelLog {
+    type Ok = ();
+    type Err = slog::Never;
+
+    fn log(&self, record: &slog::Record<'_>, values: &OwnedKVList) -> Result<(), slog::Never> {
+        let level = level(record.level());
+        if !self.enabled(&Metadata::builder().level(level).target(record.module()).build()) {
+            return Ok(());
+        }
+        let mut pairs = Pairs(String::new());
+        let _ = values.serialize(record, &mut pairs);
+        let _ = record.kv().serialize(record, &mut pairs);
+        let mut text = record.msg().to_string();
+        if !pairs.0.is_empty() {
+            text.push(' ');
+            text.push_str(&pairs.0);
+        }
+        Log::log(self, &Record::builder()
+            .level(level)
+            .target(record.module())
+            .module_path_static(Some(record.module()))
+            .file_static(Some(record.file()))
+            .line(Some(record.line()))
+            .args(format_args!("{}", text))
+            .build());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use log::LevelFilter;
+    use slog::{debug, error, info, o, Drain};
+
+    use crate::KernelLog;
+
+    #[test]
+    fn drain_records() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-slog", std::process::id()));
+        fs::write(&path, "").unwrap();
+        let klog = KernelLog::builder().device(&path).pid(false).level(LevelFilter::Info).build().unwrap();
+        let logger = slog::Logger::root(klog.fuse(), o!("service" => "netd"));
+        info!(logger, "link up"; "iface" => "eth0");
+        error!(logger.new(o!("iface" => "eth1")), "link down");
+        debug!(logger, "filtered out");
+        assert_eq!(fs::read_to_string(&path).unwrap(), concat!(
+            "<5>kernlog::drain::tests: link up service=netd iface=eth0\n",
+            "<3>kernlog::drain::tests: link down iface=eth1 service=netd\n",
+        ));
+        fs::remove_file(path).unwrap();
+    }
+}
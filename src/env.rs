@@ -0,0 +1,50 @@
+//! Logger configuration from environment variables
+
+use std::env::{self, VarError};
+use std::ffi::OsString;
+use std::io;
+
+use log::LevelFilter;
+
+use crate::filter::Filter;
+use crate::level;
+use crate::template::TemplateFormat;
+
+/// Device to log to
+pub(crate) const DEVICE: &str = "KERNLOG_DEVICE";
+/// Level filter
+pub(crate) const LEVEL: &str = "KERNLOG_LEVEL";
+/// Per-target level filter directives
+pub(crate) const FILTER: &str = "KERNLOG_FILTER";
+/// Record format template
+pub(crate) const FORMAT: &str = "KERNLOG_FORMAT";
+
+/// Get device path from environment, `None` if it's not set
+pub(crate) fn device() -> Option<OsString> {
+    env::var_os(DEVICE)
+}
+
+/// Get level filter from environment, `None` if it's not set
+pub(crate) fn level() -> io::Result<Option<LevelFilter>> {
+    level::parse_env_filter(LEVEL, env::var(LEVEL))
+}
+
+/// Get validated filter directives from environment, `None` if it's not set
+pub(crate) fn filter() -> io::Result<Option<String>> {
+    match env::var(FILTER) {
+        Ok(directives) => Filter::parse(&directives).map(|_| Some(directives)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput, format!("invalid {} value: not unicode", FILTER))),
+    }
+}
+
+/// Get validated format template from environment, `None` if it's not set
+pub(crate) fn template() -> io::Result<Option<String>> {
+    match env::var(FORMAT) {
+        Ok(template) => TemplateFormat::new(&template).map(|_| Some(template)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(_)) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput, format!("invalid {} value: not unicode", FORMAT))),
+    }
+}
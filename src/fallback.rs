@@ -0,0 +1,196 @@
+//! Fallback destinations for records the device doesn't accept
+
+use std::io::{self, Write};
+use std::os::unix::net::UnixDatagram;
+use std::sync::OnceLock;
+
+use crate::backend::message_line;
+
+/// Classic syslog socket
+const SYSLOG_SOCKET: &str = "/dev/log";
+/// systemd-journald native protocol socket
+#[cfg(feature = "journald")]
+pub(crate) const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// User-level messages syslog facility, used instead of kernel one for syslog
+pub(crate) const LOG_USER: u32 = 1 << 3;
+
+/// Where to write records when the device can't be opened or written to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Fallback {
+    /// Drop records (default)
+    #[default]
+    None,
+    /// Write records to stderr without dictionary fields, including `<priority>` prefix,
+    /// which is understood by systemd and other service managers
+    Stderr,
+    /// Send records to `/dev/log` syslog socket as `<priority>message` datagrams
+    ///
+    /// Dictionary fields are not sent, and kernel facility is replaced with user one,
+    /// as syslog daemons don't accept kernel messages from userspace.
+    Syslog,
+    /// Send records to systemd-journald native socket with `MESSAGE`, `PRIORITY`,
+    /// `SYSLOG_FACILITY`, `SYSLOG_IDENTIFIER`, `SYSLOG_PID` and dictionary fields
+    #[cfg(feature = "journald")]
+    Journald,
+}
+
+/// Fallback destination with its open resources
+#[derive(Debug, Default)]
+pub(crate) struct FallbackWriter {
+    fallback: Fallback,
+    /// Syslog or journald socket, created on first use
+    socket: OnceLock<UnixDatagram>,
+}
+
+impl FallbackWriter {
+    pub(crate) fn new(fallback: Fallback) -> FallbackWriter {
+        FallbackWriter { fallback, socket: OnceLock::new() }
+    }
+
+    /// Write formatted record to fallback destination
+    pub(crate) fn write(&self, record: &[u8]) -> io::Result<()> {
+        match self.fallback {
+            Fallback::None => Ok(()),
+            Fallback::Stderr => io::stderr().lock().write_all(message_line(record)),
+            Fallback::Syslog => {
+                let message = syslog_message(record);
+                self.socket()?.send_to(&message, SYSLOG_SOCKET).map(drop)
+            }
+            #[cfg(feature = "journald")]
+            Fallback::Journald => {
+                let entry = journald_entry(record);
+                self.socket()?.send_to(&entry, JOURNALD_SOCKET).map(drop)
+            }
+        }
+    }
+
+    /// Write formatted record to fallback destination, returning if it was accepted
+    pub(crate) fn accept(&self, record: &[u8]) -> bool {
+        self.fallback != Fallback::None && self.write(record).is_ok()
+    }
+
+    fn socket(&self) -> io::Result<&UnixDatagram> {
+        if let Some(socket) = self.socket.get() {
+            return Ok(socket);
+        }
+        let socket = UnixDatagram::unbound()?;
+        Ok(self.socket.get_or_init(|| socket))
+    }
+}
+
+/// Backend sending records to systemd-journald native socket, picked by [`init_auto()`](crate::init_auto)
+#[cfg(feature = "journald")]
+pub(crate) struct Journald {
+    socket: UnixDatagram,
+}
+
+#[cfg(feature = "journald")]
+impl Journald {
+    pub(crate) fn new() -> io::Result<Journald> {
+        Ok(Journald { socket: UnixDatagram::unbound()? })
+    }
+}
+
+#[cfg(feature = "journald")]
+impl crate::KmsgBackend for Journald {
+    fn open(&mut self) -> io::Result<()> {
+        self.socket = UnixDatagram::unbound()?;
+        Ok(())
+    }
+
+    fn write_record(&self, record: &[u8]) -> io::Result<()> {
+        self.socket.send_to(&journald_entry(record), JOURNALD_SOCKET).map(drop)
+    }
+}
+
+/// Split `<priority>` prefix off record line
+pub(crate) fn parse_priority(line: &str) -> Option<(u32, &str)> {
+    let (priority, message) = line.strip_prefix('<')?.split_once('>')?;
+    Some((priority.parse().ok()?, message))
+}
+
+/// Convert kmsg record to syslog message: drop dictionary and trailing new line,
+/// replace kernel facility with user one
+fn syslog_message(record: &[u8]) -> Vec<u8> {
+    let line = record.split(|&byte| byte == b'\n').next().unwrap_or_default();
+    let line = String::from_utf8_lossy(line);
+    match parse_priority(&line) {
+        Some((priority, message)) if priority >> 3 == 0 => format!("<{}>{}", priority | LOG_USER, message).into_bytes(),
+        _ => line.into_owned().into_bytes(),
+    }
+}
+
+/// Convert kmsg record to journald native protocol entry, with dictionary lines as fields
+#[cfg(feature = "journald")]
+fn journald_entry(record: &[u8]) -> Vec<u8> {
+    let record = String::from_utf8_lossy(record);
+    let mut lines = record.trim_end_matches('\n').split('\n');
+    let line = lines.next().unwrap_or_default();
+    let (priority, message) = parse_priority(line).unwrap_or((LOG_USER | 6, line));
+    let facility = match priority >> 3 {
+        0 => LOG_USER >> 3,
+        facility => facility,
+    };
+
+    let mut entry = Vec::new();
+    journald_field(&mut entry, "MESSAGE", message);
+    journald_field(&mut entry, "PRIORITY", &(priority & 7).to_string());
+    journald_field(&mut entry, "SYSLOG_FACILITY", &facility.to_string());
+    let mut identifier = false;
+    let mut pid = false;
+    for field in lines {
+        if let Some((key, value)) = field.strip_prefix(' ').and_then(|field| field.split_once('=')) {
+            identifier |= key == "SYSLOG_IDENTIFIER";
+            pid |= key == "SYSLOG_PID";
+            journald_field(&mut entry, key, value);
+        }
+    }
+    if !identifier {
+        journald_field(&mut entry, "SYSLOG_IDENTIFIER", &crate::format::program_name());
+    }
+    if !pid {
+        journald_field(&mut entry, "SYSLOG_PID", &crate::format::getpid().to_string());
+    }
+    entry
+}
+
+/// Append field to journald entry, in binary form if value has new lines
+#[cfg(feature = "journald")]
+fn journald_field(entry: &mut Vec<u8>, key: &str, value: &str) {
+    entry.extend_from_slice(key.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::syslog_message;
+    #[cfg(feature = "journald")]
+    use super::journald_entry;
+    #[cfg(feature = "journald")]
+    use crate::format::getpid;
+
+    #[test]
+    fn convert_to_syslog() {
+        assert_eq!(syslog_message(b"<6>init[1]: mounted\n"), b"<14>init[1]: mounted");
+        assert_eq!(syslog_message(b"<30>init[1]: mounted\n SYSLOG_IDENTIFIER=init\n"), b"<30>init[1]: mounted");
+        assert_eq!(syslog_message(b"no priority\n"), b"no priority");
+    }
+
+    #[cfg(feature = "journald")]
+    #[test]
+    fn convert_to_journald() {
+        let entry = journald_entry(b"<30>init: mounted\n SYSLOG_IDENTIFIER=init\n");
+        let expected = format!("MESSAGE=init: mounted\nPRIORITY=6\nSYSLOG_FACILITY=3\nSYSLOG_IDENTIFIER=init\nSYSLOG_PID={}\n", getpid());
+        assert_eq!(String::from_utf8(entry).unwrap(), expected);
+    }
+}
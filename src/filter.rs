@@ -0,0 +1,259 @@
+//! Per-target level filtering
+
+use std::io;
+
+use log::{Level, LevelFilter};
+#[cfg(feature = "regex")]
+use regex::Regex;
+
+use crate::level;
+use crate::RateLimit;
+
+/// Level filter directive for a target and its submodules
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Directive {
+    name: String,
+    level: LevelFilter,
+}
+
+/// Level remapping for records from a target and its submodules
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Remap {
+    name: String,
+    from: Level,
+    to: Level,
+}
+
+/// Per-target level filter, parsed from `RUST_LOG` style directives
+/// like `info,my_crate::net=trace,hyper=warn`
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Filter {
+    /// Level for targets not matching any directive, if set
+    pub(crate) default: Option<LevelFilter>,
+    /// Directives sorted by name length, longest first
+    directives: Vec<Directive>,
+    /// Regex directives in the order given
+    #[cfg(feature = "regex")]
+    patterns: Vec<(Regex, LevelFilter)>,
+    /// `env_logger` filter replacing levels, if set
+    #[cfg(feature = "env_filter")]
+    pub(crate) env: Option<env_filter::Filter>,
+    /// Targets to pass, all if empty
+    pub(crate) allow: Vec<String>,
+    /// Targets to drop regardless of level
+    pub(crate) deny: Vec<String>,
+    /// Level remappings sorted by name length, longest first
+    remaps: Vec<Remap>,
+    /// Rate limits of targets given as `target=5/s` directives
+    pub(crate) rate_limits: Vec<(String, RateLimit)>,
+}
+
+impl Filter {
+    /// Parse comma separated directives
+    ///
+    /// Each directive is either a level (`info`), which becomes a default level,
+    /// a target with level (`hyper=warn`) or a target alone (`my_crate`), which enables all levels
+    /// for it. Levels may be also given as printk priorities (`0` to `7`).
+    ///
+    /// With `regex` feature, target may be a regular expression between slashes
+    /// (`/^app::(net|db)/=trace`), matched anywhere in record target.
+    ///
+    /// A target with a rate limit (`udev_helper=5/s`) sets rate limit for the target
+    /// instead of level, see [`RateLimit`].
+    pub(crate) fn parse(spec: &str) -> io::Result<Filter> {
+        let mut filter = Filter::default();
+        for directive in spec.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+            let (name, level) = match directive.rsplit_once('=') {
+                Some((name, value)) if value.contains('/') => {
+                    let name = name.trim();
+                    if name.is_empty() || name.starts_with('/') {
+                        return Err(invalid(directive));
+                    }
+                    filter.rate_limits.push((name.to_owned(), value.parse()?));
+                    continue;
+                }
+                Some((name, value)) => {
+                    let level = level::parse_filter(value).ok_or_else(|| invalid(directive))?;
+                    let name = name.trim();
+                    if name.is_empty() {
+                        return Err(invalid(directive));
+                    }
+                    (name, level)
+                }
+                None => match level::parse_filter(directive) {
+                    Some(level) => {
+                        filter.default = Some(level);
+                        continue;
+                    }
+                    None => (directive, LevelFilter::Trace),
+                },
+            };
+            match name.strip_prefix('/').and_then(|name| name.strip_suffix('/')) {
+                Some(pattern) => filter.push_pattern(pattern, level)?,
+                None => filter.push(name, level),
+            }
+        }
+        filter.directives.sort_by_key(|directive| std::cmp::Reverse(directive.name.len()));
+        Ok(filter)
+    }
+
+    /// Add directive, replacing earlier one for the same target
+    fn push(&mut self, name: &str, level: LevelFilter) {
+        match self.directives.iter_mut().find(|directive| directive.name == name) {
+            Some(directive) => directive.level = level,
+            None => self.directives.push(Directive { name: name.to_owned(), level }),
+        }
+    }
+
+    #[cfg(feature = "regex")]
+    fn push_pattern(&mut self, pattern: &str, level: LevelFilter) -> io::Result<()> {
+        let regex = Regex::new(pattern).map_err(|err| io::Error::new(
+            io::ErrorKind::InvalidInput, format!("invalid filter regex {:?}: {}", pattern, err)))?;
+        self.patterns.push((regex, level));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "regex"))]
+    fn push_pattern(&mut self, pattern: &str, _level: LevelFilter) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput, format!("filter regex /{}/ requires `regex` feature", pattern)))
+    }
+
+    /// Get level filter of the most specific directive matching target, if any
+    ///
+    /// Regex directives are only consulted if no plain directive matches, the first matching one wins.
+    pub(crate) fn level(&self, target: &str) -> Option<LevelFilter> {
+        let level = self.directives
+            .iter()
+            .find(|directive| matches(&directive.name, target))
+            .map(|directive| directive.level);
+        #[cfg(feature = "regex")]
+        let level = level.or_else(|| {
+            self.patterns.iter().find(|(regex, _)| regex.is_match(target)).map(|&(_, level)| level)
+        });
+        level
+    }
+
+    /// Add level remapping for target, replacing earlier one for the same target and level
+    pub(crate) fn add_remap(&mut self, name: &str, from: Level, to: Level) {
+        match self.remaps.iter_mut().find(|remap| remap.name == name && remap.from == from) {
+            Some(remap) => remap.to = to,
+            None => self.remaps.push(Remap { name: name.to_owned(), from, to }),
+        }
+        self.remaps.sort_by_key(|remap| std::cmp::Reverse(remap.name.len()));
+    }
+
+    /// Get record level after applying the most specific remapping matching target and level
+    pub(crate) fn remap(&self, target: &str, level: Level) -> Level {
+        self.remaps
+            .iter()
+            .find(|remap| remap.from == level && matches(&remap.name, target))
+            .map_or(level, |remap| remap.to)
+    }
+
+    /// Check target is allowed and not denied, along with its submodules
+    pub(crate) fn allows(&self, target: &str) -> bool {
+        !self.deny.iter().any(|name| matches(name, target))
+            && (self.allow.is_empty() || self.allow.iter().any(|name| matches(name, target)))
+    }
+
+    /// Get the most verbose level of all directives
+    pub(crate) fn max_level(&self) -> LevelFilter {
+        let level = self.directives.iter().map(|directive| directive.level).max().unwrap_or(LevelFilter::Off);
+        #[cfg(feature = "regex")]
+        let level = self.patterns.iter().map(|&(_, level)| level).fold(level, Ord::max);
+        level
+    }
+}
+
+/// Check target is directive's module or any of its submodules
+pub(crate) fn matches(name: &str, target: &str) -> bool {
+    match target.strip_prefix(name) {
+        Some(rest) => rest.is_empty() || rest.starts_with("::"),
+        None => false,
+    }
+}
+
+fn invalid(directive: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("invalid filter directive {:?}", directive))
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, LevelFilter};
+    use super::Filter;
+    use crate::RateLimit;
+
+    #[test]
+    fn parse_and_match() {
+        let filter = Filter::parse("info, my_crate=debug,my_crate::net=trace,hyper=warn").unwrap();
+        assert_eq!(filter.default, Some(LevelFilter::Info));
+        assert_eq!(filter.level("my_crate::net::tcp"), Some(LevelFilter::Trace));
+        assert_eq!(filter.level("my_crate::network"), Some(LevelFilter::Debug));
+        assert_eq!(filter.level("hyper"), Some(LevelFilter::Warn));
+        assert_eq!(filter.level("hyperlocal"), None);
+        assert_eq!(filter.max_level(), LevelFilter::Trace);
+
+        let filter = Filter::parse("noisy=off,mine").unwrap();
+        assert_eq!(filter.default, None);
+        assert_eq!(filter.level("noisy"), Some(LevelFilter::Off));
+        assert_eq!(filter.level("mine::sub"), Some(LevelFilter::Trace));
+
+        assert!(Filter::parse("hyper=loud").is_err());
+        assert!(Filter::parse("=warn").is_err());
+    }
+
+    #[test]
+    fn parse_rate_limits() {
+        let filter = Filter::parse("info,udev_helper=5/s,udev_helper::rules=debug").unwrap();
+        assert_eq!(filter.rate_limits, [("udev_helper".to_owned(), RateLimit::per_second(5))]);
+        assert_eq!(filter.level("udev_helper"), None);
+        assert_eq!(filter.level("udev_helper::rules"), Some(LevelFilter::Debug));
+        assert!(Filter::parse("udev_helper=5/d").is_err());
+        assert!(Filter::parse("/udev/=5/s").is_err());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn parse_regex() {
+        let filter = Filter::parse("warn,/^app::(net|db)/=debug,app::net::tcp=error,/=off").unwrap();
+        assert_eq!(filter.level("app::db::pool"), Some(LevelFilter::Debug));
+        assert_eq!(filter.level("app::net::tcp"), Some(LevelFilter::Error));
+        assert_eq!(filter.level("myapp::db"), None);
+        assert_eq!(filter.level("/"), Some(LevelFilter::Off));
+        assert!(Filter::parse("/app::(/=info").is_err());
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[test]
+    fn parse_regex_unsupported() {
+        assert!(Filter::parse("/^app/=info").is_err());
+    }
+
+    #[test]
+    fn allow_and_deny() {
+        let mut filter = Filter::default();
+        assert!(filter.allows("anything"));
+
+        filter.allow = vec!["myinit".to_owned()];
+        filter.deny = vec!["myinit::udev".to_owned()];
+        assert!(filter.allows("myinit"));
+        assert!(filter.allows("myinit::mount"));
+        assert!(!filter.allows("myinit::udev::rules"));
+        assert!(!filter.allows("myinitramfs"));
+        assert!(!filter.allows("hyper"));
+    }
+
+    #[test]
+    fn remap_levels() {
+        let mut filter = Filter::default();
+        filter.add_remap("reqwest", Level::Info, Level::Debug);
+        filter.add_remap("reqwest::connect", Level::Info, Level::Trace);
+        filter.add_remap("reqwest", Level::Warn, Level::Info);
+        assert_eq!(filter.remap("reqwest::async_impl", Level::Info), Level::Debug);
+        assert_eq!(filter.remap("reqwest::connect", Level::Info), Level::Trace);
+        assert_eq!(filter.remap("reqwest::connect", Level::Warn), Level::Info);
+        assert_eq!(filter.remap("reqwest", Level::Error), Level::Error);
+        assert_eq!(filter.remap("hyper", Level::Info), Level::Info);
+    }
+}
@@ -0,0 +1,266 @@
+//! Log record formatting
+
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+
+use log::Record;
+
+/// Kernel log record formatter
+///
+/// Formatter writes everything following the `<priority>` prefix of a kmsg record,
+/// without trailing new line: both are added by [`KernelLog`](crate::KernelLog) itself.
+pub trait KmsgFormat: Send + Sync {
+    /// Format log record into buffer
+    fn format(&self, buf: &mut dyn Write, record: &Record) -> io::Result<()>;
+}
+
+impl<F> KmsgFormat for F
+where
+    F: Fn(&mut dyn Write, &Record) -> io::Result<()> + Send + Sync,
+{
+    fn format(&self, buf: &mut dyn Write, record: &Record) -> io::Result<()> {
+        self(buf, record)
+    }
+}
+
+/// Record origin reported by the default format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Origin {
+    /// Record target, which may be overridden at call site (default)
+    #[default]
+    Target,
+    /// Module path record was emitted from, target is used if it's unknown
+    ModulePath,
+    /// Both target and module path as `target@module`, if they are different
+    Both,
+}
+
+/// Default record format: `target[pid]: message`
+///
+/// With tag, thread id, thread name and source location enabled it becomes
+/// `tag: target[pid:tid] (name): message at file:line`.
+#[derive(Debug, Clone)]
+pub struct DefaultFormat {
+    pub(crate) tag: Option<String>,
+    origin: Origin,
+    pid: bool,
+    tid: bool,
+    thread_name: bool,
+    location: bool,
+    /// Process id and its `[pid` rendering, so it's not formatted for every record
+    rendered_pid: (libc::pid_t, String),
+}
+
+impl DefaultFormat {
+    /// Create default formatter
+    pub fn new() -> DefaultFormat {
+        let pid = getpid();
+        DefaultFormat {
+            tag: None,
+            origin: Origin::Target,
+            pid: true,
+            tid: false,
+            thread_name: false,
+            location: false,
+            rendered_pid: (pid, format!("[{}", pid)),
+        }
+    }
+
+    /// Prefix records with a tag, like syslog identifier
+    pub fn tag(mut self, tag: impl Into<String>) -> DefaultFormat {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Prefix records with program name as a tag, see [`DefaultFormat::tag()`]
+    ///
+    /// Program name is taken from `/proc/self/comm`, or from `argv[0]` if it's unavailable.
+    pub fn program_tag(self) -> DefaultFormat {
+        self.tag(program_name())
+    }
+
+    /// Choose what to report as record origin, target by default
+    pub fn origin(mut self, origin: Origin) -> DefaultFormat {
+        self.origin = origin;
+        self
+    }
+
+    /// Report process id in records, enabled by default
+    pub fn pid(mut self, pid: bool) -> DefaultFormat {
+        self.pid = pid;
+        self
+    }
+
+    /// Report thread id (as returned by `gettid()`) in records, disabled by default
+    pub fn tid(mut self, tid: bool) -> DefaultFormat {
+        self.tid = tid;
+        self
+    }
+
+    /// Report current thread name in records, disabled by default
+    ///
+    /// Nothing is reported for unnamed threads.
+    pub fn thread_name(mut self, thread_name: bool) -> DefaultFormat {
+        self.thread_name = thread_name;
+        self
+    }
+
+    /// Append source location (`file:line`) to messages, disabled by default
+    ///
+    /// Module path is used instead if file name is unknown.
+    pub fn location(mut self, location: bool) -> DefaultFormat {
+        self.location = location;
+        self
+    }
+}
+
+impl Default for DefaultFormat {
+    fn default() -> DefaultFormat {
+        DefaultFormat::new()
+    }
+}
+
+impl KmsgFormat for DefaultFormat {
+    fn format(&self, buf: &mut dyn Write, record: &Record) -> io::Result<()> {
+        if let Some(ref tag) = self.tag {
+            buf.write_all(tag.as_bytes())?;
+            buf.write_all(b": ")?;
+        }
+        match (self.origin, record.module_path()) {
+            (Origin::ModulePath, Some(module)) => write!(buf, "{}", module)?,
+            (Origin::Both, Some(module)) if module != record.target() => write!(buf, "{}@{}", record.target(), module)?,
+            _ => write!(buf, "{}", record.target())?,
+        }
+        if self.pid {
+            // Process id changes in forked children
+            match (getpid(), &self.rendered_pid) {
+                (pid, &(rendered, ref text)) if pid == rendered => buf.write_all(text.as_bytes())?,
+                (pid, _) => write!(buf, "[{}", pid)?,
+            }
+        }
+        match (self.pid, self.tid) {
+            (true, true) => write!(buf, ":{}]", gettid())?,
+            (true, false) => buf.write_all(b"]")?,
+            (false, true) => write!(buf, "[{}]", gettid())?,
+            (false, false) => (),
+        }
+        if self.thread_name {
+            if let Some(name) = std::thread::current().name() {
+                write!(buf, " ({})", name)?;
+            }
+        }
+        write!(buf, ": {}", record.args())?;
+        if self.location {
+            match (record.file(), record.line(), record.module_path()) {
+                (Some(file), Some(line), _) => write!(buf, " at {}:{}", file, line)?,
+                (Some(file), None, _) => write!(buf, " at {}", file)?,
+                (None, _, Some(module)) => write!(buf, " at {}", module)?,
+                (None, _, None) => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Get current program name
+pub(crate) fn program_name() -> String {
+    if let Ok(comm) = fs::read_to_string("/proc/self/comm") {
+        let comm = comm.trim_end();
+        if !comm.is_empty() {
+            return comm.to_owned();
+        }
+    }
+    env::args_os()
+        .next()
+        .as_ref()
+        .map(Path::new)
+        .and_then(Path::file_name)
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+pub(crate) fn getpid() -> libc::pid_t {
+    unsafe { libc::getpid() }
+}
+
+pub(crate) fn gettid() -> libc::pid_t {
+    unsafe { libc::syscall(libc::SYS_gettid) as libc::pid_t }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, Record};
+    use super::{DefaultFormat, KmsgFormat, Origin, getpid, gettid};
+
+    fn format_record(format: &DefaultFormat) -> String {
+        let mut buf = Vec::new();
+        let record = Record::builder().level(Level::Info).target("test").args(format_args!("msg")).build();
+        format.format(&mut buf, &record).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn format_ids() {
+        assert_eq!(format_record(&DefaultFormat::new()), format!("test[{}]: msg", getpid()));
+        assert_eq!(format_record(&DefaultFormat::new().pid(false)), "test: msg");
+        assert_eq!(format_record(&DefaultFormat::new().tid(true)), format!("test[{}:{}]: msg", getpid(), gettid()));
+        assert_eq!(format_record(&DefaultFormat::new().pid(false).tid(true)), format!("test[{}]: msg", gettid()));
+    }
+
+    #[test]
+    fn format_tag() {
+        assert_eq!(format_record(&DefaultFormat::new().pid(false).tag("init")), "init: test: msg");
+
+        let program = format_record(&DefaultFormat::new().pid(false).program_tag());
+        assert!(program.starts_with("kernlog"));
+        assert!(program.ends_with(": test: msg"));
+    }
+
+    #[test]
+    fn format_origin() {
+        let format = |origin, module| {
+            let mut buf = Vec::new();
+            let record = Record::builder().target("net").module_path(module).args(format_args!("msg")).build();
+            DefaultFormat::new().pid(false).origin(origin).format(&mut buf, &record).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+
+        assert_eq!(format(Origin::Target, Some("app::net")), "net: msg");
+        assert_eq!(format(Origin::ModulePath, Some("app::net")), "app::net: msg");
+        assert_eq!(format(Origin::ModulePath, None), "net: msg");
+        assert_eq!(format(Origin::Both, Some("app::net")), "net@app::net: msg");
+        assert_eq!(format(Origin::Both, Some("net")), "net: msg");
+    }
+
+    #[test]
+    fn format_location() {
+        let format = DefaultFormat::new().pid(false).location(true);
+        let mut buf = Vec::new();
+        let record = Record::builder()
+            .target("test")
+            .file(Some("src/main.rs"))
+            .line(Some(42))
+            .args(format_args!("msg"))
+            .build();
+        format.format(&mut buf, &record).unwrap();
+        assert_eq!(buf, b"test: msg at src/main.rs:42");
+        assert_eq!(format_record(&format), "test: msg");
+    }
+
+    #[test]
+    fn format_thread_name() {
+        let format = DefaultFormat::new().pid(false).thread_name(true);
+        let named = std::thread::Builder::new().name("worker".into()).spawn({
+            let format = format.clone();
+            move || format_record(&format)
+        }).unwrap().join().unwrap();
+        let unnamed = std::thread::spawn(move || format_record(&format)).join().unwrap();
+
+        assert_eq!(named, "test (worker): msg");
+        assert_eq!(unnamed, "test: msg");
+    }
+}
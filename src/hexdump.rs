@@ -0,0 +1,73 @@
+//! Hexdump of binary data
+
+use std::fmt::Write;
+
+use log::{Level, Record};
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Log binary data as a hexdump, one record per 16 bytes
+///
+/// Each record message looks like `00000010  2e 73 68 73 74 72 74 61  62 00 2e 69 6e 74 65 72  |.shstrtab..inter|`,
+/// similar to kernel's `print_hex_dump()`, so records remain well below record size limit.
+/// Label is used as record target. Records are emitted through the installed logger,
+/// see [`init()`](crate::init).
+///
+/// ```rust,no_run
+/// kernlog::init().unwrap();
+/// kernlog::hexdump(log::Level::Debug, "fw", &[0x7f, b'E', b'L', b'F']);
+/// ```
+pub fn hexdump(level: Level, label: &str, data: &[u8]) {
+    let logger = log::logger();
+    if level > log::max_level() || !logger.enabled(&log::Metadata::builder().level(level).target(label).build()) {
+        return;
+    }
+
+    for (index, chunk) in data.chunks(BYTES_PER_LINE).enumerate() {
+        let line = format_line(index * BYTES_PER_LINE, chunk);
+        logger.log(&Record::builder()
+            .level(level)
+            .target(label)
+            .module_path_static(Some(module_path!()))
+            .args(format_args!("{}", line))
+            .build());
+    }
+}
+
+fn format_line(offset: usize, chunk: &[u8]) -> String {
+    let mut line = String::with_capacity(80);
+    let _ = write!(line, "{:08x} ", offset);
+    for index in 0..BYTES_PER_LINE {
+        if index % 8 == 0 {
+            line.push(' ');
+        }
+        match chunk.get(index) {
+            Some(byte) => {
+                let _ = write!(line, "{:02x} ", byte);
+            }
+            None => line.push_str("   "),
+        }
+    }
+    line.push(' ');
+    line.push('|');
+    line.extend(chunk.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }));
+    line.push('|');
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_line;
+
+    #[test]
+    fn format_lines() {
+        assert_eq!(
+            format_line(0x10, b".shstrtab\0.inter"),
+            "00000010  2e 73 68 73 74 72 74 61  62 00 2e 69 6e 74 65 72  |.shstrtab..inter|"
+        );
+        assert_eq!(
+            format_line(0, b"\x7fELF"),
+            "00000000  7f 45 4c 46                                       |.ELF|"
+        );
+    }
+}
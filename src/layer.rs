@@ -0,0 +1,171 @@
+//! `tracing` subscriber layer writing events to kernel log
+
+use std::fmt::{self, Write};
+
+use log::{Level, Log, Metadata, Record};
+use tracing_core::field::{Field, Visit};
+use tracing_core::span::{Attributes, Id, Record as Values};
+use tracing_core::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::KernelLog;
+
+/// `tracing` layer writing events to kernel log through [`KernelLog`]
+///
+/// Events are logged as records of the same target, level and location, so logger's filters,
+/// format and rate limits apply to them. Messages are prefixed with names and fields of spans
+/// the event is in, from the outermost one, and followed by event fields:
+/// `mount{dev="sda1"}: slow device retries=3`.
+///
+/// ```rust,no_run
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let klog = kernlog::KernelLog::new().unwrap();
+/// let subscriber = tracing_subscriber::registry().with(kernlog::KernelLayer::new(klog));
+/// tracing::subscriber::set_global_default(subscriber).unwrap();
+/// ```
+pub struct KernelLayer {
+    klog: KernelLog,
+}
+
+impl KernelLayer {
+    /// Create layer writing to kernel logger
+    pub fn new(klog: KernelLog) -> KernelLayer {
+        KernelLayer { klog }
+    }
+}
+
+/// Formatted span fields, stored in span extensions
+struct SpanFields(String);
+
+/// Visitor formatting fields as `name=value`, with the `message` field kept apart
+#[derive(Default)]
+struct Fields {
+    message: String,
+    fields: String,
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            if !self.fields.is_empty() {
+                self.fields.push(' ');
+            }
+            let _ = write!(self.fields, "{}={:?}", field.name(), value);
+        }
+    }
+}
+
+fn level(level: tracing_core::Level) -> Level {
+    match level {
+        tracing_core::Level::ERROR => Level::Error,
+        tracing_core::Level::WARN => Level::Warn,
+        tracing_core::Level::INFO => Level::Info,
+        tracing_core::Level::DEBUG => Level::Debug,
+        tracing_core::Level::TRACE => Level::Trace,
+    }
+}
+
+impl<S> Layer<S> for KernelLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields.fields));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Values<'_>, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut extensions = span.extensions_mut();
+        let mut fields = Fields::default();
+        if let Some(SpanFields(existing)) = extensions.get_mut::<SpanFields>() {
+            fields.fields = std::mem::take(existing);
+        }
+        values.record(&mut fields);
+        extensions.replace(SpanFields(fields.fields));
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let meta = event.metadata();
+        let level = level(*meta.level());
+        if !self.klog.enabled(&Metadata::builder().level(level).target(meta.target()).build()) {
+            return;
+        }
+
+        let mut text = String::new();
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                text.push_str(span.name());
+                if let Some(SpanFields(fields)) = span.extensions().get::<SpanFields>() {
+                    if !fields.is_empty() {
+                        let _ = write!(text, "{{{}}}", fields);
+                    }
+                }
+                text.push(':');
+            }
+            text.push(' ');
+        }
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        text.push_str(&fields.message);
+        if !fields.fields.is_empty() {
+            if !fields.message.is_empty() {
+                text.push(' ');
+            }
+            text.push_str(&fields.fields);
+        }
+
+        self.klog.log(&Record::builder()
+            .level(level)
+            .target(meta.target())
+            .module_path(meta.module_path())
+            .file(meta.file())
+            .line(meta.line())
+            .args(format_args!("{}", text))
+            .build());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use log::LevelFilter;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::KernelLayer;
+    use crate::KernelLog;
+
+    #[test]
+    fn log_tracing_events() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-tracing", std::process::id()));
+        fs::write(&path, "").unwrap();
+        let klog = KernelLog::builder().device(&path).pid(false).level(LevelFilter::Info).build().unwrap();
+        let subscriber = tracing_subscriber::registry().with(KernelLayer::new(klog));
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(target: "test", "started");
+            let span = tracing::info_span!("mount", dev = "sda1", done = tracing::field::Empty);
+            let _entered = span.enter();
+            tracing::warn!(target: "test", retries = 3, "slow device");
+            span.record("done", true);
+            tracing::info_span!("fsck").in_scope(|| tracing::error!(target: "test", errors = 2));
+            tracing::debug!(target: "test", "filtered out");
+        });
+        assert_eq!(fs::read_to_string(&path).unwrap(), concat!(
+            "<5>test: started\n",
+            "<4>test: mount{dev=\"sda1\"}: slow device retries=3\n",
+            "<3>test: mount{dev=\"sda1\" done=true}:fsck: errors=2\n",
+        ));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+//! Mapping between `log` levels and kernel printk priorities
+
+// Only a part of level mapping is needed by the stand-in logger on other platforms
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+use std::env::VarError;
+use std::io;
+
+use log::{Level, LevelFilter, Record};
+
+/// Syslog facility, encoded into record priority together with severity
+///
+/// Note kernel replaces `Kern` facility with `User` for records written by userspace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Facility {
+    /// Kernel messages (default)
+    #[default]
+    Kern = 0,
+    /// User-level messages
+    User = 1,
+    /// Mail system
+    Mail = 2,
+    /// System daemons
+    Daemon = 3,
+    /// Security/authorization messages
+    Auth = 4,
+    /// Messages generated internally by syslogd
+    Syslog = 5,
+    /// Line printer subsystem
+    Lpr = 6,
+    /// Network news subsystem
+    News = 7,
+    /// UUCP subsystem
+    Uucp = 8,
+    /// Clock daemon
+    Cron = 9,
+    /// Security/authorization private messages
+    Authpriv = 10,
+    /// FTP daemon
+    Ftp = 11,
+    /// Local use 0
+    Local0 = 16,
+    /// Local use 1
+    Local1 = 17,
+    /// Local use 2
+    Local2 = 18,
+    /// Local use 3
+    Local3 = 19,
+    /// Local use 4
+    Local4 = 20,
+    /// Local use 5
+    Local5 = 21,
+    /// Local use 6
+    Local6 = 22,
+    /// Local use 7
+    Local7 = 23,
+}
+
+/// Kernel printk severity
+///
+/// `log` levels cover only five lower severities, see [`Builder::severity_suffixes()`](crate::Builder::severity_suffixes)
+/// for a way to emit records with the higher ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// System is unusable, `KERN_EMERG`
+    Emerg = 0,
+    /// Action must be taken immediately, `KERN_ALERT`
+    Alert = 1,
+    /// Critical conditions, `KERN_CRIT`
+    Crit = 2,
+    /// Error conditions, `KERN_ERR`
+    Err = 3,
+    /// Warning conditions, `KERN_WARNING`
+    Warning = 4,
+    /// Normal but significant condition, `KERN_NOTICE`
+    Notice = 5,
+    /// Informational, `KERN_INFO`
+    Info = 6,
+    /// Debug-level messages, `KERN_DEBUG`
+    Debug = 7,
+}
+
+/// Get severity of printk priority, only its lower three bits are used
+pub(crate) fn severity(priority: u8) -> Severity {
+    match priority & 7 {
+        0 => Severity::Emerg,
+        1 => Severity::Alert,
+        2 => Severity::Crit,
+        3 => Severity::Err,
+        4 => Severity::Warning,
+        5 => Severity::Notice,
+        6 => Severity::Info,
+        _ => Severity::Debug,
+    }
+}
+
+/// Strip `::emerg`, `::alert` or `::crit` suffix from target,
+/// returning the rest of target and corresponding severity
+pub(crate) fn strip_severity_suffix(target: &str) -> Option<(&str, Severity)> {
+    let (rest, suffix) = target.rsplit_once("::")?;
+    let severity = match suffix {
+        "emerg" => Severity::Emerg,
+        "alert" => Severity::Alert,
+        "crit" => Severity::Crit,
+        _ => return None,
+    };
+    Some((rest, severity))
+}
+
+/// Compose record priority value from facility and severity
+pub(crate) fn compose(facility: Facility, severity: u8) -> u8 {
+    ((facility as u8) << 3) | severity
+}
+
+/// Pre-rendered `<priority>tag: ` record prefixes, indexed by severity
+#[derive(Debug)]
+pub(crate) struct Prefixes {
+    rendered: [String; 8],
+    /// Length of `tag: ` following `<priority>`
+    tag_len: usize,
+}
+
+impl Prefixes {
+    /// Render prefixes for all severities of facility, with tag of the default format if it's set
+    pub(crate) fn new(facility: Facility, tag: Option<&str>) -> Prefixes {
+        let tag = tag.map_or_else(String::new, |tag| format!("{}: ", tag));
+        Prefixes {
+            rendered: std::array::from_fn(|severity| format!("<{}>{}", compose(facility, severity as u8), tag)),
+            tag_len: tag.len(),
+        }
+    }
+
+    /// Get prefix of formatted record with severity
+    pub(crate) fn record(&self, severity: u8) -> &[u8] {
+        self.rendered[usize::from(severity & 7)].as_bytes()
+    }
+
+    /// Get `<priority>` prefix alone, for raw records with severity
+    pub(crate) fn priority(&self, severity: u8) -> &[u8] {
+        let prefix = self.record(severity);
+        &prefix[..prefix.len() - self.tag_len]
+    }
+}
+
+/// Get printk priority for log level
+pub(crate) fn priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 5,
+        Level::Debug => 6,
+        Level::Trace => 7,
+    }
+}
+
+/// Get log level for printk priority, the reverse of [`priority()`]
+///
+/// Priorities above `KERN_ERR` all map to `Error`, like in [`filter_from_priority()`].
+pub(crate) fn level(priority: u8) -> Level {
+    match priority & 7 {
+        0..=3 => Level::Error,
+        4 => Level::Warn,
+        5 => Level::Info,
+        6 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+/// Reserved record key to override printk severity with
+#[cfg(feature = "kv")]
+pub(crate) const SEVERITY_KEY: &str = "kmsg_priority";
+
+/// Get printk severity from reserved `kmsg_priority` record key, if it's set to `0` to `7`
+#[cfg(feature = "kv")]
+pub(crate) fn record_severity(record: &Record) -> Option<u8> {
+    let value = record.key_values().get(log::kv::Key::from_str(SEVERITY_KEY))?;
+    value.to_u64().filter(|&severity| severity <= 7).map(|severity| severity as u8)
+}
+
+/// Get printk severity from reserved record key, always `None` without `kv` feature
+#[cfg(not(feature = "kv"))]
+pub(crate) fn record_severity(_record: &Record) -> Option<u8> {
+    None
+}
+
+/// Get level filter passing records with printk priority up to given one
+///
+/// Priorities above `KERN_ERR` (0 to 3) all map to `Error`,
+/// as `log` has no more severe levels.
+pub(crate) fn filter_from_priority(priority: u8) -> Option<LevelFilter> {
+    match priority {
+        0..=3 => Some(LevelFilter::Error),
+        4 => Some(LevelFilter::Warn),
+        5 => Some(LevelFilter::Info),
+        6 => Some(LevelFilter::Debug),
+        7 => Some(LevelFilter::Trace),
+        _ => None,
+    }
+}
+
+/// Get level filter passing records kernel would print to the console with given console loglevel
+///
+/// Kernel prints records with priority below console loglevel, and as `log` has no levels
+/// more severe than `Error`, errors are always passed.
+pub(crate) fn filter_from_console_loglevel(console: u8) -> LevelFilter {
+    filter_from_priority(console.saturating_sub(1).min(7)).unwrap_or(LevelFilter::Error)
+}
+
+/// Parse level filter either from its name (`info`, `debug`, etc.)
+/// or from numeric printk priority (`0` to `7`)
+pub(crate) fn parse_filter(value: &str) -> Option<LevelFilter> {
+    let value = value.trim();
+    match value.parse::<u8>() {
+        Ok(priority) => filter_from_priority(priority),
+        Err(_) => value.parse().ok(),
+    }
+}
+
+/// Parse level filter from environment variable value, failing on invalid values
+///
+/// Returns `None` if the variable is not set.
+pub(crate) fn parse_env_filter(name: &str, value: Result<String, VarError>) -> io::Result<Option<LevelFilter>> {
+    let value = match value {
+        Ok(value) => value,
+        Err(VarError::NotPresent) => return Ok(None),
+        Err(VarError::NotUnicode(value)) => value.to_string_lossy().into_owned(),
+    };
+    match parse_filter(&value) {
+        Some(filter) => Ok(Some(filter)),
+        None => Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "invalid {} value {:?}: expected one of off, error, warn, info, debug, trace or 0 to 7",
+            name, value)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::VarError;
+
+    use log::LevelFilter;
+    use super::{compose, filter_from_console_loglevel, parse_filter, parse_env_filter, strip_severity_suffix, Facility, Prefixes, Severity};
+
+    #[test]
+    fn strip_suffix() {
+        assert_eq!(strip_severity_suffix("init::mount::crit"), Some(("init::mount", Severity::Crit)));
+        assert_eq!(strip_severity_suffix("init::emerg"), Some(("init", Severity::Emerg)));
+        assert_eq!(strip_severity_suffix("init::mount"), None);
+        assert_eq!(strip_severity_suffix("crit"), None);
+    }
+
+    #[test]
+    fn compose_priority() {
+        assert_eq!(compose(Facility::Kern, 3), 3);
+        assert_eq!(compose(Facility::Daemon, 6), 30);
+        assert_eq!(compose(Facility::Local7, 7), 191);
+        let prefixes = Prefixes::new(Facility::Kern, None);
+        assert_eq!(prefixes.record(0), b"<0>");
+        assert_eq!(prefixes.priority(0), b"<0>");
+        let prefixes = Prefixes::new(Facility::Local7, Some("init"));
+        assert_eq!(prefixes.record(7), b"<191>init: ");
+        assert_eq!(prefixes.priority(7), b"<191>");
+    }
+
+    #[test]
+    fn parse_names_and_numbers() {
+        assert_eq!(parse_filter("warn"), Some(LevelFilter::Warn));
+        assert_eq!(parse_filter("DEBUG"), Some(LevelFilter::Debug));
+        assert_eq!(parse_filter("0"), Some(LevelFilter::Error));
+        assert_eq!(parse_filter("3"), Some(LevelFilter::Error));
+        assert_eq!(parse_filter("5"), Some(LevelFilter::Info));
+        assert_eq!(parse_filter("7"), Some(LevelFilter::Trace));
+        assert_eq!(parse_filter("8"), None);
+        assert_eq!(parse_filter("loud"), None);
+    }
+
+    #[test]
+    fn console_loglevel_filter() {
+        assert_eq!(filter_from_console_loglevel(1), LevelFilter::Error);
+        assert_eq!(filter_from_console_loglevel(4), LevelFilter::Error);
+        assert_eq!(filter_from_console_loglevel(5), LevelFilter::Warn);
+        assert_eq!(filter_from_console_loglevel(7), LevelFilter::Debug);
+        assert_eq!(filter_from_console_loglevel(15), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn parse_env_strictly() {
+        assert_eq!(parse_env_filter("LEVEL", Err(VarError::NotPresent)).unwrap(), None);
+        assert_eq!(parse_env_filter("LEVEL", Ok("info".into())).unwrap(), Some(LevelFilter::Info));
+
+        let err = parse_env_filter("LEVEL", Ok("inof".into())).unwrap_err();
+        assert!(err.to_string().starts_with("invalid LEVEL value \"inof\""));
+    }
+}
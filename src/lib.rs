@@ -26,51 +26,616 @@
 //! }
 //! ```
 //! Note you have to have permissions to write to `/dev/kmsg`,
-//! which normal users (not root) usually don't.
-//! 
-//! If compiled with nightly it can use libc feature to get process id
-//! and report it into log. This feature is unavailable for stable release
-//! for now. To enable nightly features, compile with `--features nightly`:
+//! which normal users (not root) usually don't. Use [`init_or_stderr()`]
+//! for programs run both ways, and [`check_permissions()`] to find out what's missing.
+//! [`health_check()`] confirms records actually land in the kernel log.
 //!
-//! ```toml
-//! [dependencies.kernlog]
-//! version = "*"
-//! features = ["nightly"]
-//! ```
+//! # Environment
+//!
+//! [`init()`] and [`KernelLog::from_env()`] read these environment variables:
+//!
+//! - `KERNLOG_DEVICE`: device to log to instead of `/dev/kmsg`,
+//! - `KERNLOG_LEVEL`: level filter, e.g. `info` or `debug`, or numeric printk
+//!   priority from `0` to `7` (`3` is `error`, `7` is `trace`),
+//! - `KERNLOG_FILTER`: per-target level filter directives, e.g. `info,my_crate::net=trace,hyper=warn`,
+//!   see [`Builder::filter()`] for details,
+//! - `KERNLOG_FORMAT`: record format template, e.g. `{tag}[{pid}] {target}: {msg}`,
+//!   see [`TemplateFormat`] for details.
+//!
+//! [`log_every_n!`], [`log_first_n!`] and [`throttle!`] macros log only some occurrences
+//! of a call site, for loops which would otherwise flood the kernel ring buffer.
+//! [`init_auto()`] picks kernel log, journald or stderr, whichever suits the environment best.
+//! [`tee()`] combines kernel logger with another logger, to log to both at once.
+//! [`Builder::backend()`] writes formatted records to a custom [`KmsgBackend`] instead of a device.
+//!
+//! [`KmsgReader`] reads records back from the kernel log, e.g. to check what landed
+//! in the ring buffer, and [`KernelBridge`] forwards kernel messages to the installed logger.
+//! [`read_ring_buffer()`] takes a snapshot of the whole ring buffer at once,
+//! [`clear_ring_buffer()`] clears it, and [`unread_ring_buffer_size()`] tells how much
+//! of it a syslog daemon has yet to read.
+//! [`set_console_level()`], [`console_level_guard()`], [`console_off()`] and [`console_on()`]
+//! control which records kernel prints to the console.
+//!
+//! # Platforms
+//!
+//! Kernel log is Linux only. Elsewhere the crate builds with a stand-in [`KernelLog`]
+//! writing records to stderr, with the basic part of the API: constructors, [`Builder`]
+//! level and tag options, [`Handle`] and `init*()` functions, so cross-platform programs
+//! can depend on it unconditionally. Level types, [`hexdump()`] and call site macros
+//! are available everywhere. The stand-in reads only `KERNLOG_LEVEL` from the environment,
+//! `KERNLOG_DEVICE`, `KERNLOG_FILTER` and `KERNLOG_FORMAT` are ignored, and Linux-only
+//! features such as `io-uring` and `journald` have no effect there.
+//!
+//! # Features
+//!
+//! - `serde`: implement `Deserialize` for [`Config`], so the logger can be configured
+//!   straight from the application's config file.
+//! - `io-uring`: submit records queued for background writer thread through io_uring,
+//!   see [`Builder::io_uring()`].
+//! - `journald`: allow falling back to systemd-journald native socket, see [`Fallback`].
+//! - `kv`: override printk severity of individual records with reserved `kmsg_priority` key,
+//!   e.g. `error!(kmsg_priority = 2; "disk is on fire")` is logged at `KERN_CRIT`.
+//! - `parking_lot`: use `parking_lot` locks for internal state instead of `std` ones.
+//! - `env_filter`: filter records with `env_logger` filter specs, see [`Builder::env_filter()`].
+//! - `logd`: write records to Android logd with [`LogdBackend`].
+//! - `rfc5424`: send records to remote syslog collectors with [`SyslogBackend`].
+//! - `regex`: allow regular expressions as targets in filter directives,
+//!   see [`Builder::filter()`].
+//! - `tokio`: read kernel log records asynchronously with [`KmsgStream`].
+//! - `tracing`: write `tracing` events to kernel log with [`KernelLayer`].
+//! - `slog`: use [`KernelLog`] as `slog` drain.
+//! - `fern`: chain [`KernelLog`] to `fern::Dispatch`.
+//! - `log4rs`: add kernel log appenders to log4rs config with [`KernelAppenderDeserializer`].
 
 #![deny(missing_docs)]
-#![cfg_attr(feature="nightly", feature(libc))]
 
-#[macro_use]
 extern crate log;
 extern crate libc;
 
-use std::fs::{OpenOptions, File};
-use std::io::{Write, self};
-use std::path::Path;
-use std::sync::Mutex;
-use std::env;
+#[cfg(target_os = "linux")]
+use std::fmt;
+#[cfg(target_os = "linux")]
+use std::fs::File;
+use std::io;
+#[cfg(target_os = "linux")]
+use std::io::{IoSlice, Write};
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsFd, OwnedFd};
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+#[cfg(target_os = "linux")]
+use std::sync::Arc;
+#[cfg(target_os = "linux")]
+use std::time::{Duration, Instant};
+
+use log::SetLoggerError;
+#[cfg(target_os = "linux")]
+use log::{Level, Log, Metadata, Record, RecordBuilder, LevelFilter};
+
+#[cfg(all(target_os = "linux", feature = "log4rs"))]
+pub use appender::{KernelAppender, KernelAppenderDeserializer};
+#[cfg(target_os = "linux")]
+pub use backend::KmsgBackend;
+#[cfg(target_os = "linux")]
+pub use auto::Destination;
+#[cfg(target_os = "linux")]
+pub use bridge::KernelBridge;
+#[cfg(target_os = "linux")]
+pub use builder::Builder;
+#[cfg(target_os = "linux")]
+pub use config::{Config, LevelRemap};
+#[cfg(target_os = "linux")]
+pub use fallback::Fallback;
+#[cfg(target_os = "linux")]
+pub use format::{DefaultFormat, KmsgFormat, Origin};
+pub use hexdump::hexdump;
+#[cfg(all(target_os = "linux", feature = "tracing"))]
+pub use layer::KernelLayer;
+pub use level::{Facility, Severity};
+#[cfg(all(target_os = "linux", feature = "logd"))]
+pub use logd::{LogdBackend, LogdBuffer};
+#[cfg(target_os = "linux")]
+pub use preflight::{check_permissions, health_check, Health, Permissions, UnavailableReason};
+#[cfg(target_os = "linux")]
+pub use queue::Backpressure;
+#[cfg(target_os = "linux")]
+pub use ratelimit::RateLimit;
+#[cfg(all(target_os = "linux", feature = "rfc5424"))]
+pub use remote::{SyslogBackend, SyslogTransport};
+#[cfg(target_os = "linux")]
+pub use reader::{boot_time, Drain, Follow, KmsgPosition, KmsgReader, KmsgRecord};
+#[cfg(target_os = "linux")]
+pub use stats::DropCounts;
+#[cfg(target_os = "linux")]
+pub use syslog::{
+    clear_ring_buffer, console_level_guard, console_off, console_on, read_ring_buffer, ring_buffer_size,
+    set_console_level, unread_ring_buffer_size, ConsoleLevelGuard,
+};
+#[cfg(all(target_os = "linux", feature = "tokio"))]
+pub use stream::KmsgStream;
+#[cfg(target_os = "linux")]
+pub use tee::{tee, Tee};
+#[cfg(target_os = "linux")]
+pub use template::TemplateFormat;
+#[cfg(target_os = "linux")]
+pub use verbosity::{verbosity_guard, VerbosityGuard};
+#[cfg(not(target_os = "linux"))]
+pub use stub::{
+    init, init_auto, init_or_stderr, init_with_device, init_with_device_and_level, init_with_level, Builder, Handle,
+    KernelLog,
+};
 
-use log::{Log, Metadata, Record, Level, LevelFilter, SetLoggerError};
+#[cfg(target_os = "linux")]
+mod auto;
+#[cfg(target_os = "linux")]
+mod backend;
+#[cfg(target_os = "linux")]
+mod bridge;
+#[cfg(all(target_os = "linux", feature = "log4rs"))]
+mod appender;
+#[cfg(target_os = "linux")]
+mod buffer;
+#[cfg(target_os = "linux")]
+mod builder;
+#[cfg(target_os = "linux")]
+mod cmdline;
+#[cfg(target_os = "linux")]
+mod config;
+#[cfg(target_os = "linux")]
+mod dedup;
+#[cfg(target_os = "linux")]
+mod dictionary;
+#[cfg(all(target_os = "linux", feature = "fern"))]
+mod dispatch;
+#[cfg(all(target_os = "linux", feature = "slog"))]
+mod drain;
+#[cfg(target_os = "linux")]
+mod env;
+#[cfg(target_os = "linux")]
+mod fallback;
+#[cfg(target_os = "linux")]
+mod filter;
+#[cfg(target_os = "linux")]
+mod format;
+mod hexdump;
+#[cfg(all(target_os = "linux", feature = "tracing"))]
+mod layer;
+mod level;
+#[cfg(all(target_os = "linux", feature = "logd"))]
+mod logd;
+mod macros;
+#[cfg(target_os = "linux")]
+mod preflight;
+#[cfg(target_os = "linux")]
+mod queue;
+#[cfg(target_os = "linux")]
+mod ratelimit;
+#[cfg(target_os = "linux")]
+mod reader;
+#[cfg(all(target_os = "linux", feature = "rfc5424"))]
+mod remote;
+#[cfg(target_os = "linux")]
+mod replay;
+#[cfg(target_os = "linux")]
+mod sanitize;
+#[cfg(target_os = "linux")]
+mod split;
+#[cfg(target_os = "linux")]
+mod stats;
+#[cfg(target_os = "linux")]
+mod sync;
+#[cfg(target_os = "linux")]
+mod syslog;
+#[cfg(target_os = "linux")]
+pub mod sysctl;
+#[cfg(target_os = "linux")]
+mod tee;
+#[cfg(target_os = "linux")]
+mod template;
+#[cfg(all(target_os = "linux", feature = "tokio"))]
+mod stream;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+#[cfg(target_os = "linux")]
+mod verbosity;
+#[cfg(not(target_os = "linux"))]
+mod stub;
+
+#[cfg(target_os = "linux")]
+use backend::Kmsg;
+#[cfg(target_os = "linux")]
+use buffer::RecordBuf;
+#[cfg(target_os = "linux")]
+use dedup::Dedup;
+#[cfg(target_os = "linux")]
+use dictionary::Dictionary;
+#[cfg(target_os = "linux")]
+use fallback::FallbackWriter;
+#[cfg(target_os = "linux")]
+use filter::Filter;
+#[cfg(target_os = "linux")]
+use queue::Queue;
+#[cfg(target_os = "linux")]
+use ratelimit::{Limits, Suppressed};
+#[cfg(target_os = "linux")]
+use replay::Pending;
+#[cfg(target_os = "linux")]
+use sanitize::Sanitizer;
+#[cfg(target_os = "linux")]
+use stats::{Counters, Report};
+#[cfg(target_os = "linux")]
+use sync::{Mutex, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Callback reporting records that failed to be written, see [`Builder::on_error()`]
+#[cfg(target_os = "linux")]
+type ErrorHook = Box<dyn Fn(&io::Error, &str) + Send + Sync>;
+
+/// Error of a failed record write, with the record, waiting to be reported to error hook
+#[cfg(target_os = "linux")]
+type Failure = (io::Error, Vec<u8>);
+
+/// Error of writes to lazy device which can't be opened yet
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+struct NotOpened {
+    /// Record was written to fallback destination or buffered for replay instead
+    kept: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl fmt::Display for NotOpened {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("device is not opened")
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl std::error::Error for NotOpened {}
 
 /// Kernel logger implementation
+#[cfg(target_os = "linux")]
 pub struct KernelLog {
-    kmsg: Mutex<File>,
-    maxlevel: LevelFilter
+    shared: Arc<Shared>
+}
+
+// Panics can't leave shared state broken: locks are held only around self-contained updates,
+// and a logger has to keep working while a panic is being reported
+#[cfg(target_os = "linux")]
+impl std::panic::RefUnwindSafe for KernelLog {}
+#[cfg(target_os = "linux")]
+impl std::panic::UnwindSafe for KernelLog {}
+
+#[cfg(target_os = "linux")]
+struct Shared {
+    /// Device file, written through shared reference, as `write(2)` on `/dev/kmsg`
+    /// is atomic per record, so logging threads don't contend on a lock
+    kmsg: RwLock<Box<dyn KmsgBackend>>,
+    maxlevel: AtomicUsize,
+    filter: Filter,
+    format: Box<dyn KmsgFormat>,
+    sanitizer: Sanitizer,
+    dictionary: Dictionary,
+    prefixes: level::Prefixes,
+    severity_suffixes: bool,
+    max_record_len: usize,
+    /// Queue of background writer thread, if records are written asynchronously
+    queue: Option<Arc<Queue>>,
+    fallback: FallbackWriter,
+    /// Open devices switched to with [`Handle::set_device()`] with `O_NONBLOCK`
+    nonblocking: bool,
+    /// Records waiting for lazy device to be opened, locked only with `kmsg` locked for writing
+    pending: Mutex<Pending>,
+    /// Numbers of records the device failed to accept
+    counters: Counters,
+    on_error: Option<ErrorHook>,
+    /// Number of times to try reopening the device after it starts failing writes
+    reopen_attempts: u32,
+    /// Number of failed reopen attempts since the last successful write
+    reopen_failures: AtomicU32,
+    /// Last record to suppress repeats of, if deduplication is enabled
+    dedup: Option<Mutex<Dedup>>,
+    limits: Limits,
+    /// Periodic report of suppressed records, if enabled
+    report: Option<Report>
+}
+
+#[cfg(target_os = "linux")]
+impl Shared {
+    fn new(kmsg: Box<dyn KmsgBackend>, filter: LevelFilter, format: Box<dyn KmsgFormat>) -> Shared {
+        Shared {
+            max_record_len: kmsg.max_len(),
+            kmsg: RwLock::new(kmsg),
+            maxlevel: AtomicUsize::new(filter as usize),
+            filter: Filter::default(),
+            format,
+            sanitizer: Sanitizer::default(),
+            dictionary: Dictionary::default(),
+            prefixes: level::Prefixes::new(Facility::Kern, None),
+            severity_suffixes: false,
+            queue: None,
+            fallback: FallbackWriter::default(),
+            nonblocking: false,
+            pending: Mutex::new(Pending::default()),
+            counters: Counters::default(),
+            on_error: None,
+            reopen_attempts: 0,
+            reopen_failures: AtomicU32::new(0),
+            dedup: None,
+            limits: Limits::default(),
+            report: None
+        }
+    }
+
+    fn kmsg(&self) -> RwLockReadGuard<'_, Box<dyn KmsgBackend>> {
+        self.kmsg.read()
+    }
+
+    fn kmsg_mut(&self) -> RwLockWriteGuard<'_, Box<dyn KmsgBackend>> {
+        self.kmsg.write()
+    }
+
+    /// Write record to backend, or to fallback destination or replay buffer if lazy device
+    /// can't be opened yet
+    ///
+    /// Records accepted by fallback destination are not buffered, so they are not written twice.
+    fn write(&self, buf: &[u8]) -> io::Result<()> {
+        let missing = || {
+            if self.fallback.accept(buf) {
+                self.counters.count_fallback();
+            } else if self.pending.lock().push(buf) {
+                self.counters.count_buffered();
+            } else {
+                return false;
+            }
+            true
+        };
+        let write = || self.with_backend(|kmsg| kmsg.write_record(buf), missing);
+        match write() {
+            Err(ref err) if self.reopen_after(err) => write(),
+            result => result,
+        }
+    }
+
+    /// Try to reopen the device if write error means descriptor is no longer usable,
+    /// returning if write should be retried
+    ///
+    /// Gives up after `reopen_attempts` failed reopens in a row, until a write succeeds again.
+    fn reopen_after(&self, err: &io::Error) -> bool {
+        if !matches!(err.raw_os_error(), Some(libc::EBADF) | Some(libc::ENXIO) | Some(libc::EIO)) {
+            return false;
+        }
+        if self.reopen_failures.load(Ordering::Relaxed) >= self.reopen_attempts {
+            return false;
+        }
+        match self.kmsg_mut().open() {
+            Ok(()) => {
+                self.reopen_failures.store(0, Ordering::Relaxed);
+                true
+            }
+            Err(_) => {
+                self.reopen_failures.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// Suppress formatted record if it repeats the previous one, or send it on,
+    /// returning if the record was sent
+    ///
+    /// Records are sent with the last record locked, so summaries stay right after repeated
+    /// records, but failures are reported to error hook once it's unlocked, as the hook may log.
+    fn dedup(&self, record: &Record, severity: u8, buf: &[u8]) -> bool {
+        let mut failures = Vec::new();
+        let sent = match self.dedup {
+            Some(ref dedup) => {
+                let mut dedup = dedup.lock();
+                let fresh = dedup.push(record, severity, buf, |summary, severity| {
+                    self.send_summary(summary, severity, &mut failures)
+                });
+                if fresh {
+                    self.send(buf, &mut failures);
+                }
+                fresh
+            }
+            None => {
+                self.send(buf, &mut failures);
+                true
+            }
+        };
+        self.report_failures(failures);
+        sent
+    }
+
+    /// Queue log record for background writer thread, or deliver it right away
+    fn send(&self, buf: &[u8], failures: &mut Vec<Failure>) {
+        match self.queue {
+            Some(ref queue) => queue.push(buf),
+            None => self.deliver(buf, failures),
+        }
+    }
+
+    /// Write summary of suppressed record repeats, if any
+    fn flush_dedup(&self) {
+        if let Some(ref dedup) = self.dedup {
+            let mut failures = Vec::new();
+            dedup.lock().flush(|summary, severity| self.send_summary(summary, severity, &mut failures));
+            self.report_failures(failures);
+        }
+    }
+
+    /// Format and send summary of suppressed records
+    fn send_summary(&self, summary: &Record, severity: u8, failures: &mut Vec<Failure>) {
+        let mut buf = RecordBuf::new();
+        if self.format_record(&mut buf, summary, severity).is_ok() {
+            self.send(buf.as_bytes(), failures);
+        }
+    }
+
+    fn format_record(&self, buf: &mut RecordBuf, record: &Record, severity: u8) -> io::Result<()> {
+        buf.write_all(self.prefixes.record(severity))?;
+        self.format.format(buf, record)?;
+        self.dictionary.write(buf)?;
+        buf.write_all(b"\n")
+    }
+
+    /// Write log record to the device, or to fallback destination if it fails,
+    /// collecting the failure for error hook
+    fn deliver(&self, buf: &[u8], failures: &mut Vec<Failure>) {
+        if let Some(failure) = self.fail(buf, self.write(buf)) {
+            failures.push(failure);
+        }
+    }
+
+    /// Count or write to fallback destination log record which failed to be written,
+    /// and report it to error hook
+    fn settle(&self, buf: &[u8], result: io::Result<()>) {
+        if let Some(failure) = self.fail(buf, result) {
+            self.report_failures(vec![failure]);
+        }
+    }
+
+    /// Count or write to fallback destination log record which failed to be written,
+    /// returning the failure if there's an error hook to report it to
+    fn fail(&self, buf: &[u8], result: io::Result<()>) -> Option<Failure> {
+        let err = result.err()?;
+        // Records for lazy device which isn't opened yet already went to fallback or replay buffer
+        match err.get_ref().and_then(|err| err.downcast_ref::<NotOpened>()) {
+            Some(not_opened) if not_opened.kept => {}
+            Some(_) => self.counters.count(&err),
+            None if err.kind() != io::ErrorKind::WouldBlock && self.fallback.accept(buf) => self.counters.count_fallback(),
+            None => self.counters.count(&err),
+        }
+        self.on_error.as_ref().map(|_| (err, buf.to_vec()))
+    }
+
+    /// Call error hook with failed records
+    fn report_failures(&self, failures: Vec<Failure>) {
+        if let Some(ref on_error) = self.on_error {
+            for (err, buf) in failures {
+                let record = String::from_utf8_lossy(&buf);
+                on_error(&err, record.strip_suffix('\n').unwrap_or(&record));
+            }
+        }
+    }
+
+    /// Write record from several buffers, without concatenating them if backend supports it
+    fn write_vectored(&self, bufs: &mut [IoSlice<'_>]) -> io::Result<()> {
+        self.with_backend(|kmsg| kmsg.write_record_vectored(bufs), || false)
+    }
+
+    /// Write to backend, opening lazy device first if needed
+    ///
+    /// Records buffered while the device couldn't be opened are written first once it's opened,
+    /// `missing` is called while the device still can't be opened, returning if it kept the record.
+    fn with_backend(&self, write: impl FnOnce(&dyn KmsgBackend) -> io::Result<()>, missing: impl FnOnce() -> bool) -> io::Result<()> {
+        {
+            let kmsg = self.kmsg();
+            if kmsg.is_open() {
+                return write(&**kmsg);
+            }
+        }
+
+        // Lazy device is not opened yet, so it's the only time writers are serialized
+        let mut kmsg = self.kmsg_mut();
+        if kmsg.is_open() || kmsg.open().is_ok() {
+            self.pending.lock().replay(&**kmsg);
+            write(&**kmsg)
+        } else {
+            let kept = missing();
+            Err(io::Error::new(io::ErrorKind::NotFound, NotOpened { kept }))
+        }
+    }
+
+    fn maxlevel(&self) -> LevelFilter {
+        match self.maxlevel.load(Ordering::Relaxed) {
+            0 => LevelFilter::Off,
+            1 => LevelFilter::Error,
+            2 => LevelFilter::Warn,
+            3 => LevelFilter::Info,
+            4 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+
+    fn set_maxlevel(&self, filter: LevelFilter) {
+        self.maxlevel.store(filter as usize, Ordering::Relaxed);
+    }
+
+    /// Get level filter for target: off for disallowed targets, from the most specific
+    /// matching filter directive, or the logger level filter if no directive matches,
+    /// raised by current thread verbosity guard
+    fn target_level(&self, target: &str) -> LevelFilter {
+        if !self.filter.allows(target) {
+            return LevelFilter::Off;
+        }
+        let level = self.filter.level(target).unwrap_or_else(|| self.maxlevel());
+        match verbosity::thread_level() {
+            Some(raised) => level.max(raised),
+            None => level,
+        }
+    }
+
+    /// Get the most verbose level any record may pass with, for `log::set_max_level()`
+    fn global_maxlevel(&self) -> LevelFilter {
+        #[cfg(feature = "env_filter")]
+        if let Some(ref env) = self.filter.env {
+            return env.filter();
+        }
+        self.maxlevel().max(self.filter.max_level())
+    }
+
+    /// Check record with `env_logger` filter, if set: off for disallowed targets,
+    /// passed by `passes` or raised current thread verbosity guard
+    #[cfg(feature = "env_filter")]
+    fn env_passes(&self, target: &str, level: Level, passes: impl FnOnce(&env_filter::Filter) -> bool) -> Option<bool> {
+        let env = self.filter.env.as_ref()?;
+        Some(self.filter.allows(target)
+            && (passes(env) || verbosity::thread_level().is_some_and(|raised| level <= raised)))
+    }
 }
 
+#[cfg(target_os = "linux")]
+impl Drop for Shared {
+    /// Stop background writer thread, writing records it hasn't got to
+    fn drop(&mut self) {
+        self.flush_dedup();
+        if let Some(ref queue) = self.queue {
+            for record in queue.close() {
+                self.settle(&record, self.write(&record));
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
 impl KernelLog {
 
-    const DEFAULT_DEVICE: &'static str = "/dev/kmsg";
+    pub(crate) const DEFAULT_DEVICE: &'static str = "/dev/kmsg";
+
+    /// Check if records can be written to `/dev/kmsg`, without constructing a logger
+    ///
+    /// Useful for choosing between this logger and another one, see [`check_permissions()`]
+    /// for a more detailed report.
+    pub fn is_available() -> Result<(), UnavailableReason> {
+        preflight::check_available(Path::new(KernelLog::DEFAULT_DEVICE))
+    }
 
     /// Create new kernel logger
     pub fn new() -> io::Result<KernelLog> {
         KernelLog::with_level(LevelFilter::Trace)
     }
 
-    /// Create new kernel logger from default device with log level specificed by `KERNLOG_LEVEL` environment variable
+    /// Create new kernel logger from device specified by `KERNLOG_DEVICE` environment variable
+    /// (`/dev/kmsg` by default) with log level specificed by `KERNLOG_LEVEL` environment variable,
+    /// per-target filter directives specified by `KERNLOG_FILTER` environment variable
+    /// and record format template specified by `KERNLOG_FORMAT` environment variable
     pub fn from_env() -> io::Result<KernelLog> {
-        Self::from_env_with_device(Self::DEFAULT_DEVICE)
+        match env::device() {
+            Some(device) => Self::from_env_with_device(device),
+            None => Self::from_env_with_device(Self::DEFAULT_DEVICE),
+        }
     }
 
     /// Create new kernel logger from default device with error level filter
@@ -85,54 +650,487 @@ impl KernelLog {
 
     /// Create new kernel logger from specific device with error level filter
     pub fn with_device_and_level(device: impl AsRef<Path>, filter: LevelFilter) -> io::Result<KernelLog> {
-        Ok(KernelLog {
-            kmsg: Mutex::new(OpenOptions::new().write(true).open(device.as_ref())?),
-            maxlevel: filter
-        })
+        KernelLog::builder().device(device).level(filter).build()
+    }
+
+    /// Create new kernel logger from already opened device file
+    ///
+    /// Useful for sandboxed processes which get `/dev/kmsg` descriptor
+    /// from a supervisor or open it before dropping privileges.
+    pub fn from_file(file: File) -> KernelLog {
+        KernelLog::with_kmsg(Kmsg::from_file(file), LevelFilter::Trace, Box::new(DefaultFormat::new()))
+    }
+
+    /// Create new kernel logger from already opened device file descriptor
+    pub fn from_owned_fd(fd: OwnedFd) -> KernelLog {
+        KernelLog::from_file(File::from(fd))
+    }
+
+    /// Create new kernel logger from default device, which is opened on first log record
+    ///
+    /// This never fails: if the device can't be opened yet, log records are dropped
+    /// and opening is retried on every next record. Useful in early boot, when
+    /// `/dev/kmsg` may be not available at the moment logging is initialized.
+    pub fn new_lazy() -> KernelLog {
+        KernelLog::with_device_lazy(Self::DEFAULT_DEVICE)
+    }
+
+    /// Create new kernel logger from specific device, which is opened on first log record
+    pub fn with_device_lazy(device: impl AsRef<Path>) -> KernelLog {
+        KernelLog::with_kmsg(Kmsg::lazy(device.as_ref(), false), LevelFilter::Trace, Box::new(DefaultFormat::new()))
+    }
+
+    /// Create new kernel logger from configuration
+    pub fn from_config(config: &Config) -> io::Result<KernelLog> {
+        let mut builder = KernelLog::builder();
+        match config.tag.as_deref() {
+            Some("") => builder = builder.program_tag(),
+            Some(tag) => builder = builder.tag(tag),
+            None => (),
+        }
+        if let Some(ref template) = config.format {
+            builder = builder.template(template.as_str());
+        }
+        if let Some(max_record_len) = config.max_record_len {
+            builder = builder.max_record_len(max_record_len);
+        }
+        if config.fallback != Fallback::None {
+            builder = builder.fallback(config.fallback);
+        }
+        if let Some(capacity) = config.replay_buffer {
+            builder = builder.replay_buffer(capacity);
+        }
+        if let Some(capacity) = config.background {
+            builder = builder.background(capacity).backpressure(config.backpressure);
+            if config.retry_attempts > 0 {
+                builder = builder.retry(config.retry_attempts, Duration::from_millis(config.retry_backoff_ms));
+            }
+        }
+        if let Some(ref directives) = config.filter {
+            builder = builder.filter(directives.as_str());
+        }
+        #[cfg(feature = "env_filter")]
+        if let Some(ref spec) = config.env_filter {
+            builder = builder.env_filter(spec.as_str());
+        }
+        if let Some(limit) = config.call_site_rate_limit {
+            builder = builder.rate_limit_call_sites(limit);
+        }
+        if let Some(limit) = config.rate_limit {
+            builder = builder.rate_limit(limit);
+        }
+        for (target, &limit) in &config.target_rate_limits {
+            builder = builder.rate_limit_target(target.as_str(), limit);
+        }
+        for (&level, &limit) in &config.level_rate_limits {
+            builder = builder.rate_limit_level(level, limit);
+        }
+        for (&level, &one_in) in &config.sampling {
+            builder = builder.sample(level, one_in);
+        }
+        builder = builder.never_suppress(config.never_suppress);
+        if let Some(secs) = config.suppression_report_secs {
+            builder = builder.suppression_report(Duration::from_secs(secs));
+        }
+        for remap in &config.remap_levels {
+            builder = builder.remap_level(remap.target.as_str(), remap.from, remap.to);
+        }
+        let allow: Vec<&str> = config.allow_targets.iter().map(String::as_str).collect();
+        let deny: Vec<&str> = config.deny_targets.iter().map(String::as_str).collect();
+        for (key, value) in &config.fields {
+            builder = builder.field(key.as_str(), value.as_str());
+        }
+        builder
+            .device(&config.device)
+            .level(config.level)
+            .console_level(config.console_level)
+            .respect_kernel_cmdline(config.respect_kernel_cmdline)
+            .allow_targets(&allow)
+            .deny_targets(&deny)
+            .lazy(config.lazy)
+            .nonblocking(config.nonblocking)
+            .auto_reopen(config.auto_reopen)
+            .dedup(config.dedup)
+            .pace_printk_ratelimit(config.pace_printk_ratelimit)
+            .origin(config.origin)
+            .pid(config.pid)
+            .tid(config.tid)
+            .thread_name(config.thread_name)
+            .location(config.location)
+            .strip_ansi(config.strip_ansi)
+            .escape_control(config.escape_control)
+            .syslog_fields(config.syslog_fields)
+            .facility(config.facility)
+            .severity_suffixes(config.severity_suffixes)
+            .build()
+    }
+
+    /// Create new kernel logger builder
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    fn with_kmsg(kmsg: Kmsg, filter: LevelFilter, format: Box<dyn KmsgFormat>) -> KernelLog {
+        KernelLog { shared: Arc::new(Shared::new(Box::new(kmsg), filter, format)) }
     }
 
     /// Create new kernel logger from specific device with error level filter from `KERNLOG_LEVEL` environment variable
+    /// and format template from `KERNLOG_FORMAT` environment variable
     pub fn from_env_with_device(device: impl AsRef<Path>) -> io::Result<KernelLog> {
-        match env::var("KERNLOG_LEVEL") {
-            Err(_) => KernelLog::with_device(device),
-            Ok(s) => match s.parse() {
-                Ok(filter) => KernelLog::with_device_and_level(device, filter),
-                Err(_) => KernelLog::with_device(device),
-            }
+        KernelLog::builder().device(device).env().build()
+    }
+
+    /// Create new kernel logger like [`KernelLog::from_env()`], but fail on invalid environment variables
+    ///
+    /// Where `from_env()` silently falls back to `trace` level filter, no filter directives and default format,
+    /// this returns an `InvalidInput` error describing the unparsable value,
+    /// so typos in unit files and scripts don't go unnoticed.
+    pub fn from_env_strict() -> io::Result<KernelLog> {
+        match env::device() {
+            Some(device) => Self::from_env_strict_with_device(device),
+            None => Self::from_env_strict_with_device(Self::DEFAULT_DEVICE),
         }
     }
+
+    /// Create new kernel logger from specific device with settings from environment
+    /// variables, failing on invalid values, see [`KernelLog::from_env_strict()`]
+    pub fn from_env_strict_with_device(device: impl AsRef<Path>) -> io::Result<KernelLog> {
+        KernelLog::builder().device(device).env_strict()?.build()
+    }
+
+    /// Reopen the device this logger writes to
+    ///
+    /// Useful after `chroot()`, mount namespace switch or re-exec, when current
+    /// file descriptor doesn't point to the right device anymore.
+    /// Fails if the logger was created from an already opened file.
+    pub fn reopen(&self) -> io::Result<()> {
+        self.shared.kmsg_mut().open()
+    }
+
+    /// Write message with explicit printk severity, bypassing level filter and record formatting
+    ///
+    /// Message is written as is, prefixed with priority only, so it must fit into a single record
+    /// (see [`KernelLog::max_record_len()`]) and must not contain new lines.
+    /// Unlike [`Log::log()`], this reports write errors.
+    ///
+    /// ```rust,no_run
+    /// use kernlog::{KernelLog, Severity};
+    ///
+    /// let klog = KernelLog::new().unwrap();
+    /// klog.write_raw(Severity::Emerg, "no bootable root filesystem found").unwrap();
+    /// ```
+    pub fn write_raw(&self, severity: Severity, message: &str) -> io::Result<()> {
+        let prefix = self.shared.prefixes.priority(severity as u8);
+        self.shared.write_vectored(&mut [IoSlice::new(prefix), IoSlice::new(message.as_bytes()), IoSlice::new(b"\n")])
+    }
+
+    /// Get maximum length of record written to the device, including priority prefix and new line
+    ///
+    /// Longer messages are split into several records. This is detected from running kernel
+    /// version, unless overridden with [`Builder::max_record_len()`].
+    pub fn max_record_len(&self) -> usize {
+        self.shared.max_record_len
+    }
+
+    /// Get number of records dropped or replaced because background writer queue was full,
+    /// see [`Builder::backpressure()`]
+    pub fn queue_overflows(&self) -> u64 {
+        self.shared.queue.as_ref().map_or(0, |queue| queue.dropped())
+    }
+
+    /// Get number of records dropped because non-blocking device would block,
+    /// see [`Builder::nonblocking()`]
+    pub fn would_block_drops(&self) -> u64 {
+        self.shared.counters.would_block()
+    }
+
+    /// Get total number of dropped records, see [`KernelLog::drop_counts()`]
+    pub fn dropped_count(&self) -> u64 {
+        self.drop_counts().total()
+    }
+
+    /// Get numbers of dropped records by cause
+    ///
+    /// Records lost after being accepted by the device, e.g. overwritten in kernel ring buffer,
+    /// are not counted.
+    pub fn drop_counts(&self) -> DropCounts {
+        self.shared.counters.snapshot(self.queue_overflows())
+    }
+
+    /// Get current level filter
+    pub fn max_level(&self) -> LevelFilter {
+        self.shared.maxlevel()
+    }
+
+    /// Change level filter at runtime
+    ///
+    /// This doesn't touch global `log` max level, so records above the level this logger
+    /// was installed with never reach it. Once the logger is installed,
+    /// use [`Handle::set_level()`] instead, which updates both.
+    pub fn set_max_level(&self, filter: LevelFilter) {
+        self.shared.set_maxlevel(filter);
+    }
+
+    /// Get a handle to reconfigure this logger after it has been installed
+    pub fn handle(&self) -> Handle {
+        Handle { shared: self.shared.clone() }
+    }
 }
 
+#[cfg(target_os = "linux")]
 impl Log for KernelLog {
     fn enabled(&self, meta: &Metadata) -> bool {
-        meta.level() <= self.maxlevel
+        let level = self.shared.filter.remap(meta.target(), meta.level());
+        #[cfg(feature = "env_filter")]
+        if let Some(passes) = self.shared.env_passes(meta.target(), level, |env| {
+            env.enabled(&Metadata::builder().level(level).target(meta.target()).build())
+        }) {
+            return passes;
+        }
+        level <= self.shared.target_level(meta.target())
     }
 
     fn log(&self, record: &Record) {
-        if record.level() > self.maxlevel {
+        let level = self.shared.filter.remap(record.target(), record.level());
+        #[cfg(feature = "env_filter")]
+        let passes = self.shared.env_passes(record.target(), level, |env| {
+            env.matches(&rebuild(record).level(level).args(*record.args()).build())
+        }).unwrap_or_else(|| level <= self.shared.target_level(record.target()));
+        #[cfg(not(feature = "env_filter"))]
+        let passes = level <= self.shared.target_level(record.target());
+        if !passes {
             return;
         }
 
-        let level: u8 = match record.level() {
-            Level::Error => 3,
-            Level::Warn => 4,
-            Level::Info => 5,
-            Level::Debug => 6,
-            Level::Trace => 7,
+        let remapped;
+        let record = if level != record.level() {
+            remapped = rebuild(record).level(level).args(*record.args()).build();
+            &remapped
+        } else {
+            record
+        };
+
+        let suffix = if self.shared.severity_suffixes {
+            level::strip_severity_suffix(record.target())
+        } else {
+            None
+        };
+        let severity = level::record_severity(record);
+        match suffix {
+            Some((target, suffix)) => {
+                let severity = severity.unwrap_or(suffix as u8);
+                self.emit(&rebuild(record).target(target).args(*record.args()).build(), severity);
+            }
+            None => self.emit(record, severity.unwrap_or_else(|| level::priority(record.level()))),
+        }
+    }
+
+    fn flush(&self) {
+        self.flush_call_sites(Instant::now(), true);
+        self.shared.flush_dedup();
+        if let Some(ref queue) = self.shared.queue {
+            queue.flush();
+        }
+    }
+}
+
+/// Copy record metadata into a new record builder, to replace record message
+#[cfg(target_os = "linux")]
+fn rebuild<'a>(record: &'a Record<'a>) -> RecordBuilder<'a> {
+    let mut builder = Record::builder();
+    builder
+        .metadata(record.metadata().clone())
+        .module_path(record.module_path())
+        .file(record.file())
+        .line(record.line());
+    #[cfg(feature = "kv")]
+    builder.key_values(record.key_values());
+    builder
+}
+
+#[cfg(target_os = "linux")]
+impl KernelLog {
+    /// Write message unless it's over rate limits,
+    /// reporting records suppressed since the last one passed before it
+    fn emit(&self, record: &Record, severity: u8) {
+        let now = Instant::now();
+        if let Some(summary) = self.shared.report.as_ref().and_then(|report| report.take(now)) {
+            let args = format_args!("{}", summary);
+            let summary = Record::builder().level(Level::Warn).target(module_path!()).args(args).build();
+            self.write_message(&summary, level::priority(Level::Warn));
+        }
+        self.flush_call_sites(now, false);
+
+        let passed = self.shared.limits.check(record, now, |suppressed| match suppressed {
+            Suppressed::CallSite { count, file, line, .. } => {
+                let args = format_args!("suppressed {} messages at {}:{}", count, file, line);
+                self.write_message(&rebuild(record).args(args).build(), severity);
+            }
+            Suppressed::Target { target, count, period } => {
+                let args = format_args!(
+                    "suppressed {} messages from {} in the last {}s", count, target, stats::whole_secs(period));
+                let summary = Record::builder().level(Level::Warn).target(module_path!()).args(args).build();
+                self.write_message(&summary, level::priority(Level::Warn));
+            }
+            Suppressed::Period { level, count, period } => {
+                let (level, kind) = match level {
+                    Some(level) => (level, format!("{} ", level.as_str().to_ascii_lowercase())),
+                    None => (Level::Warn, String::new()),
+                };
+                let args = format_args!("suppressed {} {}messages in the last {}s", count, kind, stats::whole_secs(period));
+                let summary = Record::builder().level(level).target(module_path!()).args(args).build();
+                self.write_message(&summary, level::priority(level));
+            }
+        });
+        if passed {
+            self.write_message(record, severity);
+        } else if let Some(ref report) = self.shared.report {
+            report.count(record.target());
+        }
+    }
+
+    /// Write summaries of records suppressed at call sites which didn't pass a record since,
+    /// once their refill interval has passed or for all of them if `all` is set
+    fn flush_call_sites(&self, now: Instant, all: bool) {
+        self.shared.limits.flush_call_sites(now, all, |suppressed| {
+            if let Suppressed::CallSite { count, file, line, level, target } = suppressed {
+                let args = format_args!("suppressed {} messages at {}:{}", count, file, line);
+                let summary = Record::builder().level(level).target(target).file(Some(file)).line(Some(line)).args(args).build();
+                self.write_message(&summary, level::priority(level));
+            }
+        });
+    }
+
+    fn write_message(&self, record: &Record, severity: u8) {
+        let mut text = RecordBuf::new();
+        let msg = match record.args().as_str() {
+            Some(msg) => msg,
+            None => {
+                if fmt::Write::write_fmt(&mut text, *record.args()).is_err() {
+                    return;
+                }
+                text.as_str()
+            }
         };
+        let msg = self.shared.sanitizer.strip(msg);
+        if msg.contains('\n') {
+            // Every line becomes a separate record with the same priority and prefix,
+            // as kernel would treat anything after the first new line as garbage
+            for line in msg.lines() {
+                self.write_line(record, severity, line);
+            }
+        } else {
+            self.write_line(record, severity, &msg);
+        }
+    }
+
+    fn write_line(&self, record: &Record, severity: u8, line: &str) {
+        let line = self.shared.sanitizer.escape(line);
+        self.write_record(&rebuild(record).args(format_args!("{}", line)).build(), severity);
+    }
+
+    fn write_record(&self, record: &Record, severity: u8) {
+        let mut buf = RecordBuf::new();
+        if self.shared.format_record(&mut buf, record, severity).is_err() {
+            return;
+        }
+        // Dictionary lines don't count, as they are not written to the device
+        if backend::message_line(buf.as_bytes()).len() <= self.shared.max_record_len {
+            self.write(record, severity, buf.as_bytes());
+            return;
+        }
 
-        let mut buf = Vec::new();
-        writeln!(buf, "<{}>{}[{}]: {}", level, record.target(),
-                 unsafe { ::libc::getpid() },
-                 record.args()).unwrap();
+        // Message is too long to fit into a single record, so split it into
+        // numbered chunks, sizing them by the room left after formatting
+        let mut text = RecordBuf::new();
+        if fmt::Write::write_fmt(&mut text, *record.args()).is_err() {
+            return;
+        }
+        let msg = text.as_str();
+        let mut empty = RecordBuf::new();
+        if self.shared.format_record(&mut empty, &rebuild(record).args(format_args!("")).build(), severity).is_err() {
+            return;
+        }
+        let chunks = match self.shared.max_record_len.checked_sub(backend::message_line(empty.as_bytes()).len()).and_then(|room| split::split(msg, room)) {
+            Some(chunks) => chunks,
+            None => {
+                self.write(record, severity, buf.as_bytes());
+                return;
+            }
+        };
 
-        if let Ok(mut kmsg) = self.kmsg.lock() {
-            let _ = kmsg.write(&buf);
-            let _ = kmsg.flush();
+        for (index, chunk) in chunks.iter().enumerate() {
+            buf.clear();
+            let args = format_args!("{} ({}/{})", chunk, index + 1, chunks.len());
+            if self.shared.format_record(&mut buf, &rebuild(record).args(args).build(), severity).is_ok() {
+                self.write(record, severity, buf.as_bytes());
+            }
         }
     }
 
-    fn flush(&self) {}
+    fn write(&self, record: &Record, severity: u8, buf: &[u8]) {
+        if !self.shared.dedup(record, severity, buf) {
+            if let Some(ref report) = self.shared.report {
+                report.count(record.target());
+            }
+        }
+    }
+}
+
+/// Handle to reconfigure an installed kernel logger
+///
+/// Returned by [`init()`] and friends, or by [`KernelLog::handle()`].
+/// Handles are cheap to clone and all clones control the same logger.
+#[cfg(target_os = "linux")]
+#[derive(Clone)]
+pub struct Handle {
+    shared: Arc<Shared>
+}
+
+#[cfg(target_os = "linux")]
+impl Handle {
+    /// Get current level filter
+    pub fn level(&self) -> LevelFilter {
+        self.shared.maxlevel()
+    }
+
+    /// Change level filter, also updating global `log` max level
+    ///
+    /// Targets matching filter directives (see [`Builder::filter()`]) keep their levels.
+    pub fn set_level(&self, filter: LevelFilter) {
+        self.shared.set_maxlevel(filter);
+        verbosity::set_max_level(self.shared.global_maxlevel());
+    }
+
+    /// Get path of the device currently logged to
+    ///
+    /// Returns `None` if the logger was created from an already opened file.
+    pub fn device(&self) -> Option<PathBuf> {
+        self.shared.kmsg().device().map(Path::to_owned)
+    }
+
+    /// Reopen the device logger writes to, see [`KernelLog::reopen()`]
+    pub fn reopen(&self) -> io::Result<()> {
+        self.shared.kmsg_mut().open()
+    }
+
+    /// Switch logging to another device
+    ///
+    /// The current device is kept if the new one can't be opened.
+    pub fn set_device(&self, device: impl AsRef<Path>) -> io::Result<()> {
+        let kmsg = Kmsg::new(device.as_ref(), self.shared.nonblocking)?;
+        let mut current = self.shared.kmsg_mut();
+        *current = Box::new(kmsg);
+        self.shared.pending.lock().replay(&**current);
+        Ok(())
+    }
+}
+
+/// Implementation details of exported macros, not a public API
+#[doc(hidden)]
+pub mod __private {
+    pub use log::log;
+    pub use crate::macros::{every_n, first_n, Throttle};
 }
 
 /// KernelLog initialization error
@@ -141,7 +1139,12 @@ pub enum KernelLogInitError {
     /// IO error
     Io(io::Error),
     /// Set logger error
-    Log(SetLoggerError)
+    Log(SetLoggerError),
+    /// Writes to `/dev/kmsg` are disabled with `kernel.printk_devkmsg=off` sysctl,
+    /// so all records would be silently dropped by kernel
+    ///
+    /// Construct [`KernelLog`] and install it with `log::set_boxed_logger()` to use it anyway.
+    Disabled
 }
 
 impl std::fmt::Display for KernelLogInitError {
@@ -149,6 +1152,7 @@ impl std::fmt::Display for KernelLogInitError {
         match self {
             KernelLogInitError::Io(err) => err.fmt(f),
             KernelLogInitError::Log(err) => err.fmt(f),
+            KernelLogInitError::Disabled => f.write_str("writing to /dev/kmsg is disabled by kernel.printk_devkmsg=off"),
         }
     }
 }
@@ -158,6 +1162,7 @@ impl std::error::Error for KernelLogInitError {
         match self {
             KernelLogInitError::Io(err) => Some(err),
             KernelLogInitError::Log(err) => Some(err),
+            KernelLogInitError::Disabled => None,
         }
     }
 }
@@ -174,26 +1179,743 @@ impl From<io::Error> for KernelLogInitError {
 }
 
 /// Setup kernel logger as a default logger
-pub fn init() -> Result<(), KernelLogInitError> {
-    init_with_device(KernelLog::DEFAULT_DEVICE)
+///
+/// Device, level filter and filter directives are taken from `KERNLOG_DEVICE`, `KERNLOG_LEVEL`
+/// and `KERNLOG_FILTER` environment variables, see [`KernelLog::from_env()`].
+///
+/// Returns a [`Handle`] which can be used to reconfigure the logger later.
+/// Fails with [`KernelLogInitError::Disabled`] if `/dev/kmsg` writes are disabled by kernel.
+#[cfg(target_os = "linux")]
+pub fn init() -> Result<Handle, KernelLogInitError> {
+    check_enabled(&auto::device())?;
+    install(KernelLog::from_env()?)
 }
 
 /// Setup kernel logger as a default logger with specific device
-pub fn init_with_device(device: impl AsRef<Path>) -> Result<(), KernelLogInitError> {
-    let klog = KernelLog::from_env_with_device(device)?;
-    let maxlevel = klog.maxlevel;
-    log::set_boxed_logger(Box::new(klog))?;
-    log::set_max_level(maxlevel);
+#[cfg(target_os = "linux")]
+pub fn init_with_device(device: impl AsRef<Path>) -> Result<Handle, KernelLogInitError> {
+    check_enabled(device.as_ref())?;
+    install(KernelLog::from_env_with_device(device)?)
+}
+
+/// Setup kernel logger as a default logger with specific level filter
+#[cfg(target_os = "linux")]
+pub fn init_with_level(filter: LevelFilter) -> Result<Handle, KernelLogInitError> {
+    init_with_device_and_level(KernelLog::DEFAULT_DEVICE, filter)
+}
+
+/// Setup kernel logger as a default logger with specific device and level filter
+#[cfg(target_os = "linux")]
+pub fn init_with_device_and_level(device: impl AsRef<Path>, filter: LevelFilter) -> Result<Handle, KernelLogInitError> {
+    check_enabled(device.as_ref())?;
+    install(KernelLog::with_device_and_level(device, filter)?)
+}
+
+/// Setup kernel logger as a default logger, or a logger writing to stderr if the device
+/// can't be opened or writes to it are disabled
+///
+/// Useful for tools run both as early boot services and as unprivileged programs.
+/// Records written to stderr have the same format, including `<priority>` prefix,
+/// and are configured from the same environment variables, see [`init()`].
+#[cfg(target_os = "linux")]
+pub fn init_or_stderr() -> Result<Handle, KernelLogInitError> {
+    match init() {
+        Err(KernelLogInitError::Io(_)) | Err(KernelLogInitError::Disabled) => {
+            let stderr = io::stderr().as_fd().try_clone_to_owned()?;
+            // Environment is parsed leniently, like `init()` does
+            install(KernelLog::builder().env().file(File::from(stderr)).build()?)
+        }
+        result => result,
+    }
+}
+
+/// Setup kernel logger as a default logger, writing to the best destination for current environment,
+/// see [`Destination::detect()`]
+///
+/// Useful for programs run as early boot services, systemd units and container entrypoints alike.
+/// Records have the same format everywhere and are configured from the same environment variables,
+/// see [`init()`]. Journald is written to through stderr if it's connected to the journal,
+/// or through native socket with `journald` feature.
+#[cfg(target_os = "linux")]
+pub fn init_auto() -> Result<Handle, KernelLogInitError> {
+    let builder = KernelLog::builder().env();
+    let builder = match Destination::detect() {
+        Destination::Kmsg => builder.device(auto::device()),
+        #[cfg(feature = "journald")]
+        Destination::Journald if !auto::journal_stream() => builder.backend(fallback::Journald::new()?),
+        Destination::Journald | Destination::Stderr => {
+            builder.file(File::from(io::stderr().as_fd().try_clone_to_owned()?))
+        }
+    };
+    install(builder.build()?)
+}
+
+/// Fail if writes to the device are disabled by kernel
+///
+/// It's checked before the device is opened, as kernel refuses to open `/dev/kmsg` for writing
+/// with `EPERM` then.
+#[cfg(target_os = "linux")]
+fn check_enabled(device: &Path) -> Result<(), KernelLogInitError> {
+    if backend::is_kmsg(device) && sysctl::devkmsg_mode().ok() == Some(sysctl::DevkmsgMode::Off) {
+        return Err(KernelLogInitError::Disabled);
+    }
     Ok(())
 }
 
-#[cfg(test)]
+#[cfg(target_os = "linux")]
+fn install(klog: KernelLog) -> Result<Handle, KernelLogInitError> {
+    let handle = klog.handle();
+    log::set_boxed_logger(Box::new(klog))?;
+    verbosity::set_max_level(handle.shared.global_maxlevel());
+    Ok(handle)
+}
+
+#[cfg(all(test, target_os = "linux"))]
 mod tests {
-    use super::{KernelLog, init};
+    use std::fs::{self, File};
+    use std::io::{self, Write};
+    use std::path::PathBuf;
+    use std::thread;
+
+    use log::{debug, Level, LevelFilter, Log, Record};
+    use std::time::Duration;
+
+    use super::{Backpressure, Config, DropCounts, Facility, Fallback, KernelLog, Kmsg, KmsgBackend, KmsgFormat, RateLimit, Severity, init, verbosity_guard};
+
+    fn temp_device(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-{}", std::process::id(), name));
+        File::create(&path).unwrap();
+        path
+    }
+
+    fn log_msg(klog: &KernelLog, level: Level, msg: &str) {
+        klog.log(&Record::builder().level(level).target("test").args(format_args!("{}", msg)).build());
+    }
 
     #[test]
     fn log_to_kernel() {
         init().unwrap();
         debug!("hello, world!");
     }
+
+    #[test]
+    fn handle_reconfigures_logger() {
+        let first = temp_device("handle-first");
+        let second = temp_device("handle-second");
+
+        let klog = KernelLog::with_device(&first).unwrap();
+        let handle = klog.handle();
+        handle.set_device(&second).unwrap();
+        assert_eq!(handle.device(), Some(second.clone()));
+
+        klog.set_max_level(LevelFilter::Info);
+        assert_eq!(handle.level(), LevelFilter::Info);
+        log_msg(&klog, Level::Debug, "hidden");
+        log_msg(&klog, Level::Info, "shown");
+
+        assert_eq!(fs::read_to_string(&first).unwrap(), "");
+        let written = fs::read_to_string(&second).unwrap();
+        assert!(written.starts_with("<5>test["));
+        assert!(written.ends_with("]: shown\n"));
+    }
+
+    #[test]
+    fn log_to_open_file() {
+        let path = temp_device("from-file");
+        let klog = KernelLog::from_file(fs::OpenOptions::new().write(true).open(&path).unwrap());
+        assert_eq!(klog.handle().device(), None);
+
+        log_msg(&klog, Level::Error, "boom");
+        assert!(fs::read_to_string(&path).unwrap().starts_with("<3>test["));
+        assert!(klog.reopen().is_err());
+    }
+
+    #[test]
+    fn log_from_config() {
+        let path = temp_device("config");
+        let klog = KernelLog::from_config(&Config {
+            device: path.clone(),
+            level: LevelFilter::Warn,
+            pid: false,
+            ..Config::default()
+        }).unwrap();
+
+        log_msg(&klog, Level::Info, "hidden");
+        log_msg(&klog, Level::Warn, "shown");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<4>test: shown\n");
+    }
+
+    #[test]
+    fn log_with_custom_format() {
+        struct Bare;
+
+        impl KmsgFormat for Bare {
+            fn format(&self, buf: &mut dyn Write, record: &Record) -> io::Result<()> {
+                write!(buf, "{}", record.args())
+            }
+        }
+
+        let path = temp_device("format");
+        let klog = KernelLog::builder().device(&path).formatter(Box::new(Bare)).build().unwrap();
+        log_msg(&klog, Level::Debug, "bare");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<6>bare\n");
+    }
+
+    #[test]
+    fn log_with_format_closure() {
+        let path = temp_device("format-closure");
+        let klog = KernelLog::builder()
+            .device(&path)
+            .format(|buf, record| write!(buf, "{} says {}", record.target(), record.args()))
+            .build()
+            .unwrap();
+        log_msg(&klog, Level::Trace, "hi");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<7>test says hi\n");
+    }
+
+    #[test]
+    fn log_without_ansi() {
+        let path = temp_device("ansi");
+        let klog = KernelLog::builder().device(&path).pid(false).strip_ansi(true).build().unwrap();
+        log_msg(&klog, Level::Error, "\x1b[31mred\x1b[0m");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<3>test: red\n");
+    }
+
+    #[test]
+    fn split_long_message() {
+        let path = temp_device("split");
+        let klog = KernelLog::builder().device(&path).pid(false).max_record_len(500).build().unwrap();
+        assert_eq!(klog.max_record_len(), 500);
+        let msg = "0123456789".repeat(100);
+        log_msg(&klog, Level::Info, &msg);
+
+        let written = fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let mut joined = String::new();
+        for (index, line) in lines.iter().enumerate() {
+            assert!(line.len() < 500);
+            let suffix = format!(" ({}/3)", index + 1);
+            joined.push_str(line.strip_prefix("<5>test: ").unwrap().strip_suffix(&suffix).unwrap());
+        }
+        assert_eq!(joined, msg);
+    }
+
+    #[test]
+    fn split_lines() {
+        let path = temp_device("lines");
+        let klog = KernelLog::builder().device(&path).pid(false).build().unwrap();
+        log_msg(&klog, Level::Warn, "first\nsecond\r\n\nfourth\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<4>test: first\n<4>test: second\n<4>test: \n<4>test: fourth\n");
+    }
+
+    #[test]
+    fn escape_control_chars() {
+        let escaped = temp_device("escape");
+        let klog = KernelLog::builder().device(&escaped).pid(false).build().unwrap();
+        log_msg(&klog, Level::Info, "a\tb\x1b");
+        assert_eq!(fs::read_to_string(&escaped).unwrap(), "<5>test: a\\x09b\\x1b\n");
+
+        let raw = temp_device("no-escape");
+        let klog = KernelLog::builder().device(&raw).pid(false).escape_control(false).build().unwrap();
+        log_msg(&klog, Level::Info, "a\tb");
+        assert_eq!(fs::read_to_string(&raw).unwrap(), "<5>test: a\tb\n");
+    }
+
+    #[test]
+    fn log_dictionary_fields() {
+        let path = temp_device("dictionary");
+        let klog = KernelLog::builder()
+            .device(&path)
+            .pid(false)
+            .tag("init")
+            .syslog_fields(true)
+            .field("UNIT", "root.mount")
+            .build()
+            .unwrap();
+        log_msg(&klog, Level::Error, "failed");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<3>init: test: failed\n");
+
+        // Only backends carrying dictionary fields get them
+        let memory = std::sync::Arc::new(Memory::default());
+        let klog = KernelLog::builder().backend(memory.clone()).pid(false).tag("init").syslog_fields(true).build().unwrap();
+        log_msg(&klog, Level::Error, "failed");
+        assert_eq!(*memory.0.lock().unwrap(), [format!(
+            "<3>init: test: failed\n SYSLOG_IDENTIFIER=init\n SYSLOG_PID={}\n", std::process::id())]);
+
+        assert!(KernelLog::builder().device(&path).field("unit", "").build().is_err());
+    }
+
+    #[test]
+    fn log_with_facility() {
+        let path = temp_device("facility");
+        let klog = KernelLog::builder().device(&path).pid(false).facility(Facility::Daemon).build().unwrap();
+        log_msg(&klog, Level::Info, "started");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<29>test: started\n");
+    }
+
+    #[test]
+    fn log_extended_severity() {
+        let path = temp_device("severity");
+        let klog = KernelLog::builder().device(&path).pid(false).severity_suffixes(true).build().unwrap();
+        klog.log(&Record::builder().level(Level::Error).target("boot::crit").args(format_args!("no root")).build());
+        klog.log(&Record::builder().level(Level::Warn).target("boot::emerg").args(format_args!("halt")).build());
+        klog.log(&Record::builder().level(Level::Error).target("boot").args(format_args!("err")).build());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<2>boot: no root\n<0>boot: halt\n<3>boot: err\n");
+    }
+
+    #[test]
+    fn write_raw_record() {
+        let path = temp_device("raw");
+        let klog = KernelLog::builder().device(&path).level(LevelFilter::Off).build().unwrap();
+        klog.write_raw(Severity::Alert, "raw message").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<1>raw message\n");
+
+        // Tag is rendered into prefixes of formatted records only
+        let tagged = temp_device("raw-tagged");
+        let klog = KernelLog::builder().device(&tagged).pid(false).tag("init").facility(Facility::Daemon).build().unwrap();
+        log_msg(&klog, Level::Info, "formatted");
+        klog.write_raw(Severity::Alert, "raw message").unwrap();
+        assert_eq!(fs::read_to_string(&tagged).unwrap(), "<29>init: test: formatted\n<25>raw message\n");
+        fs::remove_file(tagged).unwrap();
+
+        let lazy = KernelLog::with_device_lazy(path.with_extension("missing"));
+        assert!(lazy.write_raw(Severity::Alert, "lost").is_err());
+    }
+
+    #[test]
+    fn filter_by_target() {
+        let path = temp_device("filter");
+        let klog = KernelLog::builder().device(&path).pid(false).filter("warn,app::net=debug,hyper=off").build().unwrap();
+        assert_eq!(klog.handle().level(), LevelFilter::Warn);
+
+        for &(target, level) in &[("app", Level::Info), ("app", Level::Warn), ("app::net::tcp", Level::Debug), ("hyper", Level::Error)] {
+            klog.log(&Record::builder().level(level).target(target).args(format_args!("{}", level)).build());
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<4>app: WARN\n<6>app::net::tcp: DEBUG\n");
+    }
+
+    #[test]
+    #[cfg(feature = "env_filter")]
+    fn filter_by_env_filter_spec() {
+        let path = temp_device("env-filter");
+        let klog = KernelLog::builder().device(&path).pid(false).env_filter("warn,app::net=debug/retry").build().unwrap();
+        assert!(klog.enabled(&log::Metadata::builder().level(Level::Debug).target("app::net").build()));
+        assert!(!klog.enabled(&log::Metadata::builder().level(Level::Info).target("app").build()));
+
+        for &(target, level, msg) in &[("app", Level::Info, "retry"), ("app", Level::Warn, "retry"), ("app::net", Level::Debug, "retry 1"), ("app::net", Level::Debug, "done")] {
+            klog.log(&Record::builder().level(level).target(target).args(format_args!("{}", msg)).build());
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<4>app: retry\n<6>app::net: retry 1\n");
+        assert!(KernelLog::builder().device(&path).env_filter("app=loud").build().is_err());
+    }
+
+    #[test]
+    fn remap_dependency_levels() {
+        let path = temp_device("remap");
+        let klog = KernelLog::builder()
+            .device(&path)
+            .pid(false)
+            .level(LevelFilter::Info)
+            .remap_level("reqwest", Level::Info, Level::Debug)
+            .remap_level("reqwest", Level::Error, Level::Warn)
+            .build()
+            .unwrap();
+
+        for &(target, level) in &[("reqwest::connect", Level::Info), ("reqwest", Level::Error), ("app", Level::Info)] {
+            klog.log(&Record::builder().level(level).target(target).args(format_args!("{}", level)).build());
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<4>reqwest: ERROR\n<5>app: INFO\n");
+    }
+
+    #[cfg(feature = "kv")]
+    #[test]
+    fn override_severity_with_kv() {
+        let path = temp_device("kv");
+        let klog = KernelLog::builder().device(&path).pid(false).build().unwrap();
+
+        let crit = [("kmsg_priority", 2)];
+        klog.log(&Record::builder().level(Level::Error).target("app").key_values(&crit).args(format_args!("crit")).build());
+        let bogus = [("kmsg_priority", 9)];
+        klog.log(&Record::builder().level(Level::Error).target("app").key_values(&bogus).args(format_args!("error")).build());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<2>app: crit\n<3>app: error\n");
+    }
+
+    #[test]
+    fn raise_verbosity_in_scope() {
+        let path = temp_device("verbosity");
+        let klog = KernelLog::builder().device(&path).pid(false).level(LevelFilter::Info).build().unwrap();
+
+        log_msg(&klog, Level::Debug, "hidden");
+        {
+            let _guard = verbosity_guard(LevelFilter::Debug);
+            log_msg(&klog, Level::Debug, "shown");
+            log_msg(&klog, Level::Trace, "still hidden");
+            thread::scope(|scope| {
+                scope.spawn(|| log_msg(&klog, Level::Debug, "hidden in other thread"));
+            });
+        }
+        log_msg(&klog, Level::Debug, "hidden again");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<6>test: shown\n");
+    }
+
+    #[test]
+    fn write_in_background() {
+        let path = temp_device("background");
+        let klog = KernelLog::builder().device(&path).pid(false).background(16).build().unwrap();
+        for index in 0..10 {
+            klog.log(&Record::builder().level(Level::Info).target("app").args(format_args!("{}", index)).build());
+        }
+        klog.flush();
+
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(written.lines().count(), 10);
+        assert!(written.starts_with("<5>app: 0\n"));
+        assert!(written.ends_with("<5>app: 9\n"));
+    }
+
+    #[cfg(feature = "io-uring")]
+    #[test]
+    fn write_through_io_uring() {
+        let path = temp_device("io-uring");
+        let klog = KernelLog::builder().device(&path).pid(false).background(128).io_uring(true).build().unwrap();
+        for index in 0..100 {
+            klog.log(&Record::builder().level(Level::Info).target("app").args(format_args!("{}", index)).build());
+        }
+        klog.flush();
+
+        let expected: String = (0..100).map(|index| format!("<5>app: {}\n", index)).collect();
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+
+        // Backends without a file are written to with plain writes
+        let memory = std::sync::Arc::new(Memory::default());
+        let klog = KernelLog::builder().backend(memory.clone()).pid(false).background(16).io_uring(true).build().unwrap();
+        log_msg(&klog, Level::Info, "first");
+        klog.flush();
+        log_msg(&klog, Level::Info, "second");
+        klog.flush();
+        assert_eq!(*memory.0.lock().unwrap(), ["<5>test: first\n", "<5>test: second\n"]);
+    }
+
+    #[test]
+    fn kernel_cmdline_overrides_filter_default() {
+        let console = match crate::cmdline::console_loglevel() {
+            Ok(Some(console)) => console,
+            // No level parameters on kernel command line
+            _ => return,
+        };
+        let path = temp_device("cmdline");
+        let klog = KernelLog::builder().device(&path).filter("trace,net=debug").respect_kernel_cmdline(true).build().unwrap();
+        assert_eq!(klog.max_level(), crate::level::filter_from_console_loglevel(console));
+        assert_eq!(klog.shared.target_level("net"), LevelFilter::Debug);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn fallback_for_missing_device() {
+        let path = temp_device("fallback").join("missing");
+        assert!(KernelLog::builder().device(&path).build().is_err());
+
+        let klog = KernelLog::builder().device(&path).fallback(Fallback::Stderr).build().unwrap();
+        log_msg(&klog, Level::Info, "to stderr");
+        assert_eq!(klog.handle().device(), Some(path));
+        assert_eq!(klog.drop_counts().fallback, 1);
+        assert_eq!(klog.dropped_count(), 0);
+    }
+
+    #[test]
+    fn report_write_errors() {
+        use std::sync::{Arc, Mutex};
+
+        let path = temp_device("on-error").join("missing");
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let reported = errors.clone();
+        let klog = KernelLog::builder()
+            .device(&path)
+            .pid(false)
+            .fallback(Fallback::Stderr)
+            .on_error(move |err, record| reported.lock().unwrap().push((err.kind(), record.to_owned())))
+            .build()
+            .unwrap();
+        log_msg(&klog, Level::Warn, "lost");
+        assert_eq!(*errors.lock().unwrap(), [(io::ErrorKind::NotFound, "<4>test: lost".to_owned())]);
+    }
+
+    #[test]
+    fn log_from_error_hook() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::{Arc, Mutex, OnceLock};
+
+        let path = temp_device("on-error-log").join("missing");
+        let logger = Arc::new(OnceLock::<KernelLog>::new());
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let (hook_logger, reported, logged) = (logger.clone(), errors.clone(), AtomicBool::new(false));
+        let klog = KernelLog::builder()
+            .device(&path)
+            .pid(false)
+            .dedup(true)
+            .fallback(Fallback::Stderr)
+            .on_error(move |_, record| {
+                reported.lock().unwrap().push(record.to_owned());
+                if !logged.swap(true, Ordering::Relaxed) {
+                    log_msg(hook_logger.get().unwrap(), Level::Error, "write failed");
+                }
+            })
+            .build()
+            .unwrap();
+        assert!(logger.set(klog).is_ok());
+        log_msg(logger.get().unwrap(), Level::Warn, "lost");
+        assert_eq!(*errors.lock().unwrap(), ["<4>test: lost", "<3>test: write failed"]);
+    }
+
+    #[test]
+    fn log_from_error_hook_in_background() {
+        use std::sync::{mpsc, Arc, OnceLock};
+
+        let path = temp_device("on-error-queue").join("missing");
+        let logger = Arc::new(OnceLock::<KernelLog>::new());
+        let hook_logger = logger.clone();
+        let klog = KernelLog::builder()
+            .device(&path)
+            .pid(false)
+            .fallback(Fallback::Stderr)
+            .background(1)
+            .backpressure(Backpressure::Block)
+            .on_error(move |_, record| {
+                if !record.ends_with("hook") {
+                    let klog = hook_logger.get().unwrap();
+                    log_msg(klog, Level::Error, "hook");
+                    klog.flush();
+                }
+            })
+            .build()
+            .unwrap();
+        assert!(logger.set(klog).is_ok());
+
+        let (done, finished) = mpsc::channel();
+        let producer = logger.clone();
+        thread::spawn(move || {
+            for _ in 0..50 {
+                log_msg(producer.get().unwrap(), Level::Info, "lost");
+            }
+            producer.get().unwrap().flush();
+            done.send(()).unwrap();
+        });
+        assert!(finished.recv_timeout(Duration::from_secs(10)).is_ok(), "writer thread deadlocked");
+    }
+
+    #[test]
+    fn replay_when_device_appears() {
+        let path = temp_device("replay");
+        fs::remove_file(&path).unwrap();
+        let klog = KernelLog::builder().device(&path).pid(false).replay_buffer(8).build().unwrap();
+        log_msg(&klog, Level::Info, "early");
+        log_msg(&klog, Level::Warn, "still early");
+
+        File::create(&path).unwrap();
+        log_msg(&klog, Level::Info, "late");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<5>test: early\n<4>test: still early\n<5>test: late\n");
+        assert_eq!(klog.drop_counts().buffered, 2);
+        assert_eq!(klog.dropped_count(), 0);
+
+        // Records written to fallback are not replayed
+        fs::remove_file(&path).unwrap();
+        let klog = KernelLog::builder().device(&path).pid(false).replay_buffer(8).fallback(Fallback::Stderr).build().unwrap();
+        log_msg(&klog, Level::Info, "early");
+        File::create(&path).unwrap();
+        log_msg(&klog, Level::Info, "late");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<5>test: late\n");
+        assert_eq!(klog.drop_counts(), DropCounts { fallback: 1, ..DropCounts::default() });
+
+        // Records neither buffered nor written to fallback are lost
+        fs::remove_file(&path).unwrap();
+        let klog = KernelLog::builder().device(&path).pid(false).replay_buffer(1).build().unwrap();
+        log_msg(&klog, Level::Info, "early");
+        log_msg(&klog, Level::Info, "lost");
+        assert_eq!(klog.drop_counts(), DropCounts { write_error: 1, buffered: 1, ..DropCounts::default() });
+    }
+
+    #[derive(Default)]
+    struct Memory(std::sync::Mutex<Vec<String>>);
+
+    impl KmsgBackend for std::sync::Arc<Memory> {
+        fn open(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn write_record(&self, record: &[u8]) -> io::Result<()> {
+            self.0.lock().unwrap().push(String::from_utf8_lossy(record).into_owned());
+            Ok(())
+        }
+
+        fn max_len(&self) -> usize {
+            24
+        }
+    }
+
+    #[test]
+    fn write_to_custom_backend() {
+        let memory = std::sync::Arc::new(Memory::default());
+        let klog = KernelLog::builder().backend(memory.clone()).pid(false).build().unwrap();
+        assert_eq!(klog.max_record_len(), 24);
+        assert_eq!(klog.handle().device(), None);
+        log_msg(&klog, Level::Info, "started");
+        log_msg(&klog, Level::Warn, "a rather long message");
+        klog.write_raw(Severity::Crit, "raw").unwrap();
+        klog.reopen().unwrap();
+        assert_eq!(*memory.0.lock().unwrap(), [
+            "<5>test: started\n",
+            "<4>test: a rather (1/3)\n",
+            "<4>test:  long me (2/3)\n",
+            "<4>test: ssage (3/3)\n",
+            "<2>raw\n",
+        ]);
+    }
+
+    #[test]
+    fn drop_when_would_block() {
+        use std::os::unix::io::FromRawFd;
+
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        let reader = unsafe { File::from_raw_fd(fds[0]) };
+        let writer = unsafe { File::from_raw_fd(fds[1]) };
+
+        let klog = KernelLog::builder().file(writer).nonblocking(true).build().unwrap();
+        let msg = "x".repeat(512);
+        for _ in 0..1024 {
+            log_msg(&klog, Level::Info, &msg);
+        }
+        assert!(klog.would_block_drops() > 0);
+        drop(reader);
+    }
+
+    #[test]
+    fn reopen_after_write_failure() {
+        let path = temp_device("auto-reopen");
+        let klog = KernelLog::builder().device(&path).pid(false).auto_reopen(1).build().unwrap();
+
+        // Read-only descriptor fails writes with EBADF, like a stale one
+        *klog.shared.kmsg_mut() = Box::new(Kmsg { file: Some(File::open(&path).unwrap()), ..Kmsg::lazy(&path, false) });
+        log_msg(&klog, Level::Info, "restored");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<5>test: restored\n");
+        assert_eq!(klog.dropped_count(), 0);
+
+        *klog.shared.kmsg_mut() = Box::new(Kmsg { file: Some(File::open(&path).unwrap()), ..Kmsg::lazy(&path, false) });
+        fs::remove_file(&path).unwrap();
+        fs::create_dir(&path).unwrap();
+        log_msg(&klog, Level::Info, "lost");
+        log_msg(&klog, Level::Info, "lost again");
+        assert_eq!(klog.dropped_count(), 2);
+        fs::remove_dir(&path).unwrap();
+    }
+
+    #[test]
+    fn suppress_repeated_records() {
+        let path = temp_device("dedup");
+        let klog = KernelLog::builder().device(&path).pid(false).dedup(true).build().unwrap();
+        for _ in 0..3 {
+            log_msg(&klog, Level::Error, "failed");
+        }
+        log_msg(&klog, Level::Info, "recovered");
+        log_msg(&klog, Level::Info, "recovered");
+        klog.flush();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "<3>test: failed\n<3>test: last message repeated 2 times\n<5>test: recovered\n<5>test: last message repeated 1 time\n"
+        );
+    }
+
+    #[test]
+    fn rate_limit_call_sites() {
+        let path = temp_device("call-sites");
+        let klog = KernelLog::builder()
+            .device(&path)
+            .pid(false)
+            .rate_limit_call_sites(RateLimit::new(2, Duration::from_secs(3600)))
+            .build()
+            .unwrap();
+        for line in [10, 10, 10, 10, 11] {
+            klog.log(&Record::builder().target("test").file(Some("main.rs")).line(Some(line)).args(format_args!("loop")).build());
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<5>test: loop\n<5>test: loop\n<5>test: loop\n");
+        // Summary of call site which didn't pass a record again is written on flush
+        klog.flush();
+        klog.flush();
+        assert_eq!(fs::read_to_string(&path).unwrap(),
+            "<5>test: loop\n<5>test: loop\n<5>test: loop\n<5>test: suppressed 2 messages at main.rs:10\n");
+    }
+
+    #[test]
+    fn pace_printk_ratelimit() {
+        let path = temp_device("pace");
+        let klog = KernelLog::builder().device(&path).pid(false).pace_printk_ratelimit(true).build().unwrap();
+        for _ in 0..100 {
+            log_msg(&klog, Level::Info, "loop");
+        }
+        // Kernel rate limit doesn't apply to other devices
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 100);
+    }
+
+    #[test]
+    fn report_suppressed_records() {
+        let path = temp_device("report");
+        let klog = KernelLog::builder()
+            .device(&path)
+            .pid(false)
+            .dedup(true)
+            .suppression_report(Duration::ZERO)
+            .build()
+            .unwrap();
+        log_msg(&klog, Level::Info, "again");
+        log_msg(&klog, Level::Info, "again");
+        log_msg(&klog, Level::Info, "done");
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "<5>test: again\n<5>test: last message repeated 1 time\n<4>kernlog: suppressed in the last 1s: test=1\n<5>test: done\n"
+        );
+    }
+
+    #[test]
+    fn report_sampled_out_records() {
+        let path = temp_device("report-sampled");
+        let klog = KernelLog::builder()
+            .device(&path)
+            .pid(false)
+            .sample(Level::Debug, u32::MAX)
+            .suppression_report(Duration::ZERO)
+            .build()
+            .unwrap();
+        log_msg(&klog, Level::Debug, "sampled");
+        log_msg(&klog, Level::Debug, "sampled");
+        log_msg(&klog, Level::Info, "done");
+        let report = "<4>kernlog: suppressed in the last 1s: test=1\n";
+        assert_eq!(fs::read_to_string(&path).unwrap(), format!("{}{}<5>test: done\n", report, report));
+    }
+
+    #[test]
+    fn reopen_device() {
+        let path = temp_device("reopen");
+        let klog = KernelLog::with_device(&path).unwrap();
+
+        fs::remove_file(&path).unwrap();
+        File::create(&path).unwrap();
+        klog.handle().reopen().unwrap();
+        log_msg(&klog, Level::Warn, "again");
+
+        assert!(fs::read_to_string(&path).unwrap().starts_with("<4>test["));
+    }
+
+    #[test]
+    fn lazy_open_retries() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-lazy", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let klog = KernelLog::with_device_lazy(&path);
+
+        log_msg(&klog, Level::Info, "lost");
+        File::create(&path).unwrap();
+        log_msg(&klog, Level::Info, "found");
+
+        assert!(fs::read_to_string(&path).unwrap().ends_with("]: found\n"));
+    }
 }
@@ -0,0 +1,189 @@
+//! Backend writing records to Android logd
+
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::fallback::parse_priority;
+use crate::format::{gettid, program_name};
+use crate::KmsgBackend;
+
+/// logd socket accepting log entries from applications
+const LOGDW_SOCKET: &str = "/dev/socket/logdw";
+
+/// Android log buffer to write records to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogdBuffer {
+    /// Application log buffer (default)
+    #[default]
+    Main,
+    /// System components log buffer
+    System,
+    /// Crash reports buffer
+    Crash,
+}
+
+impl LogdBuffer {
+    fn id(self) -> u8 {
+        match self {
+            LogdBuffer::Main => 0,
+            LogdBuffer::System => 3,
+            LogdBuffer::Crash => 4,
+        }
+    }
+}
+
+/// Backend writing records to Android logd socket, see [`Builder::backend()`](crate::Builder::backend)
+///
+/// Records are sent as liblog would send them: kernel severity is mapped to Android priority
+/// (`EMERG`..`CRIT` to fatal, `NOTICE` to info, `INFO` to debug and `DEBUG` to verbose,
+/// so `log` levels keep their names), tag is `SYSLOG_IDENTIFIER` dictionary field
+/// or program name, and other dictionary fields are dropped.
+/// Socket is connected on the first write.
+///
+/// ```rust,no_run
+/// use kernlog::{KernelLog, LogdBackend};
+///
+/// let klog = KernelLog::builder().backend(LogdBackend::new().tag("netd")).build().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct LogdBackend {
+    path: PathBuf,
+    socket: Option<UnixDatagram>,
+    buffer: LogdBuffer,
+    tag: Option<String>,
+}
+
+impl LogdBackend {
+    /// Create backend writing to `/dev/socket/logdw` main buffer
+    pub fn new() -> LogdBackend {
+        LogdBackend { path: PathBuf::from(LOGDW_SOCKET), socket: None, buffer: LogdBuffer::Main, tag: None }
+    }
+
+    /// Write to another socket
+    pub fn socket(mut self, path: impl AsRef<Path>) -> LogdBackend {
+        self.path = path.as_ref().to_owned();
+        self
+    }
+
+    /// Write to another log buffer
+    pub fn buffer(mut self, buffer: LogdBuffer) -> LogdBackend {
+        self.buffer = buffer;
+        self
+    }
+
+    /// Set tag of records, replacing `SYSLOG_IDENTIFIER` field and program name
+    pub fn tag(mut self, tag: impl Into<String>) -> LogdBackend {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Build logd packet header: buffer id, thread id and realtime timestamp
+    fn header(&self, time: SystemTime) -> [u8; 11] {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let mut header = [0; 11];
+        header[0] = self.buffer.id();
+        header[1..3].copy_from_slice(&(gettid() as u16).to_le_bytes());
+        header[3..7].copy_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+        header[7..11].copy_from_slice(&since_epoch.subsec_nanos().to_le_bytes());
+        header
+    }
+
+    /// Split kmsg record into Android priority, tag and message
+    fn entry(&self, record: &[u8]) -> (u8, String, String) {
+        let record = String::from_utf8_lossy(record);
+        let mut lines = record.trim_end_matches('\n').split('\n');
+        let line = lines.next().unwrap_or_default();
+        let (severity, msg) = parse_priority(line).map_or((6, line), |(priority, msg)| (priority & 7, msg));
+        let identifier = lines
+            .filter_map(|field| field.strip_prefix(" SYSLOG_IDENTIFIER="))
+            .next();
+        let tag = match (&self.tag, identifier) {
+            (Some(tag), _) => tag.clone(),
+            (None, Some(tag)) => tag.to_owned(),
+            (None, None) => program_name(),
+        };
+        (priority(severity), tag, msg.to_owned())
+    }
+}
+
+impl Default for LogdBackend {
+    fn default() -> LogdBackend {
+        LogdBackend::new()
+    }
+}
+
+impl KmsgBackend for LogdBackend {
+    fn open(&mut self) -> io::Result<()> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(&self.path)?;
+        self.socket = Some(socket);
+        Ok(())
+    }
+
+    fn write_record(&self, record: &[u8]) -> io::Result<()> {
+        let socket = self.socket.as_ref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotConnected, "logd socket is not connected")
+        })?;
+        let header = self.header(SystemTime::now());
+        let (priority, tag, msg) = self.entry(record);
+        let mut packet = Vec::with_capacity(header.len() + tag.len() + msg.len() + 3);
+        packet.extend_from_slice(&header);
+        packet.push(priority);
+        packet.extend_from_slice(tag.as_bytes());
+        packet.push(0);
+        packet.extend_from_slice(msg.as_bytes());
+        packet.push(0);
+        socket.send(&packet).map(drop)
+    }
+
+    fn is_open(&self) -> bool {
+        self.socket.is_some()
+    }
+}
+
+/// Map kernel severity to Android log priority
+fn priority(severity: u32) -> u8 {
+    match severity {
+        0..=2 => 7,
+        3 => 6,
+        4 => 5,
+        5 => 4,
+        6 => 3,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::net::UnixDatagram;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{LogdBackend, LogdBuffer};
+    use crate::KmsgBackend;
+
+    #[test]
+    fn write_to_logd() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-logdw", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let logd = UnixDatagram::bind(&path).unwrap();
+        let mut backend = LogdBackend::new().socket(&path).buffer(LogdBuffer::System);
+        assert!(backend.write_record(b"<6>lost\n").is_err());
+        backend.open().unwrap();
+        backend.write_record(b"<5>init: mounted\n SYSLOG_IDENTIFIER=init\n").unwrap();
+        backend.tag("netd").write_record(b"<3>link down\n").unwrap();
+
+        let mut buf = [0; 256];
+        let len = logd.recv(&mut buf).unwrap();
+        assert_eq!(buf[0], 3);
+        assert_eq!(&buf[11..len], b"\x04init\0init: mounted\0");
+        let len = logd.recv(&mut buf).unwrap();
+        assert_eq!(&buf[11..len], b"\x06netd\0link down\0");
+        fs::remove_file(path).unwrap();
+
+        let header = LogdBackend::new().header(UNIX_EPOCH + Duration::new(0x0102_0304, 5));
+        assert_eq!(header[3..], [4, 3, 2, 1, 5, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,163 @@
+//! Macros logging only some occurrences of a call site
+
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Count call site occurrence, returning its number if it's one of every `n`th
+pub fn every_n(count: &AtomicUsize, n: usize) -> Option<usize> {
+    let previous = count.fetch_add(1, Ordering::Relaxed);
+    if n <= 1 || previous % n == 0 { Some(previous.wrapping_add(1)) } else { None }
+}
+
+/// Count call site occurrence, returning its number if it's one of the first `n`
+pub fn first_n(count: &AtomicUsize, n: usize) -> Option<usize> {
+    // Stop counting once past `n`, so the counter never wraps
+    if count.load(Ordering::Relaxed) >= n {
+        return None;
+    }
+    let count = count.fetch_add(1, Ordering::Relaxed) + 1;
+    if count <= n { Some(count) } else { None }
+}
+
+/// Time of the last occurrence of a throttled call site
+pub struct Throttle {
+    /// Nanoseconds since [`epoch()`] plus one, or zero if there was no occurrence yet
+    last: AtomicU64,
+}
+
+impl Throttle {
+    /// Create call site state with no occurrences yet
+    #[allow(clippy::new_without_default)]
+    pub const fn new() -> Throttle {
+        Throttle { last: AtomicU64::new(0) }
+    }
+
+    /// Check if at least `period` passed since the last occurrence, making this one the last if so
+    pub fn ready(&self, period: Duration) -> bool {
+        self.ready_at(period, epoch().elapsed())
+    }
+
+    fn ready_at(&self, period: Duration, now: Duration) -> bool {
+        let now = u64::try_from(now.as_nanos()).unwrap_or(u64::MAX - 1) + 1;
+        let last = self.last.load(Ordering::Relaxed);
+        if last != 0 && u128::from(now - last) < period.as_nanos() {
+            return false;
+        }
+        // Only one of concurrent occurrences wins
+        self.last.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_ok()
+    }
+}
+
+/// Time throttled call sites are measured from
+fn epoch() -> Instant {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    *EPOCH.get_or_init(Instant::now)
+}
+
+/// Log only every `n`th occurrence of the call site, starting with the first one
+///
+/// Occurrence number is appended to the message, so skipped ones can be accounted for.
+/// Records go to whatever logger is installed, and are filtered by its level as usual.
+///
+/// ```rust,no_run
+/// use log::Level;
+///
+/// for sector in 0..1_000_000u64 {
+///     kernlog::log_every_n!(1000, Level::Info, "scanned sector {}", sector);
+/// }
+/// ```
+#[macro_export]
+macro_rules! log_every_n {
+    ($n:expr, target: $target:expr, $lvl:expr, $($arg:tt)+) => {{
+        static COUNT: ::std::sync::atomic::AtomicUsize = ::std::sync::atomic::AtomicUsize::new(0);
+        if let Some(count) = $crate::__private::every_n(&COUNT, $n) {
+            $crate::__private::log!(target: $target, $lvl, "{} (occurrence {})", format_args!($($arg)+), count);
+        }
+    }};
+    ($n:expr, $lvl:expr, $($arg:tt)+) => {
+        $crate::log_every_n!($n, target: module_path!(), $lvl, $($arg)+)
+    };
+}
+
+/// Log only the first `n` occurrences of the call site
+///
+/// Occurrence number is appended to the message, like with [`log_every_n!`].
+///
+/// ```rust,no_run
+/// use log::Level;
+///
+/// # let retry = || false;
+/// while !retry() {
+///     kernlog::log_first_n!(3, Level::Warn, "device is not ready yet");
+/// }
+/// ```
+#[macro_export]
+macro_rules! log_first_n {
+    ($n:expr, target: $target:expr, $lvl:expr, $($arg:tt)+) => {{
+        static COUNT: ::std::sync::atomic::AtomicUsize = ::std::sync::atomic::AtomicUsize::new(0);
+        if let Some(count) = $crate::__private::first_n(&COUNT, $n) {
+            $crate::__private::log!(target: $target, $lvl, "{} (occurrence {})", format_args!($($arg)+), count);
+        }
+    }};
+    ($n:expr, $lvl:expr, $($arg:tt)+) => {
+        $crate::log_first_n!($n, target: module_path!(), $lvl, $($arg)+)
+    };
+}
+
+/// Evaluate logging expression at most once per `period` for the call site
+///
+/// The first occurrence is always logged. Meant for wait loops, which would flood
+/// the kernel ring buffer otherwise.
+///
+/// ```rust,no_run
+/// use std::path::Path;
+/// use std::time::Duration;
+/// use log::warn;
+///
+/// while !Path::new("/dev/sda").exists() {
+///     kernlog::throttle!(Duration::from_secs(5), warn!("disk still missing"));
+///     std::thread::sleep(Duration::from_millis(100));
+/// }
+/// ```
+#[macro_export]
+macro_rules! throttle {
+    ($period:expr, $log:expr $(,)?) => {{
+        static LAST: $crate::__private::Throttle = $crate::__private::Throttle::new();
+        if LAST.ready($period) {
+            $log;
+        }
+    }};
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    use super::Throttle;
+    use crate::__private::{every_n, first_n};
+
+    #[test]
+    fn count_occurrences() {
+        let count = AtomicUsize::new(0);
+        let logged: Vec<_> = (0..7).filter_map(|_| every_n(&count, 3)).collect();
+        assert_eq!(logged, [1, 4, 7]);
+
+        let count = AtomicUsize::new(0);
+        let logged: Vec<_> = (0..7).filter_map(|_| first_n(&count, 2)).collect();
+        assert_eq!(logged, [1, 2]);
+    }
+
+    #[test]
+    fn throttle_occurrences() {
+        let throttle = Throttle::new();
+        let period = Duration::from_secs(5);
+        assert!(throttle.ready_at(period, Duration::ZERO));
+        assert!(!throttle.ready_at(period, Duration::from_secs(1)));
+        assert!(!throttle.ready_at(period, Duration::from_millis(4999)));
+        assert!(throttle.ready_at(period, Duration::from_secs(5)));
+        assert!(!throttle.ready_at(period, Duration::from_secs(6)));
+    }
+}
@@ -0,0 +1,260 @@
+//! Checking permissions needed to write and read kernel log
+
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::sysctl::{self, DevkmsgMode};
+use crate::{KernelLog, KmsgPosition, KmsgReader};
+
+/// Capability number of `CAP_SYSLOG`
+const CAP_SYSLOG: u32 = 34;
+
+/// Check what the process is permitted to do with `/dev/kmsg`, see [`Permissions`]
+pub fn check_permissions() -> Permissions {
+    Permissions::check(KernelLog::DEFAULT_DEVICE)
+}
+
+/// Report of permissions to write and read kernel log
+///
+/// Its `Display` implementation describes what's missing, for startup diagnostics:
+///
+/// ```rust
+/// let permissions = kernlog::check_permissions();
+/// if !permissions.can_log() {
+///     eprintln!("kernel logging is unavailable: {}", permissions);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Permissions {
+    /// Device checked
+    pub device: PathBuf,
+    /// Whether the device can be opened for writing
+    pub write: bool,
+    /// Whether the device can be opened for reading
+    pub read: bool,
+    /// Whether the process holds effective `CAP_SYSLOG` capability, `None` if unknown
+    pub cap_syslog: Option<bool>,
+    /// Whether reading kernel log requires `CAP_SYSLOG`, `kernel.dmesg_restrict` sysctl, `None` if unknown
+    pub dmesg_restrict: Option<bool>,
+    /// Mode of writes to `/dev/kmsg`, `kernel.printk_devkmsg` sysctl, `None` if unknown
+    pub devkmsg: Option<DevkmsgMode>,
+}
+
+impl Permissions {
+    /// Check what the process is permitted to do with a kmsg device
+    pub fn check(device: impl AsRef<Path>) -> Permissions {
+        let device = device.as_ref();
+        Permissions {
+            device: device.to_owned(),
+            write: OpenOptions::new().write(true).open(device).is_ok(),
+            read: OpenOptions::new().read(true).open(device).is_ok(),
+            cap_syslog: fs::read_to_string("/proc/self/status").ok().and_then(|status| parse_cap_syslog(&status)),
+            dmesg_restrict: sysctl::dmesg_restrict().ok(),
+            devkmsg: sysctl::devkmsg_mode().ok(),
+        }
+    }
+
+    /// Check if records written to the device will reach kernel log
+    pub fn can_log(&self) -> bool {
+        self.write && self.devkmsg != Some(DevkmsgMode::Off)
+    }
+}
+
+impl fmt::Display for Permissions {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let device = self.device.display();
+        let mut problems = Vec::new();
+        if !self.write {
+            problems.push(format!("can't open {} for writing, run as root or grant write access to it", device));
+        } else if self.devkmsg == Some(DevkmsgMode::Off) {
+            problems.push(format!("writes to {} are dropped because of kernel.printk_devkmsg=off", device));
+        }
+        if !self.read {
+            if self.dmesg_restrict == Some(true) && self.cap_syslog != Some(true) {
+                problems.push(format!("can't read {}, kernel.dmesg_restrict=1 requires CAP_SYSLOG", device));
+            } else {
+                problems.push(format!("can't open {} for reading", device));
+            }
+        }
+        if problems.is_empty() {
+            write!(f, "{} can be written and read", device)
+        } else {
+            f.write_str(&problems.join("; "))
+        }
+    }
+}
+
+/// Reason records can't be written to kernel log, see [`KernelLog::is_available()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnavailableReason {
+    /// Device doesn't exist, e.g. `/dev` is not mounted yet or in a container
+    Missing,
+    /// Device can't be opened for writing by the process
+    PermissionDenied,
+    /// Writes to `/dev/kmsg` are disabled with `kernel.printk_devkmsg=off` sysctl
+    Disabled,
+    /// Device can't be opened for another reason
+    Io(io::ErrorKind),
+}
+
+impl fmt::Display for UnavailableReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UnavailableReason::Missing => f.write_str("device doesn't exist"),
+            UnavailableReason::PermissionDenied => f.write_str("no permission to write to device"),
+            UnavailableReason::Disabled => f.write_str("writing to /dev/kmsg is disabled by kernel.printk_devkmsg=off"),
+            UnavailableReason::Io(kind) => write!(f, "device can't be opened: {}", io::Error::from(*kind)),
+        }
+    }
+}
+
+impl std::error::Error for UnavailableReason {}
+
+/// Check if device can be opened for writing and writes are not discarded
+///
+/// Sysctl is checked first, as kernel refuses to open `/dev/kmsg` for writing with `EPERM`
+/// when writes are disabled.
+pub(crate) fn check_available(device: &Path) -> Result<(), UnavailableReason> {
+    if device == Path::new(KernelLog::DEFAULT_DEVICE) && sysctl::devkmsg_mode().ok() == Some(DevkmsgMode::Off) {
+        return Err(UnavailableReason::Disabled);
+    }
+    if let Err(err) = OpenOptions::new().write(true).open(device) {
+        return Err(match err.kind() {
+            io::ErrorKind::NotFound => UnavailableReason::Missing,
+            io::ErrorKind::PermissionDenied => UnavailableReason::PermissionDenied,
+            kind => UnavailableReason::Io(kind),
+        });
+    }
+    Ok(())
+}
+
+/// Diagnosis of [`health_check()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// Probe record was written and found in kernel log
+    Verified,
+    /// Probe record was written, but kernel log can't be read to confirm it landed
+    Unverified,
+    /// Probe record was written, but didn't show up in kernel log in time,
+    /// e.g. it was dropped by kernel rate limit or `kernel.printk_devkmsg=off` sysctl
+    Lost,
+    /// Probe record couldn't be written
+    Unavailable(UnavailableReason),
+}
+
+/// Write a uniquely tagged probe record to `/dev/kmsg` and check it shows up in kernel log
+///
+/// Waits up to a second for the record to be read back, if the process may read kernel log.
+/// The probe is an informational `kernlog: health check probe <id>` record.
+pub fn health_check() -> Health {
+    let device = Path::new(KernelLog::DEFAULT_DEVICE);
+    // Reader is opened first, so the probe is among the new records
+    let reader = KmsgReader::open(device).and_then(|mut reader| {
+        reader.seek(KmsgPosition::Newest)?;
+        Ok(reader)
+    });
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let probe = format!("kernlog: health check probe {}-{}", std::process::id(), nanos);
+    if let Err(reason) = check_available(device) {
+        return Health::Unavailable(reason);
+    }
+    let written = OpenOptions::new().write(true).open(device)
+        .and_then(|mut file| file.write_all(format!("<6>{}\n", probe).as_bytes()));
+    if let Err(err) = written {
+        return Health::Unavailable(UnavailableReason::Io(err.kind()));
+    }
+    match reader {
+        Ok(mut reader) => find_probe(&mut reader, &probe, Duration::from_secs(1)),
+        Err(_) => Health::Unverified,
+    }
+}
+
+fn find_probe(reader: &mut KmsgReader, probe: &str, timeout: Duration) -> Health {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        match reader.wait_record_timeout(left) {
+            Ok(Some(record)) if record.message == probe => return Health::Verified,
+            Ok(Some(_)) => continue,
+            Ok(None) => return Health::Lost,
+            Err(_) => return Health::Unverified,
+        }
+    }
+}
+
+/// Check for `CAP_SYSLOG` in `CapEff` line of `/proc/<pid>/status`
+fn parse_cap_syslog(status: &str) -> Option<bool> {
+    let caps = status.lines().find_map(|line| line.strip_prefix("CapEff:"))?;
+    let caps = u64::from_str_radix(caps.trim(), 16).ok()?;
+    Some(caps & 1 << CAP_SYSLOG != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{check_available, check_permissions, health_check, parse_cap_syslog, Health, Permissions, UnavailableReason};
+    use crate::sysctl::DevkmsgMode;
+
+    #[test]
+    fn parse_capabilities() {
+        assert_eq!(parse_cap_syslog("Name:\tinit\nCapEff:\t000001ffffffffff\n"), Some(true));
+        assert_eq!(parse_cap_syslog("CapEff:\t0000000000000000\n"), Some(false));
+        assert_eq!(parse_cap_syslog("Name:\tinit\n"), None);
+    }
+
+    #[test]
+    fn check_availability() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-available", std::process::id()));
+        assert_eq!(check_available(&path), Err(UnavailableReason::Missing));
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(check_available(&path), Ok(()));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(check_available(&std::env::temp_dir()), Err(UnavailableReason::Io(_))));
+    }
+
+    #[test]
+    fn check_health() {
+        let permissions = check_permissions();
+        let health = health_check();
+        if permissions.can_log() && permissions.read {
+            assert_eq!(health, Health::Verified);
+        } else if !permissions.write {
+            assert!(matches!(health, Health::Unavailable(_)));
+        }
+    }
+
+    #[test]
+    fn describe_problems() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-permissions", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let permissions = Permissions::check(&path);
+        assert!(permissions.write && permissions.read);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut permissions = Permissions {
+            device: PathBuf::from("/dev/kmsg"),
+            write: true,
+            read: true,
+            cap_syslog: Some(false),
+            dmesg_restrict: Some(true),
+            devkmsg: Some(DevkmsgMode::Ratelimit),
+        };
+        assert!(permissions.can_log());
+        assert_eq!(permissions.to_string(), "/dev/kmsg can be written and read");
+
+        permissions.read = false;
+        permissions.devkmsg = Some(DevkmsgMode::Off);
+        assert!(!permissions.can_log());
+        assert_eq!(permissions.to_string(), "writes to /dev/kmsg are dropped because of kernel.printk_devkmsg=off; \
+            can't read /dev/kmsg, kernel.dmesg_restrict=1 requires CAP_SYSLOG");
+
+        permissions.write = false;
+        permissions.cap_syslog = Some(true);
+        assert_eq!(permissions.to_string(), "can't open /dev/kmsg for writing, run as root or grant write access to it; \
+            can't open /dev/kmsg for reading");
+    }
+}
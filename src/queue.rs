@@ -0,0 +1,297 @@
+//! Background writer thread with a bounded record queue
+
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::thread;
+use std::time::Duration;
+
+use crate::sync::{Condvar, Mutex, MutexGuard};
+use crate::Shared;
+
+/// What to do with a new record when background writer queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Backpressure {
+    /// Block logging thread until there is room in the queue
+    Block,
+    /// Drop the new record (default)
+    #[default]
+    DropNewest,
+    /// Drop the oldest queued record to make room for the new one
+    DropOldest,
+}
+
+/// Retry policy for transient write failures in background writer thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Retry {
+    /// Number of retries after the first attempt
+    pub(crate) attempts: u32,
+    /// Delay before the first retry, doubled after each one
+    pub(crate) backoff: Duration,
+}
+
+impl Retry {
+    /// Don't retry at all
+    pub(crate) const NONE: Retry = Retry { attempts: 0, backoff: Duration::ZERO };
+
+    /// Run write, retrying it while it fails with `EAGAIN` or `EINTR`
+    fn run(&self, mut write: impl FnMut() -> io::Result<()>) -> io::Result<()> {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            match write() {
+                Err(ref err) if attempt < self.attempts && is_transient(err) => {
+                    thread::sleep(backoff);
+                    backoff = backoff.saturating_mul(2);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn is_transient(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted)
+}
+
+thread_local! {
+    /// Set on writer thread, so records logged from error hook there don't wait for the thread itself
+    static WRITER: Cell<bool> = const { Cell::new(false) };
+}
+
+fn on_writer_thread() -> bool {
+    WRITER.with(Cell::get)
+}
+
+/// Bounded queue of formatted records waiting to be written by the writer thread
+pub(crate) struct Queue {
+    state: Mutex<State>,
+    /// Signalled when records are pushed or the queue is closed
+    ready: Condvar,
+    /// Signalled when writer thread is done with records it took
+    idle: Condvar,
+    /// Signalled when writer thread takes records from the queue
+    space: Condvar,
+    backpressure: Backpressure,
+    /// Number of records dropped because the queue was full
+    dropped: AtomicU64,
+}
+
+struct State {
+    records: VecDeque<Vec<u8>>,
+    capacity: usize,
+    /// Writer thread is writing records taken from the queue
+    busy: bool,
+    closed: bool,
+}
+
+impl Queue {
+    pub(crate) fn new(capacity: usize, backpressure: Backpressure) -> Queue {
+        Queue {
+            state: Mutex::new(State { records: VecDeque::new(), capacity: capacity.max(1), busy: false, closed: false }),
+            ready: Condvar::new(),
+            idle: Condvar::new(),
+            space: Condvar::new(),
+            backpressure,
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock()
+    }
+
+    /// Queue record for writing, handling full queue according to backpressure policy
+    ///
+    /// Records pushed from the writer thread itself are dropped instead of blocking,
+    /// as nothing would make room for them.
+    pub(crate) fn push(&self, record: &[u8]) {
+        let mut state = self.state();
+        while state.records.len() >= state.capacity && !state.closed {
+            match self.backpressure {
+                Backpressure::Block if !on_writer_thread() => state = self.space.wait(state),
+                Backpressure::Block | Backpressure::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                Backpressure::DropOldest => {
+                    state.records.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        state.records.push_back(record.to_vec());
+        self.ready.notify_one();
+    }
+
+    /// Get number of records dropped because the queue was full
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Wait for records and move all of them into empty `batch` at once,
+    /// `false` once the queue is closed
+    fn take(&self, batch: &mut VecDeque<Vec<u8>>) -> bool {
+        let mut state = self.state();
+        state.busy = false;
+        self.idle.notify_all();
+        loop {
+            if state.closed {
+                return false;
+            }
+            if !state.records.is_empty() {
+                // Swap buffers, so both queues keep their memory between batches
+                std::mem::swap(&mut state.records, batch);
+                state.busy = true;
+                self.space.notify_all();
+                return true;
+            }
+            state = self.ready.wait(state);
+        }
+    }
+
+    /// Wait until all queued records are written, returns at once on the writer thread
+    pub(crate) fn flush(&self) {
+        if on_writer_thread() {
+            return;
+        }
+        let mut state = self.state();
+        while !state.closed && (state.busy || !state.records.is_empty()) {
+            state = self.idle.wait(state);
+        }
+    }
+
+    /// Stop writer thread, returning records it hasn't taken yet
+    pub(crate) fn close(&self) -> VecDeque<Vec<u8>> {
+        let mut state = self.state();
+        state.closed = true;
+        self.ready.notify_all();
+        self.idle.notify_all();
+        self.space.notify_all();
+        std::mem::take(&mut state.records)
+    }
+}
+
+/// Spawn writer thread writing records from the queue to the device
+///
+/// On every wakeup it takes all pending records and writes them in a tight loop,
+/// so bursts of records don't cost a context switch each.
+/// Writer thread only holds a weak reference to the logger, and exits once it's dropped.
+#[cfg_attr(not(feature = "io-uring"), allow(unused_variables))]
+pub(crate) fn spawn(queue: Arc<Queue>, shared: Weak<Shared>, io_uring: bool, retry: Retry) -> io::Result<()> {
+    thread::Builder::new().name("kernlog".to_owned()).spawn(move || {
+        WRITER.with(|writer| writer.set(true));
+        // Plain writes are used if io_uring is unavailable or fails
+        #[cfg(feature = "io-uring")]
+        let mut uring = if io_uring { crate::uring::Uring::new().ok() } else { None };
+
+        let mut batch = VecDeque::new();
+        while queue.take(&mut batch) {
+            let shared = match shared.upgrade() {
+                Some(shared) => shared,
+                None => break,
+            };
+            #[cfg(feature = "io-uring")]
+            if let Some(ref mut ring) = uring {
+                let records = batch.make_contiguous();
+                let mut results: Vec<Option<io::Result<()>>> = records.iter().map(|_| None).collect();
+                let mut failed = false;
+                let _ = shared.with_backend(|kmsg| {
+                    match kmsg.file() {
+                        Some(file) => failed = ring.write(file, records, |index, result| results[index] = Some(result)).is_err(),
+                        None => failed = true,
+                    }
+                    Ok(())
+                }, || false);
+                if failed {
+                    uring = None;
+                }
+                // Records are settled with device unlocked, as error hook and fallback may log,
+                // and ones the ring didn't get to are written as usual
+                for (record, result) in batch.drain(..).zip(results) {
+                    let result = result.unwrap_or_else(|| retry.run(|| shared.write(&record)));
+                    shared.settle(&record, result);
+                }
+                continue;
+            }
+            for record in batch.drain(..) {
+                let result = retry.run(|| shared.write(&record));
+                shared.settle(&record, result);
+            }
+        }
+    })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io;
+    use std::time::Duration;
+
+    use super::{Backpressure, Queue, Retry};
+
+    #[test]
+    fn shed_records_when_full() {
+        let queue = Queue::new(2, Backpressure::DropNewest);
+        for record in [&b"1"[..], b"2", b"3"] {
+            queue.push(record);
+        }
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queue.close(), [b"1".to_vec(), b"2".to_vec()]);
+
+        let queue = Queue::new(2, Backpressure::DropOldest);
+        for record in [&b"1"[..], b"2", b"3", b"4"] {
+            queue.push(record);
+        }
+        assert_eq!(queue.dropped(), 2);
+        assert_eq!(queue.close(), [b"3".to_vec(), b"4".to_vec()]);
+    }
+
+    #[test]
+    fn take_all_pending() {
+        let queue = Queue::new(4, Backpressure::DropNewest);
+        queue.push(b"1");
+        queue.push(b"2");
+
+        let mut batch = VecDeque::new();
+        assert!(queue.take(&mut batch));
+        assert_eq!(batch, [b"1".to_vec(), b"2".to_vec()]);
+        assert!(queue.close().is_empty());
+        batch.clear();
+        assert!(!queue.take(&mut batch));
+    }
+
+    #[test]
+    fn retry_transient_errors() {
+        let retry = Retry { attempts: 3, backoff: Duration::from_millis(1) };
+        let mut calls = 0;
+        let result = retry.run(|| {
+            calls += 1;
+            if calls < 3 { Err(io::ErrorKind::WouldBlock.into()) } else { Ok(()) }
+        });
+        assert!(result.is_ok());
+        assert_eq!(calls, 3);
+
+        calls = 0;
+        let result = retry.run(|| {
+            calls += 1;
+            Err(io::ErrorKind::Interrupted.into())
+        });
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert_eq!(calls, 4);
+
+        calls = 0;
+        let result = retry.run(|| {
+            calls += 1;
+            Err(io::ErrorKind::NotFound.into())
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+}
@@ -0,0 +1,512 @@
+//! Rate limiting and sampling of log records
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use log::{Level, LevelFilter, Record};
+
+use crate::filter;
+use crate::sync::Mutex;
+
+/// Token bucket rate limit: up to `burst` records at once, refilled at `burst` records per `interval`
+///
+/// Parsed from strings like `5/s`, `100/m` or `10/5s`, see [`RateLimit::from_str()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(try_from = "String"))]
+pub struct RateLimit {
+    burst: u32,
+    interval: Duration,
+}
+
+impl RateLimit {
+    /// Pass up to `burst` records per `interval`
+    pub fn new(burst: u32, interval: Duration) -> RateLimit {
+        RateLimit { burst, interval }
+    }
+
+    /// Pass up to `rate` records per second
+    pub fn per_second(rate: u32) -> RateLimit {
+        RateLimit::new(rate, Duration::from_secs(1))
+    }
+
+    /// Get number of records passed at once
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+
+    /// Get time to refill the whole burst
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Get refill rate in records per second
+    fn rate(&self) -> f64 {
+        match self.interval.as_secs_f64() {
+            secs if secs > 0.0 => f64::from(self.burst) / secs,
+            _ => f64::INFINITY,
+        }
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = io::Error;
+
+    /// Parse `<burst>/<interval>`, where interval is `s`, `m` or `h`, optionally prefixed
+    /// with a number, or a number of milliseconds with `ms` suffix, e.g. `5/s` or `10/500ms`
+    fn from_str(spec: &str) -> io::Result<RateLimit> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid rate limit {:?}", spec));
+        let (burst, interval) = spec.trim().split_once('/').ok_or_else(invalid)?;
+        let burst = burst.trim().parse().map_err(|_| invalid())?;
+        let interval = interval.trim();
+        let split = interval.find(|c: char| !c.is_ascii_digit()).ok_or_else(invalid)?;
+        let count = match &interval[..split] {
+            "" => 1,
+            count => count.parse().map_err(|_| invalid())?,
+        };
+        let unit = match &interval[split..] {
+            "ms" => Duration::from_millis(1),
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            "h" => Duration::from_secs(3600),
+            _ => return Err(invalid()),
+        };
+        Ok(RateLimit::new(burst, unit * count))
+    }
+}
+
+impl TryFrom<String> for RateLimit {
+    type Error = io::Error;
+
+    fn try_from(spec: String) -> io::Result<RateLimit> {
+        spec.parse()
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}ms", self.burst, self.interval.as_millis())
+    }
+}
+
+/// Records suppressed before the one passed by rate limits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Suppressed<'a> {
+    /// Records from the same call site, with level and target of the first one from there
+    CallSite { count: u64, file: &'a str, line: u32, level: Level, target: &'a str },
+    /// Records of a level, or of any level if it's `None`, over `period` since the first one
+    Period { level: Option<Level>, count: u64, period: Duration },
+    /// Records of a target and its submodules, over `period` since the first one
+    Target { target: &'a str, count: u64, period: Duration },
+}
+
+/// All enabled rate limits, checked in turn
+pub(crate) struct Limits {
+    /// Records at or above this level are never suppressed
+    pub(crate) exempt: LevelFilter,
+    /// Keep one in that many records of each level, indexed by level, all if it's 0 or 1
+    pub(crate) sampling: [u32; 5],
+    pub(crate) call_sites: Option<CallSites>,
+    /// Limits of targets, sorted by target length, longest first
+    pub(crate) targets: Vec<(String, Budget)>,
+    /// Limits of each level, indexed by level
+    pub(crate) levels: [Option<Budget>; 5],
+    pub(crate) global: Option<Budget>,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            exempt: LevelFilter::Error,
+            sampling: [1; 5],
+            call_sites: None,
+            targets: Vec::new(),
+            levels: Default::default(),
+            global: None,
+        }
+    }
+}
+
+impl Limits {
+    /// Check record against all rate limits, returning if it should be passed
+    ///
+    /// If it should, records suppressed before it are reported first, and it takes a token
+    /// from every budget it counts against. Otherwise no tokens are taken, and it's counted
+    /// as suppressed by the first budget which has none left, in call site, target, level
+    /// and global order.
+    ///
+    /// Records dropped by sampling are not counted as suppressed. Exempt records are passed
+    /// without consuming any budget.
+    pub(crate) fn check<'a>(&'a self, record: &Record<'a>, now: Instant, mut report: impl FnMut(Suppressed<'a>)) -> bool {
+        if record.level() <= self.exempt {
+            return true;
+        }
+        if !sample(self.sampling[record.level() as usize - 1]) {
+            return false;
+        }
+
+        let site = match (&self.call_sites, record.file(), record.line()) {
+            (Some(sites), Some(file), Some(line)) => Some((sites, file, line)),
+            _ => None,
+        };
+        let target = self.targets.iter().find(|(target, _)| filter::matches(target, record.target()));
+        let level = record.level();
+        let taken = {
+            // All budgets are locked at once, always in the same order, so that the record
+            // takes tokens from all of them or from none
+            let mut sites = site.map(|(sites, file, line)| (sites, sites.buckets.lock(), file, line));
+            let mut target = target.map(|(_, budget)| (&budget.limit, budget.bucket.lock()));
+            let mut level = self.levels[level as usize - 1].as_ref().map(|budget| (&budget.limit, budget.bucket.lock()));
+            let mut global = self.global.as_ref().map(|budget| (&budget.limit, budget.bucket.lock()));
+            let mut buckets: [Option<(&RateLimit, &mut Bucket)>; 4] = [
+                sites.as_mut().map(|(sites, buckets, file, line)| (&sites.limit, CallSites::bucket(buckets, &sites.limit, record, file, *line, now))),
+                target.as_mut().map(|(limit, bucket)| (*limit, &mut **bucket)),
+                level.as_mut().map(|(limit, bucket)| (*limit, &mut **bucket)),
+                global.as_mut().map(|(limit, bucket)| (*limit, &mut **bucket)),
+            ];
+            for (index, bucket) in buckets.iter_mut().enumerate() {
+                if let Some((limit, bucket)) = bucket {
+                    if !bucket.refill(limit, now) {
+                        bucket.suppress(now);
+                        if let (0, Some((sites, _, _))) = (index, site) {
+                            sites.pending.fetch_add(1, Ordering::Relaxed);
+                        }
+                        return false;
+                    }
+                }
+            }
+            buckets.map(|bucket| bucket.map(|(_, bucket)| bucket.take(now)))
+        };
+
+        // Summaries are reported with budgets unlocked, as they are logged
+        let [site_taken, target_taken, level_taken, global_taken] = taken.map(|taken| taken.filter(|&(count, _)| count > 0));
+        if let (Some((target, _)), Some((count, period))) = (target, target_taken) {
+            report(Suppressed::Target { target, count, period });
+        }
+        for (level, taken) in [(Some(level), level_taken), (None, global_taken)] {
+            if let Some((count, period)) = taken {
+                report(Suppressed::Period { level, count, period });
+            }
+        }
+        if let (Some((sites, file, line)), Some((count, _))) = (site, site_taken) {
+            sites.pending.fetch_sub(count, Ordering::Relaxed);
+            report(Suppressed::CallSite { count, file, line, level, target: record.target() });
+        }
+        true
+    }
+
+    /// Report records suppressed at call sites whose refill interval has passed since the first
+    /// of them, or at all call sites if `all` is set, without waiting for them to pass a record again
+    pub(crate) fn flush_call_sites(&self, now: Instant, all: bool, mut report: impl FnMut(Suppressed<'_>)) {
+        let sites = match self.call_sites {
+            Some(ref sites) if sites.pending.load(Ordering::Relaxed) > 0 => sites,
+            _ => return,
+        };
+        let mut expired = Vec::new();
+        for (file, lines) in sites.buckets.lock().iter_mut() {
+            for (&line, site) in lines.iter_mut() {
+                let bucket = &mut site.bucket;
+                if bucket.suppressed > 0 && (all || now.saturating_duration_since(bucket.since) >= sites.limit.interval) {
+                    let count = std::mem::take(&mut bucket.suppressed);
+                    sites.pending.fetch_sub(count, Ordering::Relaxed);
+                    expired.push((file.clone(), line, site.level, site.target.clone(), count));
+                }
+            }
+        }
+        // Summaries are reported with call sites unlocked, as they are logged
+        for (file, line, level, target, count) in &expired {
+            report(Suppressed::CallSite { count: *count, file, line: *line, level: *level, target });
+        }
+    }
+}
+
+thread_local! {
+    /// State of xorshift generator used for sampling, so that threads don't contend on it
+    static RANDOM: Cell<u64> = Cell::new(RandomState::new().build_hasher().finish() | 1);
+}
+
+/// Randomly decide to keep a record with probability of one in `one_in`
+fn sample(one_in: u32) -> bool {
+    if one_in <= 1 {
+        return true;
+    }
+    RANDOM.with(|random| {
+        let mut x = random.get();
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        random.set(x);
+        x % u64::from(one_in) == 0
+    })
+}
+
+/// Token bucket state with number of records suppressed since the last passed one
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    suppressed: u64,
+    /// Time the first of suppressed records came
+    since: Instant,
+}
+
+impl Bucket {
+    fn new(limit: &RateLimit, now: Instant) -> Bucket {
+        Bucket { tokens: f64::from(limit.burst), last: now, suppressed: 0, since: now }
+    }
+
+    /// Refill tokens for the time passed, returning if there's a token for a record
+    fn refill(&mut self, limit: &RateLimit, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = now;
+        self.tokens = (self.tokens + elapsed * limit.rate()).min(f64::from(limit.burst));
+        self.tokens >= 1.0
+    }
+
+    /// Take a refilled token for a record, returning number of records suppressed before it
+    /// along with time passed since the first of them
+    fn take(&mut self, now: Instant) -> (u64, Duration) {
+        self.tokens -= 1.0;
+        (std::mem::take(&mut self.suppressed), now.saturating_duration_since(self.since))
+    }
+
+    /// Count record suppressed for lack of tokens
+    fn suppress(&mut self, now: Instant) {
+        if self.suppressed == 0 {
+            self.since = now;
+        }
+        self.suppressed += 1;
+    }
+}
+
+/// Sort target limits so that the most specific target matches first
+pub(crate) fn sort_targets(targets: &mut [(String, Budget)]) {
+    targets.sort_by_key(|(target, _)| std::cmp::Reverse(target.len()));
+}
+
+/// Rate limit shared by a group of records, like all records of some level
+pub(crate) struct Budget {
+    limit: RateLimit,
+    bucket: Mutex<Bucket>,
+}
+
+impl Budget {
+    pub(crate) fn new(limit: RateLimit) -> Budget {
+        Budget { limit, bucket: Mutex::new(Bucket::new(&limit, Instant::now())) }
+    }
+}
+
+/// Rate limits for each call site, keyed by source file and line
+pub(crate) struct CallSites {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<String, HashMap<u32, Site>>>,
+    /// Number of suppressed records not reported yet, to check it without locking
+    pending: AtomicU64,
+}
+
+/// Bucket of a call site, with level and target to report its suppressed records with
+struct Site {
+    bucket: Bucket,
+    level: Level,
+    target: String,
+}
+
+impl CallSites {
+    pub(crate) fn new(limit: RateLimit) -> CallSites {
+        CallSites { limit, buckets: Mutex::new(HashMap::new()), pending: AtomicU64::new(0) }
+    }
+
+    /// Get bucket of call site, creating it if it's the first record from there
+    fn bucket<'b>(buckets: &'b mut HashMap<String, HashMap<u32, Site>>, limit: &RateLimit, record: &Record, file: &str, line: u32, now: Instant) -> &'b mut Bucket {
+        // Files are looked up first, so their names are copied only once
+        if !buckets.contains_key(file) {
+            buckets.insert(file.to_owned(), HashMap::new());
+        }
+        let lines = buckets.get_mut(file).expect("call site file was just inserted");
+        let site = lines.entry(line).or_insert_with(|| Site {
+            bucket: Bucket::new(limit, now),
+            level: record.level(),
+            target: record.target().to_owned(),
+        });
+        &mut site.bucket
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use log::{Level, LevelFilter, Record};
+    use super::{sample, sort_targets, Budget, CallSites, Limits, RateLimit, Suppressed};
+
+    #[test]
+    fn parse_limits() {
+        assert_eq!("5/s".parse::<RateLimit>().unwrap(), RateLimit::per_second(5));
+        assert_eq!("100/m".parse::<RateLimit>().unwrap(), RateLimit::new(100, Duration::from_secs(60)));
+        assert_eq!(" 10 / 5s".parse::<RateLimit>().unwrap(), RateLimit::new(10, Duration::from_secs(5)));
+        assert_eq!("1/250ms".parse::<RateLimit>().unwrap(), RateLimit::new(1, Duration::from_millis(250)));
+        assert!("5".parse::<RateLimit>().is_err());
+        assert!("5/d".parse::<RateLimit>().is_err());
+        assert!("x/s".parse::<RateLimit>().is_err());
+    }
+
+    #[test]
+    fn limit_call_sites() {
+        let limits = Limits { call_sites: Some(CallSites::new(RateLimit::new(2, Duration::from_secs(1)))), ..Limits::default() };
+        let first = Record::builder().file(Some("main.rs")).line(Some(10)).args(format_args!("first")).build();
+        let second = Record::builder().file(Some("main.rs")).line(Some(11)).args(format_args!("second")).build();
+        let start = Instant::now();
+        let mut reported = Vec::new();
+        assert!(limits.check(&first, start, |suppressed| reported.push(suppressed)));
+        assert!(limits.check(&first, start, |suppressed| reported.push(suppressed)));
+        assert!(!limits.check(&first, start, |suppressed| reported.push(suppressed)));
+        assert!(!limits.check(&first, start, |suppressed| reported.push(suppressed)));
+        assert!(limits.check(&second, start, |suppressed| reported.push(suppressed)));
+        assert!(reported.is_empty());
+
+        let later = start + Duration::from_millis(500);
+        assert!(limits.check(&first, later, |suppressed| reported.push(suppressed)));
+        assert!(!limits.check(&first, later, |suppressed| reported.push(suppressed)));
+        assert_eq!(reported, [Suppressed::CallSite { count: 2, file: "main.rs", line: 10, level: Level::Info, target: "" }]);
+    }
+
+    #[test]
+    fn flush_call_sites() {
+        let limits = Limits { call_sites: Some(CallSites::new(RateLimit::new(1, Duration::from_secs(1)))), ..Limits::default() };
+        let first = Record::builder().target("net").file(Some("main.rs")).line(Some(10)).args(format_args!("first")).build();
+        let second = Record::builder().level(Level::Warn).file(Some("main.rs")).line(Some(11)).args(format_args!("second")).build();
+        let start = Instant::now();
+        let mut reported = Vec::new();
+        let mut collect = |suppressed: Suppressed<'_>| match suppressed {
+            Suppressed::CallSite { count, file, line, level, target } => {
+                reported.push((count, file.to_owned(), line, level, target.to_owned()))
+            }
+            _ => panic!("only call sites are flushed"),
+        };
+        for _ in 0..3 {
+            limits.check(&first, start, |_| ());
+        }
+        limits.flush_call_sites(start + Duration::from_millis(500), false, |_| panic!("interval hasn't passed yet"));
+        for _ in 0..2 {
+            limits.check(&second, start + Duration::from_millis(600), |_| ());
+        }
+
+        let later = start + Duration::from_millis(1500);
+        limits.flush_call_sites(later, false, &mut collect);
+        limits.flush_call_sites(later, true, &mut collect);
+        limits.flush_call_sites(later, true, |_| panic!("everything is reported"));
+        assert_eq!(reported, [
+            (2, "main.rs".to_owned(), 10, Level::Info, "net".to_owned()),
+            (1, "main.rs".to_owned(), 11, Level::Warn, "".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn take_tokens_only_when_passed() {
+        let mut limits = Limits {
+            call_sites: Some(CallSites::new(RateLimit::new(5, Duration::from_secs(1)))),
+            global: Some(Budget::new(RateLimit::new(1, Duration::from_secs(1)))),
+            ..Limits::default()
+        };
+        limits.targets.push(("udev".to_owned(), Budget::new(RateLimit::new(2, Duration::from_secs(1)))));
+        let record = Record::builder().target("udev").file(Some("main.rs")).line(Some(10)).args(format_args!("spam")).build();
+        let start = Instant::now();
+        let mut reported = Vec::new();
+        assert!(limits.check(&record, start, |suppressed| reported.push(suppressed)));
+        // Rejected by global budget, leaving call site and target ones untouched
+        assert!(!limits.check(&record, start, |suppressed| reported.push(suppressed)));
+        assert!(!limits.check(&record, start, |suppressed| reported.push(suppressed)));
+
+        assert!(limits.check(&record, start + Duration::from_secs(1), |suppressed| reported.push(suppressed)));
+        assert_eq!(reported, [Suppressed::Period { level: None, count: 2, period: Duration::from_secs(1) }]);
+        assert!(limits.check(&record, start + Duration::from_secs(2), |suppressed| reported.push(suppressed)));
+        assert_eq!(reported.len(), 1);
+    }
+
+    #[test]
+    fn limit_globally() {
+        let limits = Limits { global: Some(Budget::new(RateLimit::new(1, Duration::from_secs(10)))), ..Limits::default() };
+        let record = Record::builder().args(format_args!("spam")).build();
+        let start = Instant::now();
+        let mut reported = Vec::new();
+        assert!(limits.check(&record, start, |suppressed| reported.push(suppressed)));
+        assert!(!limits.check(&record, start + Duration::from_secs(1), |suppressed| reported.push(suppressed)));
+        assert!(!limits.check(&record, start + Duration::from_secs(2), |suppressed| reported.push(suppressed)));
+        assert!(reported.is_empty());
+
+        assert!(limits.check(&record, start + Duration::from_secs(11), |suppressed| reported.push(suppressed)));
+        assert_eq!(reported, [Suppressed::Period { level: None, count: 2, period: Duration::from_secs(10) }]);
+    }
+
+    #[test]
+    fn sample_records() {
+        assert!((0..100).all(|_| sample(1)));
+        let kept = (0..10000).filter(|_| sample(10)).count();
+        assert!(kept > 500 && kept < 1500, "kept {} of 10000", kept);
+
+        let mut limits = Limits::default();
+        limits.sampling[Level::Trace as usize - 1] = u32::MAX;
+        let trace = Record::builder().level(Level::Trace).args(format_args!("trace")).build();
+        let warn = Record::builder().level(Level::Warn).args(format_args!("warn")).build();
+        assert!((0..100).filter(|_| limits.check(&trace, Instant::now(), |_| ())).count() < 2);
+        assert!((0..100).all(|_| limits.check(&warn, Instant::now(), |_| ())));
+    }
+
+    #[test]
+    fn exempt_severe_records() {
+        let mut limits = Limits { global: Some(Budget::new(RateLimit::new(0, Duration::from_secs(1)))), ..Limits::default() };
+        let error = Record::builder().level(Level::Error).args(format_args!("error")).build();
+        let warn = Record::builder().level(Level::Warn).args(format_args!("warn")).build();
+        assert!(limits.check(&error, Instant::now(), |_| ()));
+        assert!(!limits.check(&warn, Instant::now(), |_| ()));
+
+        limits.exempt = LevelFilter::Off;
+        assert!(!limits.check(&error, Instant::now(), |_| ()));
+        limits.exempt = LevelFilter::Warn;
+        assert!(limits.check(&warn, Instant::now(), |_| ()));
+    }
+
+    #[test]
+    fn limit_targets() {
+        let mut limits = Limits::default();
+        limits.targets.push(("udev".to_owned(), Budget::new(RateLimit::new(1, Duration::from_secs(1)))));
+        limits.targets.push(("udev::rules".to_owned(), Budget::new(RateLimit::new(2, Duration::from_secs(1)))));
+        sort_targets(&mut limits.targets);
+        let udev = Record::builder().target("udev::net").args(format_args!("udev")).build();
+        let rules = Record::builder().target("udev::rules").args(format_args!("rules")).build();
+        let other = Record::builder().target("udevadm").args(format_args!("other")).build();
+        let start = Instant::now();
+        let mut reported = Vec::new();
+        assert!(limits.check(&udev, start, |suppressed| reported.push(suppressed)));
+        assert!(!limits.check(&udev, start, |suppressed| reported.push(suppressed)));
+        assert!(limits.check(&rules, start, |suppressed| reported.push(suppressed)));
+        assert!(limits.check(&rules, start, |suppressed| reported.push(suppressed)));
+        assert!(!limits.check(&rules, start, |suppressed| reported.push(suppressed)));
+        assert!((0..10).all(|_| limits.check(&other, start, |suppressed| reported.push(suppressed))));
+        assert!(limits.check(&udev, start + Duration::from_secs(1), |suppressed| reported.push(suppressed)));
+        assert_eq!(reported, [Suppressed::Target { target: "udev", count: 1, period: Duration::from_secs(1) }]);
+    }
+
+    #[test]
+    fn limit_levels() {
+        let mut limits = Limits::default();
+        limits.levels[Level::Info as usize - 1] = Some(Budget::new(RateLimit::new(1, Duration::from_secs(1))));
+        let info = Record::builder().level(Level::Info).args(format_args!("info")).build();
+        let error = Record::builder().level(Level::Error).args(format_args!("error")).build();
+        let start = Instant::now();
+        let mut reported = Vec::new();
+        assert!(limits.check(&info, start, |suppressed| reported.push(suppressed)));
+        assert!(!limits.check(&info, start, |suppressed| reported.push(suppressed)));
+        assert!(limits.check(&error, start, |suppressed| reported.push(suppressed)));
+        assert!(limits.check(&info, start + Duration::from_secs(1), |suppressed| reported.push(suppressed)));
+        assert_eq!(reported, [Suppressed::Period { level: Some(Level::Info), count: 1, period: Duration::from_secs(1) }]);
+    }
+}
@@ -0,0 +1,578 @@
+//! Reading and parsing records from `/dev/kmsg`
+
+use std::fs::{File, OpenOptions};
+use std::convert::TryFrom;
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::Level;
+
+use crate::level;
+use crate::{Facility, KernelLog, Severity};
+
+/// Maximum size of a record returned by a single `read(2)`, `CONSOLE_EXT_LOG_MAX` in kernel
+const MAX_RECORD_SIZE: usize = 8192;
+
+/// Kernel log record, as read from `/dev/kmsg`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KmsgRecord {
+    /// Printk priority (severity), from `0` (`KERN_EMERG`) to `7` (`KERN_DEBUG`)
+    pub priority: u8,
+    /// Syslog facility number, `0` for kernel messages and `1` for records written from userspace
+    pub facility: u8,
+    /// Record sequence number
+    pub sequence: u64,
+    /// Time since boot the record was logged at, by `CLOCK_MONOTONIC`
+    pub timestamp: Duration,
+    /// Continuation flag: `-` for a complete record, `c` for the start of a continued line
+    /// and `+` for its continuation
+    pub flags: char,
+    /// Record message, with escaped non-printable characters restored
+    pub message: String,
+    /// Dictionary fields, like `SUBSYSTEM=pci` or `DEVICE=+pci:0000:00:02.0`, in order given
+    pub dictionary: Vec<(String, String)>,
+}
+
+impl KmsgRecord {
+    /// Parse record in `/dev/kmsg` format: `<pri>,<seq>,<usec>,<flags>[,...];<message>`
+    /// followed by dictionary lines of ` KEY=value` form
+    pub fn parse(record: &[u8]) -> io::Result<KmsgRecord> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid kmsg record");
+        let split = record.iter().position(|&byte| byte == b';').ok_or_else(invalid)?;
+        let header = std::str::from_utf8(&record[..split]).map_err(|_| invalid())?;
+        let mut fields = header.split(',');
+        let mut field = || fields.next().ok_or_else(invalid);
+        let prival: u32 = field()?.parse().map_err(|_| invalid())?;
+        let sequence = field()?.parse().map_err(|_| invalid())?;
+        let timestamp = Duration::from_micros(field()?.parse().map_err(|_| invalid())?);
+        let flags = field()?.chars().next().unwrap_or('-');
+
+        let mut lines = record[split + 1..].split(|&byte| byte == b'\n');
+        let message = unescape(lines.next().unwrap_or_default());
+        let dictionary = lines
+            .filter_map(|line| line.strip_prefix(b" "))
+            .filter_map(|line| {
+                let line = unescape(line);
+                let (key, value) = line.split_once('=')?;
+                Some((key.to_owned(), value.to_owned()))
+            })
+            .collect();
+        Ok(KmsgRecord {
+            priority: (prival & 7) as u8,
+            facility: (prival >> 3) as u8,
+            sequence,
+            timestamp,
+            flags,
+            message,
+            dictionary,
+        })
+    }
+
+    /// Get record severity
+    pub fn severity(&self) -> Severity {
+        level::severity(self.priority)
+    }
+
+    /// Get wall clock time the record was logged at, see [`boot_time()`]
+    pub fn system_time(&self) -> io::Result<SystemTime> {
+        Ok(self.system_time_with(boot_time()?))
+    }
+
+    /// Get wall clock time the record was logged at, given system boot time
+    ///
+    /// Useful for converting many records with [`boot_time()`] looked up once.
+    pub fn system_time_with(&self, boot_time: SystemTime) -> SystemTime {
+        boot_time + self.timestamp
+    }
+
+    /// Get log level of the record, with severities above `KERN_ERR` mapped to `Error`
+    ///
+    /// This is the reverse of the mapping used for writing records, so records written
+    /// by [`KernelLog`] get their original levels back.
+    pub fn level(&self) -> Level {
+        level::level(self.priority)
+    }
+
+    /// Get value of a dictionary field, the first one if there are several
+    pub fn field(&self, key: &str) -> Option<&str> {
+        self.dictionary.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str())
+    }
+}
+
+/// Get wall clock time the system booted at
+///
+/// This is current `CLOCK_REALTIME` minus `CLOCK_BOOTTIME`, like `dmesg --ctime` does,
+/// so it follows wall clock adjustments. Record timestamps don't include time spent
+/// in suspend, so records logged after resume get converted to earlier times than actual.
+pub fn boot_time() -> io::Result<SystemTime> {
+    let since_epoch = clock(libc::CLOCK_REALTIME)?.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let since_boot = clock(libc::CLOCK_BOOTTIME)?.duration_since(UNIX_EPOCH).unwrap_or_default();
+    Ok(UNIX_EPOCH + since_epoch.saturating_sub(since_boot))
+}
+
+/// Read a clock as time since `UNIX_EPOCH`
+fn clock(clock: libc::clockid_t) -> io::Result<SystemTime> {
+    let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    if unsafe { libc::clock_gettime(clock, &mut time) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(UNIX_EPOCH + Duration::new(time.tv_sec as u64, time.tv_nsec as u32))
+}
+
+/// Restore bytes kernel escaped as `\xNN`
+fn unescape(text: &[u8]) -> String {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut rest = text;
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = match tail {
+            [b'x', high, low, ..] if byte == b'\\' => hex(*high).zip(hex(*low)).map(|(high, low)| high << 4 | low),
+            _ => None,
+        };
+        match escaped {
+            Some(escaped) => {
+                bytes.push(escaped);
+                rest = &tail[3..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn hex(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|digit| digit as u8)
+}
+
+/// Position in kernel ring buffer to read records from, see [`KmsgReader::seek()`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KmsgPosition {
+    /// The oldest record still in the ring buffer, `SEEK_SET`
+    Oldest,
+    /// After the newest record, so that only records logged from now on are read, `SEEK_END`
+    Newest,
+    /// The first record after the last buffer clear, like by `dmesg --clear`, `SEEK_DATA`
+    AfterClear,
+}
+
+/// Reader of kernel log records from `/dev/kmsg`
+///
+/// Each read returns one record, starting with the oldest one still in the ring buffer,
+/// unless the reader is moved with [`KmsgReader::seek()`].
+/// Reads don't block, use [`KmsgReader::follow()`] to wait for new records like `dmesg --follow`.
+/// The reader itself is an endless iterator like the one returned by `follow()`,
+/// while [`KmsgReader::drain()`] stops once all records logged so far are read.
+///
+/// ```rust,no_run
+/// let mut reader = kernlog::KmsgReader::new().unwrap();
+/// while let Some(record) = reader.read_record().unwrap() {
+///     println!("[{:?}] {}", record.timestamp, record.message);
+/// }
+/// ```
+///
+/// If the kernel overwrites records before they are read, the reader skips to the oldest
+/// record still available, and [`KmsgReader::gap()`] tells how many were lost.
+///
+/// Records can be filtered by severity and facility, so that only wanted ones are returned:
+///
+/// ```rust,no_run
+/// use kernlog::{Facility, KmsgReader, Severity};
+///
+/// let mut reader = KmsgReader::new().unwrap()
+///     .min_severity(Severity::Err)
+///     .facilities([Facility::Kern]);
+/// for record in reader.drain() {
+///     println!("{}", record.unwrap().message);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct KmsgReader {
+    file: File,
+    buf: Box<[u8]>,
+    /// Sequence number of the last record read
+    last: Option<u64>,
+    /// Whether kernel reported records overwritten since the last record read
+    overrun: bool,
+    gap: u64,
+    skipped: u64,
+    min_severity: Severity,
+    /// Bit mask of facility numbers to return records of, all if not set
+    facilities: Option<u32>,
+}
+
+impl KmsgReader {
+    /// Open `/dev/kmsg` for reading
+    pub fn new() -> io::Result<KmsgReader> {
+        KmsgReader::open(KernelLog::DEFAULT_DEVICE)
+    }
+
+    /// Open a kmsg device for reading
+    ///
+    /// Reading requires `CAP_SYSLOG`, unless `kernel.dmesg_restrict` sysctl is off.
+    pub fn open(device: impl AsRef<Path>) -> io::Result<KmsgReader> {
+        let file = OpenOptions::new().read(true).custom_flags(libc::O_NONBLOCK).open(device)?;
+        Ok(KmsgReader {
+            file,
+            buf: vec![0; MAX_RECORD_SIZE].into_boxed_slice(),
+            last: None,
+            overrun: false,
+            gap: 0,
+            skipped: 0,
+            min_severity: Severity::Debug,
+            facilities: None,
+        })
+    }
+
+    /// Return only records at least as severe as given, all by default
+    pub fn min_severity(mut self, severity: Severity) -> KmsgReader {
+        self.min_severity = severity;
+        self
+    }
+
+    /// Return only records of given facilities, all by default
+    ///
+    /// Records logged by kernel itself are [`Facility::Kern`] ones, while records written
+    /// to `/dev/kmsg` from userspace are [`Facility::User`] ones, unless they set another facility.
+    pub fn facilities(mut self, facilities: impl IntoIterator<Item = Facility>) -> KmsgReader {
+        self.facilities = Some(facilities.into_iter().fold(0, |mask, facility| mask | 1 << facility as u32));
+        self
+    }
+
+    /// Get number of records lost to ring buffer overrun right before the last record read
+    ///
+    /// Overrun before the first record read after opening or seeking is not counted,
+    /// as there is no record to count from.
+    pub fn gap(&self) -> u64 {
+        self.gap
+    }
+
+    /// Get total number of records lost to ring buffer overrun
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Move the reader to a position in the ring buffer
+    pub fn seek(&mut self, position: KmsgPosition) -> io::Result<()> {
+        let whence = match position {
+            KmsgPosition::Oldest => libc::SEEK_SET,
+            KmsgPosition::Newest => libc::SEEK_END,
+            KmsgPosition::AfterClear => libc::SEEK_DATA,
+        };
+        // Kernel only supports zero offsets
+        if unsafe { libc::lseek(self.file.as_raw_fd(), 0, whence) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        self.last = None;
+        self.overrun = false;
+        self.gap = 0;
+        Ok(())
+    }
+
+    /// Read and parse the next record, returning `None` if there are no more records yet
+    pub fn read_record(&mut self) -> io::Result<Option<KmsgRecord>> {
+        loop {
+            match self.file.read(&mut self.buf) {
+                Ok(0) => return Ok(None),
+                Ok(len) => {
+                    let record = self.track(KmsgRecord::parse(&self.buf[..len])?);
+                    if self.wanted(&record) {
+                        return Ok(Some(record));
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                // Kernel already moved on to the oldest record available
+                Err(err) if err.raw_os_error() == Some(libc::EPIPE) => self.overrun = true,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Read the next record, waiting for kernel to log one if there are none yet
+    pub fn wait_record(&mut self) -> io::Result<KmsgRecord> {
+        loop {
+            if let Some(record) = self.read_record()? {
+                return Ok(record);
+            }
+            self.poll(None)?;
+        }
+    }
+
+    /// Read the next record, waiting up to `timeout` for kernel to log one if there are none yet
+    pub fn wait_record_timeout(&mut self, timeout: Duration) -> io::Result<Option<KmsgRecord>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(record) = self.read_record()? {
+                return Ok(Some(record));
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() || !self.poll(Some(left))? {
+                return Ok(None);
+            }
+        }
+    }
+
+    /// Check if record passes severity and facility filters
+    fn wanted(&self, record: &KmsgRecord) -> bool {
+        let facility = match self.facilities {
+            Some(mask) => record.facility < 32 && mask & 1 << record.facility != 0,
+            None => true,
+        };
+        facility && record.severity() <= self.min_severity
+    }
+
+    /// Count records lost before the one just read
+    fn track(&mut self, record: KmsgRecord) -> KmsgRecord {
+        self.gap = match self.last {
+            Some(last) if self.overrun => record.sequence.saturating_sub(last.saturating_add(1)),
+            _ => 0,
+        };
+        self.skipped += self.gap;
+        self.last = Some(record.sequence);
+        self.overrun = false;
+        record
+    }
+
+    /// Iterate over records forever, waiting for new ones once all records are read
+    ///
+    /// ```rust,no_run
+    /// let mut reader = kernlog::KmsgReader::new().unwrap();
+    /// for record in reader.follow() {
+    ///     println!("{}", record.unwrap().message);
+    /// }
+    /// ```
+    pub fn follow(&mut self) -> Follow<'_> {
+        Follow { reader: self }
+    }
+
+    /// Iterate over records logged so far, stopping once there are no more
+    ///
+    /// ```rust,no_run
+    /// let mut reader = kernlog::KmsgReader::new().unwrap();
+    /// let errors = reader
+    ///     .drain()
+    ///     .filter_map(Result::ok)
+    ///     .filter(|record| record.priority <= 3)
+    ///     .count();
+    /// println!("{} errors since boot", errors);
+    /// ```
+    pub fn drain(&mut self) -> Drain<'_> {
+        Drain { reader: self }
+    }
+
+    /// Wait for a record to become readable, returning `false` on timeout
+    fn poll(&self, timeout: Option<Duration>) -> io::Result<bool> {
+        let timeout = timeout.map_or(-1, |timeout| {
+            // Round up, so that short timeouts don't spin
+            let millis = timeout.as_millis() + u128::from(timeout.subsec_nanos() % 1_000_000 > 0);
+            libc::c_int::try_from(millis).unwrap_or(libc::c_int::MAX)
+        });
+        let mut fd = libc::pollfd { fd: self.file.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+        loop {
+            match unsafe { libc::poll(&mut fd, 1, timeout) } {
+                -1 => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                }
+                ready => return Ok(ready > 0),
+            }
+        }
+    }
+}
+
+impl AsFd for KmsgReader {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+impl AsRawFd for KmsgReader {
+    fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+}
+
+/// Endless iterator over kernel log records, returned by [`KmsgReader::follow()`]
+#[derive(Debug)]
+pub struct Follow<'a> {
+    reader: &'a mut KmsgReader,
+}
+
+impl Iterator for Follow<'_> {
+    type Item = io::Result<KmsgRecord>;
+
+    fn next(&mut self) -> Option<io::Result<KmsgRecord>> {
+        Some(self.reader.wait_record())
+    }
+}
+
+impl Iterator for KmsgReader {
+    type Item = io::Result<KmsgRecord>;
+
+    fn next(&mut self) -> Option<io::Result<KmsgRecord>> {
+        Some(self.wait_record())
+    }
+}
+
+/// Iterator over records logged so far, returned by [`KmsgReader::drain()`]
+#[derive(Debug)]
+pub struct Drain<'a> {
+    reader: &'a mut KmsgReader,
+}
+
+impl Iterator for Drain<'_> {
+    type Item = io::Result<KmsgRecord>;
+
+    fn next(&mut self) -> Option<io::Result<KmsgRecord>> {
+        self.reader.read_record().transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use super::{boot_time, KmsgPosition, KmsgReader, KmsgRecord};
+    use crate::{Facility, KernelLog, Severity};
+
+    #[test]
+    fn parse_record() {
+        let record = KmsgRecord::parse(
+            b"14,1234,5678901,c,extra;init: started \\x1b[1mfoo\\x1b[0m\\\\\n SUBSYSTEM=pci\n DEVICE=+pci:0000:00:02.0\n",
+        ).unwrap();
+        assert_eq!(record, KmsgRecord {
+            priority: 6,
+            facility: 1,
+            sequence: 1234,
+            timestamp: Duration::from_micros(5678901),
+            flags: 'c',
+            message: "init: started \x1b[1mfoo\x1b[0m\\\\".to_owned(),
+            dictionary: vec![
+                ("SUBSYSTEM".to_owned(), "pci".to_owned()),
+                ("DEVICE".to_owned(), "+pci:0000:00:02.0".to_owned()),
+            ],
+        });
+        assert_eq!(record.severity(), Severity::Info);
+        assert_eq!(record.field("SUBSYSTEM"), Some("pci"));
+
+        assert!(KmsgRecord::parse(b"6,1,2;no flags").is_err());
+        assert!(KmsgRecord::parse(b"no header").is_err());
+    }
+
+    #[test]
+    fn convert_timestamps() {
+        let boot = boot_time().unwrap();
+        let now = SystemTime::now();
+        assert!(boot < now);
+        assert!(boot > UNIX_EPOCH);
+
+        let record = KmsgRecord::parse(b"6,1,1500000,-;started").unwrap();
+        assert_eq!(record.system_time_with(UNIX_EPOCH), UNIX_EPOCH + Duration::from_millis(1500));
+        assert_eq!(record.system_time_with(boot), boot + Duration::from_millis(1500));
+        let time = record.system_time().unwrap();
+        assert!(time >= boot && time <= now + Duration::from_secs(2));
+    }
+
+    #[test]
+    fn count_overrun_gap() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-overrun", std::process::id()));
+        std::fs::write(&path, "6,9,100,-;hello\n").unwrap();
+        let mut reader = KmsgReader::open(&path).unwrap();
+        reader.last = Some(5);
+        reader.overrun = true;
+        assert_eq!(reader.read_record().unwrap().unwrap().sequence, 9);
+        assert_eq!(reader.gap(), 3);
+        assert_eq!(reader.skipped(), 3);
+        assert!(!reader.overrun);
+
+        let record = KmsgRecord::parse(b"6,10,200,-;next").unwrap();
+        reader.track(record);
+        assert_eq!(reader.gap(), 0);
+        assert_eq!(reader.skipped(), 3);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn filter_records() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-filter", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let reader = KmsgReader::open(&path).unwrap().min_severity(Severity::Err);
+        let parse = |record: &str| KmsgRecord::parse(record.as_bytes()).unwrap();
+        assert!(reader.wanted(&parse("3,1,0,-;error")));
+        assert!(reader.wanted(&parse("10,1,0,-;critical from userspace")));
+        assert!(!reader.wanted(&parse("4,1,0,-;warning")));
+
+        let reader = reader.facilities([Facility::User, Facility::Daemon]);
+        assert!(!reader.wanted(&parse("3,1,0,-;error")));
+        assert!(reader.wanted(&parse("11,1,0,-;error from userspace")));
+        assert!(reader.wanted(&parse("27,1,0,-;error from daemon")));
+        assert!(!reader.wanted(&parse("14,1,0,-;info from userspace")));
+        assert!(!reader.wanted(&parse("1019,1,0,-;error from unknown facility")));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn read_kernel_log() {
+        let mut reader = match KmsgReader::new() {
+            Ok(reader) => reader,
+            // No permission to read kernel log
+            Err(_) => return,
+        };
+        if let Some(record) = reader.read_record().unwrap() {
+            assert!(record.priority <= 7);
+        }
+    }
+
+    #[test]
+    fn seek_kernel_log() {
+        let mut reader = match KmsgReader::new() {
+            Ok(reader) => reader,
+            // No permission to read kernel log
+            Err(_) => return,
+        };
+        let first = reader.read_record().unwrap();
+        reader.read_record().unwrap();
+        reader.seek(KmsgPosition::Oldest).unwrap();
+        let again = reader.read_record().unwrap();
+        // Unless the record was overwritten in between
+        if let (Some(first), Some(again)) = (first, again) {
+            assert!(again.sequence >= first.sequence);
+        }
+
+        reader.seek(KmsgPosition::AfterClear).unwrap();
+        reader.seek(KmsgPosition::Newest).unwrap();
+        assert!(reader.read_record().is_ok());
+    }
+
+    #[test]
+    fn follow_kernel_log() {
+        let mut reader = match KmsgReader::new() {
+            Ok(reader) => reader,
+            // No permission to read kernel log
+            Err(_) => return,
+        };
+        for record in reader.drain() {
+            assert!(record.unwrap().priority <= 7);
+        }
+        assert!(reader.wait_record_timeout(Duration::from_millis(1)).is_ok());
+
+        let message = format!("kernlog follow test {}", std::process::id());
+        match KernelLog::builder().build() {
+            Ok(klog) if klog.write_raw(Severity::Info, &message).is_ok() => (),
+            // No permission to write kernel log
+            _ => return,
+        }
+        let found = reader
+            .by_ref()
+            .take(100)
+            .any(|record| record.is_ok_and(|record| record.message == message));
+        assert!(found);
+    }
+}
@@ -0,0 +1,261 @@
+//! Backend sending records to remote syslog collector in RFC 5424 format
+
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::fallback::{parse_priority, LOG_USER};
+use crate::format::{getpid, program_name};
+use crate::sync::Mutex;
+use crate::KmsgBackend;
+
+/// Transport to send syslog messages over
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyslogTransport {
+    /// UDP datagrams to `host:port`, one message per datagram (RFC 5426)
+    Udp(String),
+    /// TCP stream to `host:port`, with octet counting framing (RFC 6587)
+    Tcp(String),
+    /// Unix datagram socket, like `/dev/log`
+    Unix(PathBuf),
+}
+
+enum Socket {
+    Udp(UdpSocket),
+    Tcp(Mutex<TcpStream>),
+    Unix(UnixDatagram),
+}
+
+/// Backend sending records as RFC 5424 syslog messages, see [`Builder::backend()`](crate::Builder::backend)
+///
+/// Connection is made on the first write, and retried on every write until it succeeds,
+/// so records logged before networking is up are dropped, or buffered with
+/// [`Builder::replay_buffer()`](crate::Builder::replay_buffer) and sent once it's up.
+/// Broken TCP connections are reestablished once per failed write.
+///
+/// Messages have the current time, `APP-NAME` and `PROCID` from `SYSLOG_IDENTIFIER` and `SYSLOG_PID`
+/// dictionary fields, or program name and process id, and no structured data.
+/// Kernel facility is replaced with user one, like for [`Fallback::Syslog`](crate::Fallback::Syslog).
+///
+/// ```rust,no_run
+/// use kernlog::{KernelLog, SyslogBackend, SyslogTransport};
+///
+/// let backend = SyslogBackend::new(SyslogTransport::Udp("logs.example.com:514".to_owned()));
+/// let klog = KernelLog::builder().backend(backend).replay_buffer(256).build().unwrap();
+/// ```
+pub struct SyslogBackend {
+    transport: SyslogTransport,
+    socket: Option<Socket>,
+    hostname: String,
+    app_name: Option<String>,
+}
+
+impl SyslogBackend {
+    /// Create backend sending messages over transport, with hostname from `gethostname()`
+    pub fn new(transport: SyslogTransport) -> SyslogBackend {
+        SyslogBackend { transport, socket: None, hostname: hostname().unwrap_or_default(), app_name: None }
+    }
+
+    /// Set `HOSTNAME` field of messages
+    pub fn hostname(mut self, hostname: impl Into<String>) -> SyslogBackend {
+        self.hostname = hostname.into();
+        self
+    }
+
+    /// Set `APP-NAME` field of messages, replacing `SYSLOG_IDENTIFIER` field and program name
+    pub fn app_name(mut self, app_name: impl Into<String>) -> SyslogBackend {
+        self.app_name = Some(app_name.into());
+        self
+    }
+
+    /// Get transport messages are sent over
+    pub fn transport(&self) -> &SyslogTransport {
+        &self.transport
+    }
+
+    fn connect(&self) -> io::Result<Socket> {
+        match self.transport {
+            SyslogTransport::Udp(ref addr) => {
+                let mut last = io::Error::new(io::ErrorKind::InvalidInput, "address resolved to nothing");
+                for addr in addr.to_socket_addrs()? {
+                    let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+                    match UdpSocket::bind(local).and_then(|socket| socket.connect(addr).map(|()| socket)) {
+                        Ok(socket) => return Ok(Socket::Udp(socket)),
+                        Err(err) => last = err,
+                    }
+                }
+                Err(last)
+            }
+            SyslogTransport::Tcp(ref addr) => Ok(Socket::Tcp(Mutex::new(TcpStream::connect(addr.as_str())?))),
+            SyslogTransport::Unix(ref path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Ok(Socket::Unix(socket))
+            }
+        }
+    }
+
+    /// Convert kmsg record to RFC 5424 message
+    fn message(&self, record: &[u8], time: SystemTime) -> String {
+        let record = String::from_utf8_lossy(record);
+        let mut lines = record.trim_end_matches('\n').split('\n');
+        let line = lines.next().unwrap_or_default();
+        let (priority, msg) = match parse_priority(line) {
+            Some((priority, msg)) if priority >> 3 == 0 => (priority | LOG_USER, msg),
+            Some((priority, msg)) => (priority, msg),
+            None => (LOG_USER | 6, line),
+        };
+
+        let mut identifier = None;
+        let mut pid = None;
+        for field in lines {
+            match field.strip_prefix(' ').and_then(|field| field.split_once('=')) {
+                Some(("SYSLOG_IDENTIFIER", value)) => identifier = Some(value),
+                Some(("SYSLOG_PID", value)) => pid = Some(value),
+                _ => (),
+            }
+        }
+        let app_name = match (&self.app_name, identifier) {
+            (Some(name), _) => name.clone(),
+            (None, Some(name)) => name.to_owned(),
+            (None, None) => program_name(),
+        };
+        let pid = pid.map_or_else(|| getpid().to_string(), str::to_owned);
+        format!("<{}>1 {} {} {} {} - - {}",
+            priority, timestamp(time), header_field(&self.hostname, 255), header_field(&app_name, 48),
+            header_field(&pid, 128), msg)
+    }
+}
+
+impl KmsgBackend for SyslogBackend {
+    fn open(&mut self) -> io::Result<()> {
+        self.socket = Some(self.connect()?);
+        Ok(())
+    }
+
+    fn write_record(&self, record: &[u8]) -> io::Result<()> {
+        let message = self.message(record, SystemTime::now());
+        match self.socket {
+            Some(Socket::Udp(ref socket)) => socket.send(message.as_bytes()).map(drop),
+            Some(Socket::Unix(ref socket)) => socket.send(message.as_bytes()).map(drop),
+            Some(Socket::Tcp(ref stream)) => {
+                let frame = format!("{} {}", message.len(), message);
+                let mut stream = stream.lock();
+                match stream.write_all(frame.as_bytes()) {
+                    Ok(()) => Ok(()),
+                    Err(_) => match self.transport {
+                        SyslogTransport::Tcp(ref addr) => {
+                            *stream = TcpStream::connect(addr.as_str())?;
+                            stream.write_all(frame.as_bytes())
+                        }
+                        _ => unreachable!("TCP socket is connected for TCP transport only"),
+                    },
+                }
+            }
+            None => Err(io::Error::new(io::ErrorKind::NotConnected, "syslog socket is not connected")),
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.socket.is_some()
+    }
+}
+
+/// Make header field of printable ASCII up to `max` bytes, or `-` if it's empty
+fn header_field(value: &str, max: usize) -> String {
+    let field: String = value.chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(max)
+        .collect();
+    if field.is_empty() {
+        "-".to_owned()
+    } else {
+        field
+    }
+}
+
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|&byte| byte == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).into_owned())
+}
+
+/// Format time as RFC 3339 UTC timestamp with microseconds
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs = secs % 86400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year, month, day, secs / 3600, secs / 60 % 60, secs % 60, since_epoch.subsec_micros())
+}
+
+/// Convert number of days since Unix epoch to Gregorian calendar date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::{TcpListener, UdpSocket};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{timestamp, SyslogBackend, SyslogTransport};
+    use crate::KmsgBackend;
+
+    #[test]
+    fn format_messages() {
+        assert_eq!(timestamp(UNIX_EPOCH + Duration::from_micros(1_709_210_096_500_000)), "2024-02-29T12:34:56.500000Z");
+        assert_eq!(timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000000Z");
+
+        let backend = SyslogBackend::new(SyslogTransport::Udp("localhost:514".to_owned())).hostname("box 1");
+        let time = UNIX_EPOCH + Duration::from_secs(86400);
+        assert_eq!(backend.message(b"<3>init: failed\n SYSLOG_IDENTIFIER=init\n SYSLOG_PID=1\n", time),
+            "<11>1 1970-01-02T00:00:00.000000Z box_1 init 1 - - init: failed");
+        let backend = backend.app_name("netd").hostname("");
+        assert_eq!(backend.message(b"<30>up\n SYSLOG_PID=7\n", time), "<30>1 1970-01-02T00:00:00.000000Z - netd 7 - - up");
+    }
+
+    #[test]
+    fn send_over_udp_and_tcp() {
+        let collector = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let transport = SyslogTransport::Udp(collector.local_addr().unwrap().to_string());
+        let mut backend = SyslogBackend::new(transport).hostname("box").app_name("init");
+        assert!(!backend.is_open());
+        assert!(backend.write_record(b"<6>lost\n").is_err());
+        backend.open().unwrap();
+        backend.write_record(b"<6>mounted\n").unwrap();
+        let mut buf = [0; 256];
+        let len = collector.recv(&mut buf).unwrap();
+        let message = std::str::from_utf8(&buf[..len]).unwrap();
+        assert!(message.starts_with("<14>1 "), "{}", message);
+        assert!(message.ends_with(&format!(" box init {} - - mounted", std::process::id())), "{}", message);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let transport = SyslogTransport::Tcp(listener.local_addr().unwrap().to_string());
+        let mut backend = SyslogBackend::new(transport).hostname("box").app_name("init");
+        backend.open().unwrap();
+        backend.write_record(b"<6>up\n SYSLOG_PID=1\n").unwrap();
+        drop(backend);
+        let mut received = String::new();
+        listener.accept().unwrap().0.read_to_string(&mut received).unwrap();
+        let (len, message) = received.split_once(' ').unwrap();
+        assert_eq!(len.parse::<usize>().unwrap(), message.len());
+        assert!(message.ends_with(" box init 1 - - up"), "{}", message);
+    }
+}
@@ -0,0 +1,58 @@
+//! Buffering records until the device can be opened
+
+use std::collections::VecDeque;
+
+use crate::KmsgBackend;
+
+/// Records formatted before the device could be opened, replayed in order once it's open
+#[derive(Debug, Default)]
+pub(crate) struct Pending {
+    records: VecDeque<Vec<u8>>,
+    /// Maximum number of buffered records, nothing is buffered if it's zero
+    capacity: usize,
+}
+
+impl Pending {
+    pub(crate) fn new(capacity: usize) -> Pending {
+        Pending { records: VecDeque::new(), capacity }
+    }
+
+    /// Buffer record, unless the buffer is full, returning if it was buffered
+    ///
+    /// Later records are dropped, as the earliest ones are usually the most interesting.
+    pub(crate) fn push(&mut self, record: &[u8]) -> bool {
+        if self.records.len() >= self.capacity {
+            return false;
+        }
+        self.records.push_back(record.to_vec());
+        true
+    }
+
+    /// Write all buffered records to the device
+    pub(crate) fn replay(&mut self, kmsg: &dyn KmsgBackend) {
+        for record in self.records.drain(..) {
+            let _ = kmsg.write_record(&record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use super::Pending;
+    use crate::backend::Kmsg;
+
+    #[test]
+    fn buffer_and_replay() {
+        let mut pending = Pending::new(2);
+        assert!(pending.push(b"<6>first\n"));
+        assert!(pending.push(b"<6>second\n"));
+        assert!(!pending.push(b"<6>third\n"));
+
+        let path = std::env::temp_dir().join(format!("kernlog-{}-replay", std::process::id()));
+        pending.replay(&Kmsg::from_file(File::create(&path).unwrap()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<6>first\n<6>second\n");
+        assert!(pending.records.is_empty());
+    }
+}
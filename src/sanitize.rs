@@ -0,0 +1,115 @@
+//! Message sanitizing
+
+use std::borrow::Cow;
+use std::fmt::Write;
+
+/// Message sanitizer, applied to messages before they are formatted
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Sanitizer {
+    pub(crate) strip_ansi: bool,
+    pub(crate) escape_control: bool,
+}
+
+impl Default for Sanitizer {
+    fn default() -> Sanitizer {
+        Sanitizer { strip_ansi: false, escape_control: true }
+    }
+}
+
+impl Sanitizer {
+    /// Sanitize whole message, before it's split into lines
+    pub(crate) fn strip<'a>(&self, msg: &'a str) -> Cow<'a, str> {
+        if self.strip_ansi {
+            strip_ansi(msg)
+        } else {
+            Cow::Borrowed(msg)
+        }
+    }
+
+    /// Sanitize single line of message
+    pub(crate) fn escape<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        if self.escape_control {
+            escape_control(line)
+        } else {
+            Cow::Borrowed(line)
+        }
+    }
+}
+
+/// Remove ANSI escape sequences (colors, cursor movements, etc.) from text
+pub(crate) fn strip_ansi(text: &str) -> Cow<'_, str> {
+    if !text.contains(['\x1b', '\u{9b}']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => match chars.next() {
+                // CSI: parameters and intermediate bytes up to a final byte
+                Some('[') => skip_csi(&mut chars),
+                // OSC, DCS, etc.: up to BEL or string terminator (ESC \)
+                Some(']') | Some('P') | Some('X') | Some('^') | Some('_') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' || (c == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                // Two character escape sequence
+                Some(_) | None => (),
+            },
+            '\u{9b}' => skip_csi(&mut chars),
+            c => result.push(c),
+        }
+    }
+    Cow::Owned(result)
+}
+
+/// Escape ASCII control characters as `\xNN`, the way kernel escapes them in `/dev/kmsg` output
+pub(crate) fn escape_control(text: &str) -> Cow<'_, str> {
+    if !text.contains(|c: char| c.is_ascii_control()) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut result = String::with_capacity(text.len() + 8);
+    for c in text.chars() {
+        if c.is_ascii_control() {
+            let _ = write!(result, "\\x{:02x}", c as u8);
+        } else {
+            result.push(c);
+        }
+    }
+    Cow::Owned(result)
+}
+
+fn skip_csi(chars: &mut impl Iterator<Item = char>) {
+    for c in chars {
+        if ('\x40'..='\x7e').contains(&c) {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_control, strip_ansi};
+
+    #[test]
+    fn escape_control_chars() {
+        assert_eq!(escape_control("plain ünicode"), "plain ünicode");
+        assert_eq!(escape_control("tab\there\r\0"), "tab\\x09here\\x0d\\x00");
+        assert_eq!(escape_control("bell\x07\x7f"), "bell\\x07\\x7f");
+    }
+
+    #[test]
+    fn strip_ansi_sequences() {
+        assert_eq!(strip_ansi("plain text"), "plain text");
+        assert_eq!(strip_ansi("\x1b[1;31merror\x1b[0m: failed"), "error: failed");
+        assert_eq!(strip_ansi("\x1b]0;title\x07done"), "done");
+        assert_eq!(strip_ansi("\x1b]8;;http://x\x1b\\link\x1b]8;;\x1b\\"), "link");
+        assert_eq!(strip_ansi("\x1bMup\u{9b}2Jclear"), "upclear");
+        assert_eq!(strip_ansi("trailing\x1b["), "trailing");
+    }
+}
@@ -0,0 +1,96 @@
+//! Record size limit and splitting of messages exceeding it
+
+use std::ffi::CStr;
+
+/// Record size limit of kernels before 6.3, `LOG_LINE_MAX` with `CONFIG_PRINTK_CALLER` enabled
+///
+/// Kernels built without `CONFIG_PRINTK_CALLER` accept up to 992 bytes,
+/// but there's no cheap way to tell them apart, so the lower limit is used.
+pub(crate) const LEGACY_MAX_RECORD_LEN: usize = 976;
+
+/// Record size limit of kernels since 6.3, `PRINTKRB_RECORD_MAX`
+pub(crate) const MAX_RECORD_LEN: usize = 1024;
+
+/// Detect maximum record length accepted by `/dev/kmsg` of the running kernel,
+/// including priority prefix and trailing new line
+pub(crate) fn detect_max_record_len() -> usize {
+    match kernel_version() {
+        Some(version) if version >= (6, 3) => MAX_RECORD_LEN,
+        _ => LEGACY_MAX_RECORD_LEN,
+    }
+}
+
+/// Get running kernel version as (major, minor) pair
+fn kernel_version() -> Option<(u32, u32)> {
+    let mut uts = unsafe { std::mem::zeroed::<libc::utsname>() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return None;
+    }
+    let release = unsafe { CStr::from_ptr(uts.release.as_ptr()) };
+    parse_version(&release.to_string_lossy())
+}
+
+fn parse_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(|c: char| !c.is_ascii_digit());
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// Split message into chunks, so that every chunk with ` (i/n)` suffix
+/// appended fits into `room` bytes
+///
+/// Chunks are split at character boundaries. Returns `None` if there's
+/// no room even for a single character with the suffix.
+pub(crate) fn split(msg: &str, room: usize) -> Option<Vec<&str>> {
+    let mut digits = 1;
+    loop {
+        // " (" + index + "/" + count + ")"
+        let suffix = 4 + 2 * digits;
+        let chunks = chunks(msg, room.checked_sub(suffix)?)?;
+        if chunks.len().to_string().len() <= digits {
+            return Some(chunks);
+        }
+        digits += 1;
+    }
+}
+
+fn chunks(mut msg: &str, size: usize) -> Option<Vec<&str>> {
+    let mut chunks = Vec::new();
+    while msg.len() > size {
+        let mut end = size;
+        while !msg.is_char_boundary(end) {
+            end -= 1;
+        }
+        if end == 0 {
+            return None;
+        }
+        let (chunk, rest) = msg.split_at(end);
+        chunks.push(chunk);
+        msg = rest;
+    }
+    chunks.push(msg);
+    Some(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_version, split};
+
+    #[test]
+    fn parse_kernel_version() {
+        assert_eq!(parse_version("6.8.0-45-generic"), Some((6, 8)));
+        assert_eq!(parse_version("5.10.0"), Some((5, 10)));
+        assert_eq!(parse_version("4.19-rc1"), Some((4, 19)));
+        assert_eq!(parse_version("linux"), None);
+    }
+
+    #[test]
+    fn split_message() {
+        assert_eq!(split("short", 20), Some(vec!["short"]));
+        assert_eq!(split("abcdefghij", 10), Some(vec!["abcd", "efgh", "ij"]));
+        assert_eq!(split("ab", 6), None);
+        assert_eq!(split("ééé", 9), Some(vec!["é", "é", "é"]));
+        assert_eq!(split(&"x".repeat(100), 10).map(|chunks| chunks.len()), Some(50));
+    }
+}
@@ -0,0 +1,190 @@
+//! Counters of records which didn't make it to the device
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::sync::Mutex;
+
+/// Numbers of records dropped since logger was built, by cause
+///
+/// Returned by [`KernelLog::drop_counts()`](crate::KernelLog::drop_counts). Records the device didn't accept,
+/// but which were written to fallback destination or buffered for replay instead, are counted separately
+/// and are not included in the [total](DropCounts::total).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DropCounts {
+    /// Non-blocking device would block (`EAGAIN`), see [`Builder::nonblocking()`](crate::Builder::nonblocking)
+    pub would_block: u64,
+    /// Background writer queue was full, see [`Builder::backpressure()`](crate::Builder::backpressure)
+    pub queue_overflow: u64,
+    /// Device rejected record as too long (`EINVAL`)
+    pub oversize: u64,
+    /// Device failed to accept record for any other reason
+    pub write_error: u64,
+    /// Records written to fallback destination instead, see [`Builder::fallback()`](crate::Builder::fallback)
+    pub fallback: u64,
+    /// Records buffered until lazy device is opened, see [`Builder::replay_buffer()`](crate::Builder::replay_buffer)
+    pub buffered: u64,
+}
+
+impl DropCounts {
+    /// Get total number of lost records
+    pub fn total(&self) -> u64 {
+        self.would_block + self.queue_overflow + self.oversize + self.write_error
+    }
+}
+
+/// Counters of records the device failed to accept, queue overflows are counted by the queue itself
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    would_block: AtomicU64,
+    oversize: AtomicU64,
+    write_error: AtomicU64,
+    fallback: AtomicU64,
+    buffered: AtomicU64,
+}
+
+impl Counters {
+    /// Count record lost because it failed to be written with error
+    pub(crate) fn count(&self, err: &io::Error) {
+        let counter = match err.kind() {
+            io::ErrorKind::WouldBlock => &self.would_block,
+            io::ErrorKind::InvalidInput => &self.oversize,
+            _ => &self.write_error,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count record written to fallback destination instead of the device
+    pub(crate) fn count_fallback(&self) {
+        self.fallback.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count record buffered for replay until the device is opened
+    pub(crate) fn count_buffered(&self) {
+        self.buffered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn would_block(&self) -> u64 {
+        self.would_block.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn snapshot(&self, queue_overflow: u64) -> DropCounts {
+        DropCounts {
+            would_block: self.would_block(),
+            queue_overflow,
+            oversize: self.oversize.load(Ordering::Relaxed),
+            write_error: self.write_error.load(Ordering::Relaxed),
+            fallback: self.fallback.load(Ordering::Relaxed),
+            buffered: self.buffered.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Numbers of records suppressed by rate limits, sampling or deduplication for each target,
+/// to be reported periodically
+pub(crate) struct Report {
+    interval: Duration,
+    /// Number of records counted since the last report, to check it without locking
+    pending: AtomicU64,
+    tally: Mutex<Tally>,
+}
+
+struct Tally {
+    /// Time the first record since the last report was suppressed
+    since: Instant,
+    targets: BTreeMap<String, u64>,
+}
+
+impl Report {
+    pub(crate) fn new(interval: Duration) -> Report {
+        Report {
+            interval,
+            pending: AtomicU64::new(0),
+            tally: Mutex::new(Tally { since: Instant::now(), targets: BTreeMap::new() }),
+        }
+    }
+
+    /// Count suppressed record from target
+    pub(crate) fn count(&self, target: &str) {
+        let mut tally = self.tally.lock();
+        if tally.targets.is_empty() {
+            tally.since = Instant::now();
+        }
+        match tally.targets.get_mut(target) {
+            Some(count) => *count += 1,
+            None => {
+                tally.targets.insert(target.to_owned(), 1);
+            }
+        }
+        self.pending.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Take summary of suppressed records, if any were counted at least `interval` ago
+    pub(crate) fn take(&self, now: Instant) -> Option<String> {
+        if self.pending.load(Ordering::Relaxed) == 0 {
+            return None;
+        }
+        let mut tally = self.tally.lock();
+        let period = now.saturating_duration_since(tally.since);
+        if tally.targets.is_empty() || period < self.interval {
+            return None;
+        }
+        self.pending.store(0, Ordering::Relaxed);
+        let mut summary = format!("suppressed in the last {}s:", whole_secs(period));
+        for (target, count) in std::mem::take(&mut tally.targets) {
+            let _ = write!(summary, " {}={}", target, count);
+        }
+        Some(summary)
+    }
+}
+
+/// Round period up to seconds, so short periods aren't reported as 0s
+pub(crate) fn whole_secs(period: Duration) -> u64 {
+    (period.as_secs() + u64::from(period.subsec_nanos() > 0)).max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::{Duration, Instant};
+
+    use super::{Counters, DropCounts, Report};
+
+    #[test]
+    fn count_by_cause() {
+        let counters = Counters::default();
+        counters.count(&io::ErrorKind::WouldBlock.into());
+        counters.count(&io::Error::from_raw_os_error(libc::EINVAL));
+        counters.count(&io::Error::from_raw_os_error(libc::EIO));
+        counters.count(&io::Error::from_raw_os_error(libc::EIO));
+        counters.count_fallback();
+        counters.count_buffered();
+        let counts = counters.snapshot(3);
+        assert_eq!(counts, DropCounts {
+            would_block: 1,
+            queue_overflow: 3,
+            oversize: 1,
+            write_error: 2,
+            fallback: 1,
+            buffered: 1,
+        });
+        assert_eq!(counts.total(), 7);
+    }
+
+    #[test]
+    fn report_by_target() {
+        let report = Report::new(Duration::from_secs(10));
+        assert_eq!(report.take(Instant::now() + Duration::from_secs(60)), None);
+
+        for target in ["net", "db", "net"] {
+            report.count(target);
+        }
+        assert_eq!(report.take(Instant::now()), None);
+        let later = Instant::now() + Duration::from_millis(10500);
+        assert_eq!(report.take(later).unwrap(), "suppressed in the last 11s: db=1 net=2");
+        assert_eq!(report.take(later), None);
+    }
+}
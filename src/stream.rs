@@ -0,0 +1,125 @@
+//! Asynchronous reading of kernel log records with tokio
+
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_core::Stream;
+use tokio::io::unix::AsyncFd;
+use tokio::io::Interest;
+
+use crate::{KmsgReader, KmsgRecord};
+
+/// Stream of kernel log records, waiting for new ones without blocking a thread
+///
+/// Needs to be created inside of tokio runtime with IO driver enabled.
+/// Like [`KmsgReader`] iterator, the stream never ends.
+///
+/// ```rust,no_run
+/// # async fn run() -> std::io::Result<()> {
+/// let mut stream = kernlog::KmsgStream::new()?;
+/// loop {
+///     let record = stream.next_record().await?;
+///     println!("{}", record.message);
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct KmsgStream {
+    fd: AsyncFd<KmsgReader>,
+}
+
+impl KmsgStream {
+    /// Open `/dev/kmsg` for reading
+    pub fn new() -> io::Result<KmsgStream> {
+        KmsgStream::from_reader(KmsgReader::new()?)
+    }
+
+    /// Read records of a reader, applying its position and filters as is
+    pub fn from_reader(reader: KmsgReader) -> io::Result<KmsgStream> {
+        Ok(KmsgStream { fd: AsyncFd::with_interest(reader, Interest::READABLE)? })
+    }
+
+    /// Get the underlying reader
+    pub fn get_ref(&self) -> &KmsgReader {
+        self.fd.get_ref()
+    }
+
+    /// Get the underlying reader mutably, e.g. to seek it
+    pub fn get_mut(&mut self) -> &mut KmsgReader {
+        self.fd.get_mut()
+    }
+
+    /// Unwrap the underlying reader
+    pub fn into_inner(self) -> KmsgReader {
+        self.fd.into_inner()
+    }
+
+    /// Read the next record, waiting for kernel to log one if there are none yet
+    pub async fn next_record(&mut self) -> io::Result<KmsgRecord> {
+        loop {
+            let mut guard = self.fd.readable_mut().await?;
+            if let Some(record) = guard.get_inner_mut().read_record()? {
+                return Ok(record);
+            }
+            guard.clear_ready();
+        }
+    }
+}
+
+impl Stream for KmsgStream {
+    type Item = io::Result<KmsgRecord>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<KmsgRecord>>> {
+        let fd = &mut self.get_mut().fd;
+        loop {
+            let mut guard = ready!(fd.poll_read_ready_mut(cx))?;
+            match guard.get_inner_mut().read_record() {
+                Ok(Some(record)) => return Poll::Ready(Some(Ok(record))),
+                Ok(None) => guard.clear_ready(),
+                Err(err) => return Poll::Ready(Some(Err(err))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::poll_fn;
+    use std::pin::Pin;
+    use std::time::Duration;
+
+    use futures_core::Stream;
+
+    use crate::{KernelLog, KmsgPosition, KmsgReader, KmsgStream, Severity};
+
+    #[test]
+    fn stream_kernel_log() {
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_io().enable_time().build().unwrap();
+        runtime.block_on(async {
+            let mut reader = match KmsgReader::new() {
+                Ok(reader) => reader,
+                // No permission to read kernel log
+                Err(_) => return,
+            };
+            reader.seek(KmsgPosition::Newest).unwrap();
+            let mut stream = KmsgStream::from_reader(reader).unwrap();
+
+            let message = format!("kernlog stream test {}", std::process::id());
+            match KernelLog::builder().build() {
+                Ok(klog) if klog.write_raw(Severity::Info, &message).is_ok() => (),
+                // No permission to write kernel log
+                _ => return,
+            }
+            let found = tokio::time::timeout(Duration::from_secs(5), async {
+                loop {
+                    let record = poll_fn(|cx| Pin::new(&mut stream).poll_next(cx)).await.unwrap().unwrap();
+                    if record.message == message {
+                        break;
+                    }
+                }
+            }).await;
+            assert!(found.is_ok());
+        });
+    }
+}
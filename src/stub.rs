@@ -0,0 +1,235 @@
+//! Stand-in logger for platforms without kernel log
+
+use std::env;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::{level, KernelLogInitError};
+
+/// Stand-in for kernel logger on platforms without kernel log, writing records to stderr
+///
+/// Records are written as `<priority>tag: target: message` lines, so the same binary
+/// can be built and run on developer machines. Only the basic part of Linux API is provided,
+/// devices are accepted but ignored, and `KERNLOG_LEVEL` is the only environment variable read.
+pub struct KernelLog {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    maxlevel: AtomicUsize,
+    tag: Option<String>,
+}
+
+impl Shared {
+    fn maxlevel(&self) -> LevelFilter {
+        match self.maxlevel.load(Ordering::Relaxed) {
+            0 => LevelFilter::Off,
+            1 => LevelFilter::Error,
+            2 => LevelFilter::Warn,
+            3 => LevelFilter::Info,
+            4 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+}
+
+impl KernelLog {
+    /// Create new logger writing to stderr with trace level filter
+    pub fn new() -> io::Result<KernelLog> {
+        KernelLog::builder().build()
+    }
+
+    /// Create new logger with level filter from `KERNLOG_LEVEL` environment variable
+    pub fn from_env() -> io::Result<KernelLog> {
+        KernelLog::builder().env().build()
+    }
+
+    /// Create new logger, ignoring the device
+    pub fn with_device(_device: impl AsRef<Path>) -> io::Result<KernelLog> {
+        KernelLog::new()
+    }
+
+    /// Create new logger with level filter
+    pub fn with_level(filter: LevelFilter) -> io::Result<KernelLog> {
+        KernelLog::builder().level(filter).build()
+    }
+
+    /// Create new logger with level filter, ignoring the device
+    pub fn with_device_and_level(_device: impl AsRef<Path>, filter: LevelFilter) -> io::Result<KernelLog> {
+        KernelLog::with_level(filter)
+    }
+
+    /// Create new logger builder
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    /// Get current level filter
+    pub fn max_level(&self) -> LevelFilter {
+        self.shared.maxlevel()
+    }
+
+    /// Change level filter at runtime
+    pub fn set_max_level(&self, filter: LevelFilter) {
+        self.shared.maxlevel.store(filter as usize, Ordering::Relaxed);
+    }
+
+    /// Get a handle to reconfigure this logger after it has been installed
+    pub fn handle(&self) -> Handle {
+        Handle { shared: self.shared.clone() }
+    }
+}
+
+impl Log for KernelLog {
+    fn enabled(&self, meta: &Metadata) -> bool {
+        meta.level() <= self.shared.maxlevel()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut stderr = io::stderr().lock();
+        let _ = match self.shared.tag {
+            Some(ref tag) => writeln!(stderr, "<{}>{}: {}: {}",
+                level::priority(record.level()), tag, record.target(), record.args()),
+            None => writeln!(stderr, "<{}>{}: {}", level::priority(record.level()), record.target(), record.args()),
+        };
+    }
+
+    fn flush(&self) {
+        let _ = io::stderr().flush();
+    }
+}
+
+/// Stand-in logger builder, with the basic part of Linux builder options
+pub struct Builder {
+    level: LevelFilter,
+    tag: Option<String>,
+}
+
+impl Builder {
+    /// Create new builder logging everything
+    pub fn new() -> Builder {
+        Builder { level: LevelFilter::Trace, tag: None }
+    }
+
+    /// Accept device to log to, which is ignored
+    pub fn device(self, _device: impl AsRef<Path>) -> Builder {
+        self
+    }
+
+    /// Set level filter
+    pub fn level(mut self, filter: LevelFilter) -> Builder {
+        self.level = filter;
+        self
+    }
+
+    /// Set tag to prefix records with
+    pub fn tag(mut self, tag: impl Into<String>) -> Builder {
+        self.tag = Some(tag.into());
+        self
+    }
+
+    /// Take level filter from `KERNLOG_LEVEL` environment variable, ignoring invalid values
+    pub fn env(mut self) -> Builder {
+        if let Ok(Some(filter)) = level::parse_env_filter("KERNLOG_LEVEL", env::var("KERNLOG_LEVEL")) {
+            self.level = filter;
+        }
+        self
+    }
+
+    /// Build logger
+    pub fn build(self) -> io::Result<KernelLog> {
+        let shared = Shared { maxlevel: AtomicUsize::new(self.level as usize), tag: self.tag };
+        Ok(KernelLog { shared: Arc::new(shared) })
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+/// Handle to reconfigure an installed logger
+#[derive(Clone)]
+pub struct Handle {
+    shared: Arc<Shared>,
+}
+
+impl Handle {
+    /// Get current level filter
+    pub fn level(&self) -> LevelFilter {
+        self.shared.maxlevel()
+    }
+
+    /// Change level filter, also updating global `log` max level
+    pub fn set_level(&self, filter: LevelFilter) {
+        self.shared.maxlevel.store(filter as usize, Ordering::Relaxed);
+        log::set_max_level(filter);
+    }
+
+    /// Get path of the device logged to, always `None` here
+    pub fn device(&self) -> Option<PathBuf> {
+        None
+    }
+}
+
+/// Setup stand-in logger as a default logger, with level filter from `KERNLOG_LEVEL`
+pub fn init() -> Result<Handle, KernelLogInitError> {
+    install(KernelLog::from_env()?)
+}
+
+/// Setup stand-in logger as a default logger, ignoring the device
+pub fn init_with_device(_device: impl AsRef<Path>) -> Result<Handle, KernelLogInitError> {
+    init()
+}
+
+/// Setup stand-in logger as a default logger with specific level filter
+pub fn init_with_level(filter: LevelFilter) -> Result<Handle, KernelLogInitError> {
+    install(KernelLog::with_level(filter)?)
+}
+
+/// Setup stand-in logger as a default logger with specific level filter, ignoring the device
+pub fn init_with_device_and_level(_device: impl AsRef<Path>, filter: LevelFilter) -> Result<Handle, KernelLogInitError> {
+    init_with_level(filter)
+}
+
+/// Setup stand-in logger as a default logger, it writes to stderr anyway
+pub fn init_or_stderr() -> Result<Handle, KernelLogInitError> {
+    init()
+}
+
+/// Setup stand-in logger as a default logger, stderr being the only destination here
+pub fn init_auto() -> Result<Handle, KernelLogInitError> {
+    init()
+}
+
+fn install(klog: KernelLog) -> Result<Handle, KernelLogInitError> {
+    let handle = klog.handle();
+    log::set_boxed_logger(Box::new(klog))?;
+    log::set_max_level(handle.level());
+    Ok(handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, LevelFilter, Log, Metadata};
+
+    use super::KernelLog;
+
+    #[test]
+    fn filter_by_level() {
+        let klog = KernelLog::builder().device("/dev/null").tag("init").level(LevelFilter::Info).build().unwrap();
+        assert!(klog.enabled(&Metadata::builder().level(Level::Warn).build()));
+        assert!(!klog.enabled(&Metadata::builder().level(Level::Debug).build()));
+        klog.handle().set_level(LevelFilter::Debug);
+        assert_eq!(klog.max_level(), LevelFilter::Debug);
+        assert_eq!(klog.handle().device(), None);
+    }
+}
@@ -0,0 +1,94 @@
+//! Locking primitives, backed by `parking_lot` with `parking_lot` feature
+//!
+//! Both flavors share the same poison-free interface: a panic while holding
+//! a lock never disables logging.
+
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) use std::sync::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+#[cfg(feature = "parking_lot")]
+pub(crate) use parking_lot::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+
+/// Mutual exclusion lock ignoring poisoning
+pub(crate) struct Mutex<T>(
+    #[cfg(not(feature = "parking_lot"))] std::sync::Mutex<T>,
+    #[cfg(feature = "parking_lot")] parking_lot::Mutex<T>,
+);
+
+impl<T> Mutex<T> {
+    pub(crate) const fn new(value: T) -> Mutex<T> {
+        #[cfg(not(feature = "parking_lot"))]
+        return Mutex(std::sync::Mutex::new(value));
+        #[cfg(feature = "parking_lot")]
+        return Mutex(parking_lot::Mutex::new(value));
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+        #[cfg(not(feature = "parking_lot"))]
+        return self.0.lock().unwrap_or_else(|err| err.into_inner());
+        #[cfg(feature = "parking_lot")]
+        return self.0.lock();
+    }
+}
+
+/// Condition variable to wait on with [`Mutex`] guards
+pub(crate) struct Condvar(
+    #[cfg(not(feature = "parking_lot"))] std::sync::Condvar,
+    #[cfg(feature = "parking_lot")] parking_lot::Condvar,
+);
+
+impl Condvar {
+    pub(crate) const fn new() -> Condvar {
+        #[cfg(not(feature = "parking_lot"))]
+        return Condvar(std::sync::Condvar::new());
+        #[cfg(feature = "parking_lot")]
+        return Condvar(parking_lot::Condvar::new());
+    }
+
+    #[cfg_attr(not(feature = "parking_lot"), allow(unused_mut))]
+    pub(crate) fn wait<'a, T>(&self, mut guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        #[cfg(not(feature = "parking_lot"))]
+        return self.0.wait(guard).unwrap_or_else(|err| err.into_inner());
+        #[cfg(feature = "parking_lot")]
+        {
+            self.0.wait(&mut guard);
+            guard
+        }
+    }
+
+    pub(crate) fn notify_one(&self) {
+        self.0.notify_one();
+    }
+
+    pub(crate) fn notify_all(&self) {
+        self.0.notify_all();
+    }
+}
+
+/// Reader-writer lock ignoring poisoning
+pub(crate) struct RwLock<T>(
+    #[cfg(not(feature = "parking_lot"))] std::sync::RwLock<T>,
+    #[cfg(feature = "parking_lot")] parking_lot::RwLock<T>,
+);
+
+impl<T> RwLock<T> {
+    pub(crate) const fn new(value: T) -> RwLock<T> {
+        #[cfg(not(feature = "parking_lot"))]
+        return RwLock(std::sync::RwLock::new(value));
+        #[cfg(feature = "parking_lot")]
+        return RwLock(parking_lot::RwLock::new(value));
+    }
+
+    pub(crate) fn read(&self) -> RwLockReadGuard<'_, T> {
+        #[cfg(not(feature = "parking_lot"))]
+        return self.0.read().unwrap_or_else(|err| err.into_inner());
+        #[cfg(feature = "parking_lot")]
+        return self.0.read();
+    }
+
+    pub(crate) fn write(&self) -> RwLockWriteGuard<'_, T> {
+        #[cfg(not(feature = "parking_lot"))]
+        return self.0.write().unwrap_or_else(|err| err.into_inner());
+        #[cfg(feature = "parking_lot")]
+        return self.0.write();
+    }
+}
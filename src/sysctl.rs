@@ -0,0 +1,201 @@
+//! Kernel logging related sysctl values
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::RateLimit;
+
+const PRINTK: &str = "/proc/sys/kernel/printk";
+const PRINTK_DEVKMSG: &str = "/proc/sys/kernel/printk_devkmsg";
+const DMESG_RESTRICT: &str = "/proc/sys/kernel/dmesg_restrict";
+const PRINTK_RATELIMIT: &str = "/proc/sys/kernel/printk_ratelimit";
+const PRINTK_RATELIMIT_BURST: &str = "/proc/sys/kernel/printk_ratelimit_burst";
+
+/// Mode of `/dev/kmsg` writes, `kernel.printk_devkmsg` sysctl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DevkmsgMode {
+    /// Writes are accepted unlimited
+    On,
+    /// Writes are silently discarded
+    Off,
+    /// Writes are ratelimited (default)
+    Ratelimit,
+}
+
+/// Get mode of `/dev/kmsg` writes
+///
+/// When it's [`DevkmsgMode::Off`], kernel pretends writes succeed, but drops them.
+pub fn devkmsg_mode() -> io::Result<DevkmsgMode> {
+    parse_devkmsg_mode(&read(PRINTK_DEVKMSG)?)
+}
+
+fn parse_devkmsg_mode(value: &str) -> io::Result<DevkmsgMode> {
+    match value {
+        "on" => Ok(DevkmsgMode::On),
+        "off" => Ok(DevkmsgMode::Off),
+        "ratelimit" => Ok(DevkmsgMode::Ratelimit),
+        _ => Err(invalid(PRINTK_DEVKMSG, value)),
+    }
+}
+
+/// Console loglevels of `kernel.printk` sysctl
+///
+/// Kernel prints records with priority below console loglevel to the console.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Printk {
+    /// Current console loglevel
+    pub console: u8,
+    /// Priority of records logged without one
+    pub default_message: u8,
+    /// Lowest console loglevel that can be set
+    pub minimum_console: u8,
+    /// Console loglevel at boot
+    pub default_console: u8,
+}
+
+/// Get all four values of `kernel.printk` sysctl
+pub fn printk() -> io::Result<Printk> {
+    parse_printk(&read(PRINTK)?)
+}
+
+/// Set all four values of `kernel.printk` sysctl, requires root
+pub fn set_printk(printk: Printk) -> io::Result<()> {
+    write_printk(PRINTK, printk)
+}
+
+fn write_printk(path: impl AsRef<Path>, printk: Printk) -> io::Result<()> {
+    fs::write(path, format!(
+        "{} {} {} {}\n", printk.console, printk.default_message, printk.minimum_console, printk.default_console))
+}
+
+/// Set console loglevel through `kernel.printk` sysctl, keeping its other values, requires root
+///
+/// Unlike `syslog(2)`, this accepts levels above 8, e.g. 10 set by `ignore_loglevel` boot.
+pub(crate) fn set_console_loglevel(level: u8) -> io::Result<()> {
+    set_console_loglevel_at(PRINTK, level)
+}
+
+fn set_console_loglevel_at(path: impl AsRef<Path>, level: u8) -> io::Result<()> {
+    let printk = parse_printk(&read(&path)?)?;
+    write_printk(path, Printk { console: level, ..printk })
+}
+
+fn parse_printk(value: &str) -> io::Result<Printk> {
+    let levels = value.split_whitespace()
+        .map(|level| level.parse().map_err(|_| invalid(PRINTK, value)))
+        .collect::<io::Result<Vec<u8>>>()?;
+    match levels[..] {
+        [console, default_message, minimum_console, default_console] => {
+            Ok(Printk { console, default_message, minimum_console, default_console })
+        }
+        _ => Err(invalid(PRINTK, value)),
+    }
+}
+
+/// Get current console loglevel, the first value of `kernel.printk` sysctl
+///
+/// Kernel prints records with priority below this value to the console.
+pub fn console_loglevel() -> io::Result<u8> {
+    parse_console_loglevel(&read(PRINTK)?)
+}
+
+fn parse_console_loglevel(value: &str) -> io::Result<u8> {
+    value.split_whitespace().next()
+        .and_then(|level| level.parse().ok())
+        .ok_or_else(|| invalid(PRINTK, value))
+}
+
+/// Get kernel printk rate limit, `kernel.printk_ratelimit` interval in seconds
+/// and `kernel.printk_ratelimit_burst` sysctls
+///
+/// Their defaults, 10 records per 5 seconds, are also the limit kernel applies to writes
+/// to each open `/dev/kmsg` in [`DevkmsgMode::Ratelimit`] mode, silently dropping records over it.
+pub fn printk_ratelimit() -> io::Result<RateLimit> {
+    let interval = parse_number(PRINTK_RATELIMIT, &read(PRINTK_RATELIMIT)?)?;
+    let burst = parse_number(PRINTK_RATELIMIT_BURST, &read(PRINTK_RATELIMIT_BURST)?)?;
+    Ok(RateLimit::new(burst, Duration::from_secs(interval.into())))
+}
+
+fn parse_number(path: &str, value: &str) -> io::Result<u32> {
+    value.parse().map_err(|_| invalid(path, value))
+}
+
+/// Check if reading kernel log requires `CAP_SYSLOG`, `kernel.dmesg_restrict` sysctl
+pub fn dmesg_restrict() -> io::Result<bool> {
+    parse_flag(DMESG_RESTRICT, &read(DMESG_RESTRICT)?)
+}
+
+fn parse_flag(path: &str, value: &str) -> io::Result<bool> {
+    match value {
+        "0" => Ok(false),
+        "1" => Ok(true),
+        _ => Err(invalid(path, value)),
+    }
+}
+
+fn read(path: impl AsRef<Path>) -> io::Result<String> {
+    let mut value = fs::read_to_string(path)?;
+    value.truncate(value.trim_end().len());
+    Ok(value)
+}
+
+fn invalid(path: &str, value: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("unexpected {} value {:?}", path, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{
+        parse_console_loglevel, parse_devkmsg_mode, parse_flag, parse_number, parse_printk, set_console_loglevel_at,
+        DevkmsgMode, Printk,
+    };
+
+    #[test]
+    fn parse_devkmsg() {
+        assert_eq!(parse_devkmsg_mode("on").unwrap(), DevkmsgMode::On);
+        assert_eq!(parse_devkmsg_mode("off").unwrap(), DevkmsgMode::Off);
+        assert_eq!(parse_devkmsg_mode("ratelimit").unwrap(), DevkmsgMode::Ratelimit);
+        assert!(parse_devkmsg_mode("maybe").is_err());
+    }
+
+    #[test]
+    fn parse_printk_levels() {
+        assert_eq!(parse_printk("4\t4\t1\t7").unwrap(), Printk {
+            console: 4,
+            default_message: 4,
+            minimum_console: 1,
+            default_console: 7,
+        });
+        assert!(parse_printk("4\t4\t1").is_err());
+        assert!(parse_printk("4 4 1 x").is_err());
+    }
+
+    #[test]
+    fn parse_flags() {
+        assert!(parse_flag("dmesg_restrict", "1").unwrap());
+        assert!(!parse_flag("dmesg_restrict", "0").unwrap());
+        assert!(parse_flag("dmesg_restrict", "yes").is_err());
+        assert_eq!(parse_number("printk_ratelimit_burst", "10").unwrap(), 10);
+        assert!(parse_number("printk_ratelimit_burst", "-1").is_err());
+    }
+
+    #[test]
+    fn parse_console() {
+        assert_eq!(parse_console_loglevel("4\t4\t1\t7").unwrap(), 4);
+        assert_eq!(parse_console_loglevel("15 4 1 7").unwrap(), 15);
+        assert!(parse_console_loglevel("").is_err());
+    }
+
+    #[test]
+    fn set_console_above_syslog_range() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-printk", std::process::id()));
+        fs::write(&path, "8\t4\t1\t7\n").unwrap();
+        set_console_loglevel_at(&path, 10).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "10 4 1 7\n");
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,251 @@
+//! Kernel ring buffer access through `syslog(2)`, also known as `klogctl()`
+
+use std::convert::TryFrom;
+use std::io;
+use std::time::Duration;
+
+use crate::{sysctl, KmsgRecord};
+
+const SYSLOG_ACTION_READ_ALL: libc::c_int = 3;
+const SYSLOG_ACTION_CLEAR: libc::c_int = 5;
+const SYSLOG_ACTION_CONSOLE_OFF: libc::c_int = 6;
+const SYSLOG_ACTION_CONSOLE_ON: libc::c_int = 7;
+const SYSLOG_ACTION_CONSOLE_LEVEL: libc::c_int = 8;
+const SYSLOG_ACTION_SIZE_UNREAD: libc::c_int = 9;
+const SYSLOG_ACTION_SIZE_BUFFER: libc::c_int = 10;
+
+/// Bounds of buffer size for [`read_ring_buffer()`], grown from the ring buffer size as needed
+const MIN_SNAPSHOT_SIZE: usize = 4096;
+const MAX_SNAPSHOT_SIZE: usize = 1 << 30;
+
+/// Read all records currently in the kernel ring buffer at once
+///
+/// Unlike [`KmsgReader`](crate::KmsgReader), this doesn't need read access to `/dev/kmsg`,
+/// only `CAP_SYSLOG` (or `kernel.dmesg_restrict` sysctl off). Records come without
+/// sequence numbers and dictionaries, so their `sequence` is `0` and `dictionary` is empty,
+/// and their `timestamp` is zero if kernel is built without `CONFIG_PRINTK_TIME`
+/// or `printk.time` is off.
+pub fn read_ring_buffer() -> io::Result<Vec<KmsgRecord>> {
+    let mut buf = vec![0; ring_buffer_size()?.max(MIN_SNAPSHOT_SIZE)];
+    loop {
+        let len = klogctl(SYSLOG_ACTION_READ_ALL, &mut buf)?;
+        // Kernel drops the oldest records not fitting the buffer, and with `<pri>[time]` prefixes
+        // text may be larger than the ring buffer itself
+        if len < buf.len() || buf.len() >= MAX_SNAPSHOT_SIZE {
+            return Ok(parse_ring_buffer(&buf[..len]));
+        }
+        buf.resize(buf.len() * 2, 0);
+    }
+}
+
+/// Clear the kernel ring buffer
+///
+/// Cleared records are no longer returned by [`read_ring_buffer()`] and readers seeking to
+/// [`KmsgPosition::AfterClear`](crate::KmsgPosition::AfterClear), but are still there
+/// for `/dev/kmsg` readers from the oldest record. Requires `CAP_SYSLOG`.
+pub fn clear_ring_buffer() -> io::Result<()> {
+    klogctl_arg(SYSLOG_ACTION_CLEAR, 0).map(drop)
+}
+
+/// Set console loglevel, so that kernel prints records with priority below it to the console
+///
+/// Level must be from `1` (only `KERN_EMERG`) to `8` (everything). Requires `CAP_SYSLOG`.
+/// Current level can be read with [`sysctl::console_loglevel()`](crate::sysctl::console_loglevel).
+pub fn set_console_level(level: u8) -> io::Result<()> {
+    if !(1..=8).contains(&level) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+            "invalid console loglevel {}: expected 1 to 8", level)));
+    }
+    klogctl_arg(SYSLOG_ACTION_CONSOLE_LEVEL, level.into()).map(drop)
+}
+
+/// Guard restoring console loglevel on drop, see [`console_level_guard()`]
+#[derive(Debug)]
+#[must_use = "console loglevel is restored as soon as the guard is dropped"]
+pub struct ConsoleLevelGuard {
+    previous: u8,
+}
+
+impl ConsoleLevelGuard {
+    /// Get console loglevel to be restored
+    pub fn previous(&self) -> u8 {
+        self.previous
+    }
+}
+
+/// Set console loglevel until returned guard is dropped, see [`set_console_level()`]
+///
+/// ```rust,no_run
+/// {
+///     // Let KERN_DEBUG records reach the serial console while probing hardware
+///     let _guard = kernlog::console_level_guard(8).unwrap();
+///     # let probe_devices = || ();
+///     probe_devices();
+/// }
+/// ```
+///
+/// Levels outside of 1 to 8, which `syslog(2)` doesn't accept, are restored through `kernel.printk` sysctl.
+/// Failure to restore the level on drop is ignored.
+pub fn console_level_guard(level: u8) -> io::Result<ConsoleLevelGuard> {
+    let previous = sysctl::console_loglevel()?;
+    set_console_level(level)?;
+    Ok(ConsoleLevelGuard { previous })
+}
+
+impl Drop for ConsoleLevelGuard {
+    fn drop(&mut self) {
+        let _ = if !(1..=8).contains(&self.previous) {
+            sysctl::set_console_loglevel(self.previous)
+        } else {
+            set_console_level(self.previous)
+        };
+    }
+}
+
+/// Stop printing records to the console, except for panics, saving the current console loglevel
+pub fn console_off() -> io::Result<()> {
+    klogctl_arg(SYSLOG_ACTION_CONSOLE_OFF, 0).map(drop)
+}
+
+/// Resume printing records to the console at console loglevel saved by [`console_off()`]
+pub fn console_on() -> io::Result<()> {
+    klogctl_arg(SYSLOG_ACTION_CONSOLE_ON, 0).map(drop)
+}
+
+/// Get number of bytes of kernel log not yet consumed through `syslog(2)` or `/proc/kmsg`
+///
+/// This is what a syslog daemon reading `/proc/kmsg` still has to read,
+/// compare it to [`ring_buffer_size()`] to see how close it is to losing records.
+/// Reads from `/dev/kmsg` don't consume records. Requires `CAP_SYSLOG`.
+pub fn unread_ring_buffer_size() -> io::Result<usize> {
+    klogctl_arg(SYSLOG_ACTION_SIZE_UNREAD, 0)
+}
+
+/// Get size of the kernel ring buffer in bytes, `log_buf_len` kernel parameter
+///
+/// Useful for sizing own capture buffers, note text of all records read through `syslog(2)`
+/// with their `<pri>[time]` prefixes may be larger. Doesn't require `CAP_SYSLOG`,
+/// unless `kernel.dmesg_restrict` sysctl is on.
+pub fn ring_buffer_size() -> io::Result<usize> {
+    klogctl_arg(SYSLOG_ACTION_SIZE_BUFFER, 0)
+}
+
+fn parse_ring_buffer(text: &[u8]) -> Vec<KmsgRecord> {
+    text.split(|&byte| byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(parse_line)
+        .collect()
+}
+
+/// Parse ring buffer line in `<pri>[secs.usecs] message` form
+fn parse_line(line: &[u8]) -> KmsgRecord {
+    let line = String::from_utf8_lossy(line);
+    let (prival, rest) = line.strip_prefix('<')
+        .and_then(|rest| rest.split_once('>'))
+        .and_then(|(prival, rest)| Some((prival.parse::<u32>().ok()?, rest)))
+        .unwrap_or((libc::LOG_INFO as u32, &line));
+    let (timestamp, rest) = rest.strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+        .and_then(|(timestamp, rest)| Some((parse_timestamp(timestamp.trim())?, rest)))
+        .unwrap_or((Duration::ZERO, rest));
+    // Caller id like `[    T1]` with `CONFIG_PRINTK_CALLER`
+    let rest = rest.strip_prefix('[')
+        .and_then(|rest| rest.split_once(']'))
+        .filter(|(caller, _)| caller.trim_start().starts_with(['T', 'C']))
+        .map_or(rest, |(_, rest)| rest);
+    KmsgRecord {
+        priority: (prival & 7) as u8,
+        facility: (prival >> 3) as u8,
+        sequence: 0,
+        timestamp,
+        flags: '-',
+        message: rest.strip_prefix(' ').unwrap_or(rest).to_owned(),
+        dictionary: Vec::new(),
+    }
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<Duration> {
+    let (secs, micros) = timestamp.split_once('.')?;
+    Some(Duration::from_secs(secs.parse().ok()?) + Duration::from_micros(micros.parse().ok()?))
+}
+
+/// Call `klogctl()` with a buffer, returning number of bytes it reports
+fn klogctl(action: libc::c_int, buf: &mut [u8]) -> io::Result<usize> {
+    let len = libc::c_int::try_from(buf.len()).unwrap_or(libc::c_int::MAX);
+    match unsafe { libc::klogctl(action, buf.as_mut_ptr().cast(), len) } {
+        -1 => Err(io::Error::last_os_error()),
+        len => Ok(len as usize),
+    }
+}
+
+/// Call `klogctl()` with a numeric argument instead of a buffer
+fn klogctl_arg(action: libc::c_int, arg: libc::c_int) -> io::Result<usize> {
+    match unsafe { libc::klogctl(action, std::ptr::null_mut(), arg) } {
+        -1 => Err(io::Error::last_os_error()),
+        len => Ok(len as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{
+        console_level_guard, parse_ring_buffer, read_ring_buffer, ring_buffer_size, set_console_level,
+        unread_ring_buffer_size,
+    };
+    use crate::sysctl;
+
+    #[test]
+    fn parse_lines() {
+        let records = parse_ring_buffer(
+            b"<6>[    0.000000] Linux version 6.1.0\n<12>[   12.345678][  T321] init: started\n<4>no timestamp\n",
+        );
+        let summary: Vec<_> = records.iter()
+            .map(|record| (record.priority, record.facility, record.timestamp, record.message.as_str()))
+            .collect();
+        assert_eq!(summary, [
+            (6, 0, Duration::ZERO, "Linux version 6.1.0"),
+            (4, 1, Duration::from_micros(12345678), "init: started"),
+            (4, 0, Duration::ZERO, "no timestamp"),
+        ]);
+    }
+
+    #[test]
+    fn console_level() {
+        assert_eq!(set_console_level(0).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(set_console_level(9).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        // Setting the current level back is harmless
+        if let Ok(level) = sysctl::console_loglevel() {
+            if (1..=8).contains(&level) {
+                let _ = set_console_level(level);
+                assert_eq!(sysctl::console_loglevel().unwrap(), level);
+                if let Ok(guard) = console_level_guard(level) {
+                    assert_eq!(guard.previous(), level);
+                }
+                assert_eq!(sysctl::console_loglevel().unwrap(), level);
+            }
+        }
+    }
+
+    #[test]
+    fn query_sizes() {
+        let size = match ring_buffer_size() {
+            Ok(size) => size,
+            // No permission to read kernel log
+            Err(_) => return,
+        };
+        assert!(size > 0);
+        assert!(unread_ring_buffer_size().is_ok());
+    }
+
+    #[test]
+    fn read_kernel_ring_buffer() {
+        let records = match read_ring_buffer() {
+            Ok(records) => records,
+            // No permission to read kernel log
+            Err(_) => return,
+        };
+        assert!(records.iter().all(|record| record.priority <= 7));
+    }
+}
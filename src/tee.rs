@@ -0,0 +1,110 @@
+//! Logging to kernel log and another logger at once
+
+use log::{Log, Metadata, Record};
+
+use crate::KernelLog;
+
+/// Logger forwarding records to both kernel logger and another logger, see [`tee()`]
+pub struct Tee<L> {
+    klog: KernelLog,
+    other: L,
+}
+
+/// Combine kernel logger with another logger, so every record goes to both
+///
+/// Each logger filters records on its own. Global `log` max level is not set by the combined
+/// logger, it should be set to the most verbose level of both.
+///
+/// ```rust,no_run
+/// # struct Stderr;
+/// # impl log::Log for Stderr {
+/// #     fn enabled(&self, _: &log::Metadata) -> bool { true }
+/// #     fn log(&self, record: &log::Record) { eprintln!("{}", record.args()) }
+/// #     fn flush(&self) {}
+/// # }
+/// let klog = kernlog::KernelLog::new().unwrap();
+/// let stderr = Stderr;
+/// log::set_boxed_logger(Box::new(kernlog::tee(klog, stderr))).unwrap();
+/// log::set_max_level(log::LevelFilter::Trace);
+/// ```
+pub fn tee<L: Log>(klog: KernelLog, other: L) -> Tee<L> {
+    Tee { klog, other }
+}
+
+impl<L> Tee<L> {
+    /// Get kernel logger
+    pub fn kernel(&self) -> &KernelLog {
+        &self.klog
+    }
+
+    /// Get the other logger
+    pub fn other(&self) -> &L {
+        &self.other
+    }
+
+    /// Split into kernel logger and the other logger
+    pub fn into_inner(self) -> (KernelLog, L) {
+        (self.klog, self.other)
+    }
+}
+
+impl<L: Log> Log for Tee<L> {
+    fn enabled(&self, meta: &Metadata) -> bool {
+        self.klog.enabled(meta) || self.other.enabled(meta)
+    }
+
+    fn log(&self, record: &Record) {
+        self.klog.log(record);
+        if self.other.enabled(record.metadata()) {
+            self.other.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.klog.flush();
+        self.other.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Mutex;
+
+    use log::{Level, LevelFilter, Log, Metadata, Record};
+
+    use super::tee;
+    use crate::KernelLog;
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<String>>);
+
+    impl Log for Collect {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= Level::Debug
+        }
+
+        fn log(&self, record: &Record) {
+            self.0.lock().unwrap().push(format!("{} {}", record.level(), record.args()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn log_to_both() {
+        let path = std::env::temp_dir().join(format!("kernlog-{}-tee", std::process::id()));
+        fs::write(&path, "").unwrap();
+        let klog = KernelLog::builder().device(&path).pid(false).level(LevelFilter::Info).build().unwrap();
+        let logger = tee(klog, Collect::default());
+        for &level in &[Level::Warn, Level::Debug, Level::Trace] {
+            let meta = Metadata::builder().level(level).target("test").build();
+            assert_eq!(logger.enabled(&meta), level <= Level::Debug);
+            logger.log(&Record::builder().metadata(meta).args(format_args!("{}", level)).build());
+        }
+        logger.flush();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "<4>test: WARN\n");
+        assert_eq!(*logger.other().0.lock().unwrap(), ["WARN WARN", "DEBUG DEBUG"]);
+        fs::remove_file(path).unwrap();
+    }
+}
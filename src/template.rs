@@ -0,0 +1,180 @@
+//! Template string record format
+
+use std::io::{self, Write};
+
+use log::Record;
+
+use crate::format::{getpid, gettid, program_name, KmsgFormat};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Piece {
+    Literal(String),
+    Level,
+    Tag,
+    Pid,
+    Tid,
+    Thread,
+    Target,
+    Module,
+    File,
+    Line,
+    Msg,
+}
+
+/// Record format defined by a template string
+///
+/// Template is a text with placeholders in curly braces, e.g. `"{tag}[{pid}] {target}: {msg}"`.
+/// Supported placeholders are:
+///
+/// - `{level}`: log level name (`ERROR`, `WARN`, etc.),
+/// - `{tag}`: tag, program name by default,
+/// - `{pid}`: process id,
+/// - `{tid}`: thread id,
+/// - `{thread}`: thread name, empty for unnamed threads,
+/// - `{target}`: record target,
+/// - `{module}`: module path,
+/// - `{file}` and `{line}`: source location,
+/// - `{msg}`: message itself.
+///
+/// Literal braces are written as `{{` and `}}`.
+#[derive(Debug, Clone)]
+pub struct TemplateFormat {
+    pieces: Vec<Piece>,
+    tag: String,
+}
+
+impl TemplateFormat {
+    /// Parse template string
+    ///
+    /// Fails with `InvalidInput` error on unknown placeholders and unbalanced braces.
+    pub fn new(template: &str) -> io::Result<TemplateFormat> {
+        Ok(TemplateFormat {
+            pieces: parse(template)?,
+            tag: program_name(),
+        })
+    }
+
+    /// Set value of `{tag}` placeholder
+    pub fn tag(mut self, tag: impl Into<String>) -> TemplateFormat {
+        self.tag = tag.into();
+        self
+    }
+}
+
+impl KmsgFormat for TemplateFormat {
+    fn format(&self, buf: &mut dyn Write, record: &Record) -> io::Result<()> {
+        for piece in &self.pieces {
+            match *piece {
+                Piece::Literal(ref text) => buf.write_all(text.as_bytes())?,
+                Piece::Level => write!(buf, "{}", record.level())?,
+                Piece::Tag => buf.write_all(self.tag.as_bytes())?,
+                Piece::Pid => write!(buf, "{}", getpid())?,
+                Piece::Tid => write!(buf, "{}", gettid())?,
+                Piece::Thread => if let Some(name) = std::thread::current().name() {
+                    buf.write_all(name.as_bytes())?
+                },
+                Piece::Target => buf.write_all(record.target().as_bytes())?,
+                Piece::Module => buf.write_all(record.module_path().unwrap_or_default().as_bytes())?,
+                Piece::File => buf.write_all(record.file().unwrap_or_default().as_bytes())?,
+                Piece::Line => if let Some(line) = record.line() {
+                    write!(buf, "{}", line)?
+                },
+                Piece::Msg => write!(buf, "{}", record.args())?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse(template: &str) -> io::Result<Vec<Piece>> {
+    let mut pieces = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(invalid(format!("unclosed placeholder {{{}", name))),
+                    }
+                }
+                let piece = match name.as_str() {
+                    "level" => Piece::Level,
+                    "tag" => Piece::Tag,
+                    "pid" => Piece::Pid,
+                    "tid" => Piece::Tid,
+                    "thread" => Piece::Thread,
+                    "target" => Piece::Target,
+                    "module" => Piece::Module,
+                    "file" => Piece::File,
+                    "line" => Piece::Line,
+                    "msg" => Piece::Msg,
+                    _ => return Err(invalid(format!("unknown placeholder {{{}}}", name))),
+                };
+                if !literal.is_empty() {
+                    pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                }
+                pieces.push(piece);
+            }
+            '}' => return Err(invalid("unmatched }".to_owned())),
+            c => literal.push(c),
+        }
+    }
+
+    if !literal.is_empty() {
+        pieces.push(Piece::Literal(literal));
+    }
+    Ok(pieces)
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("invalid format template: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, Record};
+    use super::{KmsgFormat, Piece, TemplateFormat, parse};
+
+    #[test]
+    fn parse_template() {
+        assert_eq!(parse("{{{level}}} {msg}!").unwrap(), vec![
+            Piece::Literal("{".into()),
+            Piece::Level,
+            Piece::Literal("} ".into()),
+            Piece::Msg,
+            Piece::Literal("!".into()),
+        ]);
+        assert!(parse("{msg").is_err());
+        assert!(parse("{message}").is_err());
+        assert!(parse("msg}").is_err());
+    }
+
+    #[test]
+    fn format_template() {
+        let format = TemplateFormat::new("{level} {tag} {target}: {msg} ({file}:{line})").unwrap().tag("init");
+        let record = Record::builder()
+            .level(Level::Warn)
+            .target("mount")
+            .file(Some("main.rs"))
+            .line(Some(7))
+            .args(format_args!("no root"))
+            .build();
+
+        let mut buf = Vec::new();
+        format.format(&mut buf, &record).unwrap();
+        assert_eq!(buf, b"WARN init mount: no root (main.rs:7)");
+    }
+}
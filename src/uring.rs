@@ -0,0 +1,117 @@
+//! Batched record writes through io_uring
+
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+
+use io_uring::{opcode, squeue, types, IoUring};
+
+use crate::backend::message_line;
+
+/// Number of submission queue entries, records are submitted in chunks of this size
+const ENTRIES: u32 = 64;
+
+/// Offset telling kernel to use and advance current file position, like `write(2)`
+const CURRENT_POSITION: u64 = u64::MAX;
+
+/// Ring submitting all records of a batch with a single `io_uring_enter(2)`
+pub(crate) struct Uring {
+    ring: IoUring,
+}
+
+impl Uring {
+    pub(crate) fn new() -> io::Result<Uring> {
+        Ok(Uring { ring: IoUring::new(ENTRIES)? })
+    }
+
+    /// Write message lines of records in order, reporting result of each written record
+    /// to `settle` with its index
+    ///
+    /// Submissions are linked, so kernel doesn't reorder them, and writes after a failed one
+    /// are cancelled and reported with `ECANCELED`. Fails if the ring itself fails, so it shouldn't
+    /// be used anymore, leaving records not reported to `settle` unwritten, though some of them
+    /// may be written by the kernel anyway.
+    pub(crate) fn write(&mut self, file: &File, records: &[Vec<u8>], mut settle: impl FnMut(usize, io::Result<()>)) -> io::Result<()> {
+        for (index, chunk) in records.chunks(ENTRIES as usize).enumerate() {
+            let start = index * ENTRIES as usize;
+            self.submit(file, chunk, |index, result| settle(start + index, result))?;
+        }
+        Ok(())
+    }
+
+    fn submit(&mut self, file: &File, records: &[Vec<u8>], mut settle: impl FnMut(usize, io::Result<()>)) -> io::Result<()> {
+        let fd = types::Fd(file.as_raw_fd());
+        for (index, record) in records.iter().enumerate() {
+            let record = message_line(record);
+            let mut entry = opcode::Write::new(fd, record.as_ptr(), record.len() as u32)
+                .offset(CURRENT_POSITION)
+                .build()
+                .user_data(index as u64);
+            if index + 1 < records.len() {
+                entry = entry.flags(squeue::Flags::IO_LINK);
+            }
+            // Records outlive submissions, as all completions are waited for below
+            unsafe { self.ring.submission().push(&entry) }
+                .map_err(|_| io::Error::other("io_uring submission queue is full"))?;
+        }
+
+        let mut pending = records.len();
+        while pending > 0 {
+            match self.ring.submit_and_wait(pending) {
+                Ok(_) => (),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+            for entry in self.ring.completion() {
+                pending -= 1;
+                let index = entry.user_data() as usize;
+                let record = message_line(&records[index]);
+                let result = match usize::try_from(entry.result()) {
+                    Ok(written) if written >= record.len() => Ok(()),
+                    Ok(written) => write_rest(file, record, written),
+                    Err(_) => Err(io::Error::from_raw_os_error(-entry.result())),
+                };
+                settle(index, result);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn write_rest(mut file: &File, record: &[u8], written: usize) -> io::Result<()> {
+    file.write_all(&record[written..])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File};
+
+    use super::{Uring, ENTRIES};
+
+    #[test]
+    fn write_batch_in_order() {
+        let mut uring = match Uring::new() {
+            Ok(uring) => uring,
+            // io_uring may be disabled by seccomp or `kernel.io_uring_disabled` sysctl
+            Err(_) => return,
+        };
+        let path = std::env::temp_dir().join(format!("kernlog-{}-uring", std::process::id()));
+        let file = File::create(&path).unwrap();
+        let records: Vec<Vec<u8>> = (0..ENTRIES + 6).map(|index| format!("<6>{}\n", index).into_bytes()).collect();
+        let mut settled = vec![false; records.len()];
+        uring.write(&file, &records, |index, result| {
+            result.unwrap();
+            settled[index] = true;
+        }).unwrap();
+        assert!(settled.iter().all(|&settled| settled));
+        assert_eq!(fs::read(&path).unwrap(), records.concat());
+
+        let readonly = File::open(&path).unwrap();
+        let mut errors = Vec::new();
+        uring.write(&readonly, &records[..3], |index, result| errors.push((index, result.unwrap_err().raw_os_error()))).unwrap();
+        errors.sort();
+        assert_eq!(errors, [(0, Some(libc::EBADF)), (1, Some(libc::ECANCELED)), (2, Some(libc::ECANCELED))]);
+        fs::remove_file(path).unwrap();
+    }
+}
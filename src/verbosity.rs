@@ -0,0 +1,97 @@
+//! Scoped per-thread verbosity override
+
+use std::cell::Cell;
+use std::marker::PhantomData;
+
+use log::LevelFilter;
+
+use crate::sync::{Mutex, MutexGuard};
+
+thread_local! {
+    static OVERRIDE: Cell<Option<LevelFilter>> = const { Cell::new(None) };
+}
+
+/// Global `log` max level bookkeeping while guards are alive
+struct Raised {
+    /// Number of alive guards in all threads
+    guards: usize,
+    /// Max level to restore when the last guard is dropped
+    base: LevelFilter,
+    /// The most verbose level of guards alive since the first one was created
+    level: LevelFilter,
+}
+
+static RAISED: Mutex<Raised> = Mutex::new(Raised { guards: 0, base: LevelFilter::Off, level: LevelFilter::Off });
+
+fn raised() -> MutexGuard<'static, Raised> {
+    RAISED.lock()
+}
+
+/// Guard raising level filter of kernel logger for the current thread, see [`verbosity_guard()`]
+///
+/// Restores previous level filter on drop. It's bound to the thread it was created in.
+#[must_use = "level filter is restored as soon as the guard is dropped"]
+pub struct VerbosityGuard {
+    previous: Option<LevelFilter>,
+    _thread: PhantomData<*const ()>,
+}
+
+/// Raise level filter of kernel logger for the current thread until returned guard is dropped
+///
+/// ```rust,no_run
+/// use log::{trace, LevelFilter};
+///
+/// kernlog::init().unwrap();
+/// {
+///     let _guard = kernlog::verbosity_guard(LevelFilter::Trace);
+///     trace!("mounting root filesystem");
+/// }
+/// trace!("this is filtered out again");
+/// ```
+///
+/// Records from other threads are filtered as before, but as global `log` max level has to be
+/// raised while any guard is alive, they reach the logger to be filtered there.
+/// Guards can be nested, level filter is never lowered by an inner guard.
+/// Records from denied targets are still dropped.
+pub fn verbosity_guard(level: LevelFilter) -> VerbosityGuard {
+    let previous = OVERRIDE.with(|current| current.replace(Some(current.get().map_or(level, |prev| prev.max(level)))));
+
+    let mut raised = raised();
+    if raised.guards == 0 {
+        raised.base = log::max_level();
+        raised.level = LevelFilter::Off;
+    }
+    raised.guards += 1;
+    raised.level = raised.level.max(level);
+    log::set_max_level(raised.base.max(raised.level));
+
+    VerbosityGuard { previous, _thread: PhantomData }
+}
+
+impl Drop for VerbosityGuard {
+    fn drop(&mut self) {
+        OVERRIDE.with(|current| current.set(self.previous));
+
+        let mut raised = raised();
+        raised.guards -= 1;
+        if raised.guards == 0 {
+            log::set_max_level(raised.base);
+        }
+    }
+}
+
+/// Get level filter raised for the current thread, if any
+pub(crate) fn thread_level() -> Option<LevelFilter> {
+    OVERRIDE.with(Cell::get)
+}
+
+/// Set global `log` max level, keeping it raised while there are verbosity guards alive
+pub(crate) fn set_max_level(level: LevelFilter) {
+    let mut raised = raised();
+    if raised.guards == 0 {
+        log::set_max_level(level);
+    } else {
+        raised.base = level;
+        log::set_max_level(level.max(raised.level));
+    }
+}
@@ -0,0 +1,11 @@
+//! `init_or_stderr()` installs a global logger, so it runs in its own test binary
+
+#[test]
+fn stderr_with_invalid_env() {
+    std::env::set_var("KERNLOG_DEVICE", std::env::temp_dir().join("kernlog-missing-device"));
+    std::env::set_var("KERNLOG_LEVEL", "loud");
+    std::env::set_var("KERNLOG_FILTER", "net=loud");
+    let handle = kernlog::init_or_stderr().unwrap();
+    assert_eq!(handle.device(), None);
+    log::info!("to stderr");
+}